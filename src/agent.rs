@@ -1,75 +1,322 @@
-use crate::bus::{InboundMessage, MessageBus, OutboundMessage};
+use crate::approval::{ApprovalBroker, Decision, Prompter};
+use crate::bus::{InboundMessage, MessageBus, OutboundKind, OutboundMessage};
 use crate::channels::ChannelManager;
-use crate::config::Config;
+use crate::config::{AgentProfile, Config, GenerationSettings};
 use crate::constants;
 use crate::context_builder::ContextBuilder;
-use crate::providers::{Message, ProcessOptions, Provider};
-use crate::session::SessionManager;
+use crate::context_window;
+use crate::media;
+use crate::providers::{Message, ProcessOptions, Provider, StreamSink};
+use crate::session::{ModelSelection, SessionManager};
 use crate::state::Manager as StateManager;
-use crate::tools::{SubagentManager, ToolRegistry, ToolResult};
+use crate::tools::{SubagentManager, ToolPermissions, ToolRegistry, ToolResult};
+use crate::trace::{TraceEvent, TraceLog, TurnTrace};
+use crate::usage::{UsageContext, UsageLedger};
 use parking_lot::{Mutex, RwLock};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, watch};
+const STREAM_UPDATE_INTERVAL: Duration = Duration::from_millis(1000);
+/// History length that triggers folding older turns into the summary.
+const SUMMARY_MESSAGE_THRESHOLD: usize = 20;
+/// Most recent messages that are always kept verbatim.
+const SUMMARY_KEEP_RECENT: usize = 10;
+/// Per-message cap when rendering history for the summarizer.
+const SUMMARY_MESSAGE_MAX_CHARS: usize = 2000;
+/// How long a provider's `/list models` answer is reused.
+const MODEL_LIST_TTL: Duration = Duration::from_secs(600);
+/// Models shown by `/list models` before asking for a filter.
+const MODEL_LIST_MAX_SHOWN: usize = 50;
+/// Reply and history marker for a turn stopped with `/stop`.
+const CANCELLED_MARKER: &str = "⏹ Cancelled by user.";
+const WRAP_UP_PROMPT: &str = "You have reached the tool call limit for this turn, so no more tools can be called now. \
+Briefly tell the user what you have done so far, what is still left, and what you would do next.";
+/// User message of a turn started with `/continue`.
+const CONTINUE_PROMPT: &str =
+    "Continue the previous task from where you stopped. You have a fresh tool call budget.";
+const SUMMARY_PROMPT: &str = "You maintain the running summary of a conversation between a user and an AI assistant. \
+Merge the existing summary and the new messages into one concise summary. Keep facts about the user, decisions, \
+preferences, open tasks and tool results that may matter later; drop small talk. Reply with the summary only.";
 pub struct AgentLoop {
     bus: Arc<MessageBus>,
     provider: Arc<dyn Provider>,
     model: String,
-    context_window: i32,
-    max_iterations: i32,
-    sessions: Arc<Mutex<SessionManager>>,
+    sessions: Arc<SessionManager>,
     state: Arc<Mutex<StateManager>>,
     tools: Arc<Mutex<ToolRegistry>>,
     context_builder: ContextBuilder,
     running: AtomicBool,
     channel_manager: Arc<RwLock<Option<Arc<ChannelManager>>>>,
     tool_output_max_chars: usize,
+    streaming: bool,
+    usage: Arc<UsageLedger>,
+    provider_name: String,
+    /// Sessions with a summarization currently running.
+    summarizing: Arc<Mutex<HashSet<String>>>,
+    max_concurrent_sessions: usize,
+    /// Cancel switches for the turns currently running, by session key.
+    active_turns: Mutex<HashMap<String, watch::Sender<bool>>>,
+    /// Needed to build the providers sessions switch to.
+    config: Config,
+    switched_providers: Mutex<HashMap<String, Arc<dyn Provider>>>,
+    model_lists: Mutex<HashMap<String, (Instant, Vec<String>)>>,
+    /// Named agents from `agents.profiles`; empty on the profiles themselves.
+    profiles: HashMap<String, Arc<AgentLoop>>,
+    approvals: Arc<ApprovalBroker>,
+    traces: TraceLog,
+}
+/// How a turn's iterations ended.
+struct TurnOutcome {
+    content: String,
+    iterations: i32,
+    sent_message_tool: bool,
+    /// Stopped at `max_tool_iterations` with the model still calling tools.
+    hit_limit: bool,
+}
+/// Provider and model a session's turn runs on.
+struct ActiveModel {
+    provider: Arc<dyn Provider>,
+    provider_name: String,
+    model: String,
+}
+struct ChannelStream {
+    bus: Arc<MessageBus>,
+    channel: String,
+    chat_id: String,
+    last_sent: Mutex<Option<Instant>>,
+    published: AtomicBool,
+}
+impl ChannelStream {
+    fn update(&self, text: &str) {
+        if text.trim().is_empty() {
+            return;
+        }
+        let mut last_sent = self.last_sent.lock();
+        if last_sent.is_some_and(|t| t.elapsed() < STREAM_UPDATE_INTERVAL) {
+            return;
+        }
+        let partial = OutboundMessage {
+            channel: self.channel.clone(),
+            chat_id: self.chat_id.clone(),
+            content: text.to_string(),
+            kind: OutboundKind::Partial,
+        };
+        if self.bus.try_publish_outbound(partial).is_ok() {
+            *last_sent = Some(Instant::now());
+            self.published.store(true, Ordering::SeqCst);
+        }
+    }
+}
+/// Services every agent profile shares with the default agent.
+#[derive(Clone)]
+struct SharedServices {
+    usage: Arc<UsageLedger>,
+    state: Arc<Mutex<StateManager>>,
+    cron: Arc<Mutex<crate::cron::CronService>>,
+    channel_manager: Arc<RwLock<Option<Arc<ChannelManager>>>>,
+    approvals: Arc<ApprovalBroker>,
 }
 impl AgentLoop {
     pub fn new(config: &Config, msg_bus: &Arc<MessageBus>, provider: Arc<dyn Provider>) -> Self {
         let workspace = config.workspace_path();
-        let sessions = Arc::new(Mutex::new(SessionManager::new(workspace.join("sessions"))));
-        let state = Arc::new(Mutex::new(StateManager::new(workspace.clone())));
-        let tool_registry = ToolRegistry::with_tool_config(
+        let shared = SharedServices {
+            usage: Arc::new(UsageLedger::new(&workspace, &config.usage)),
+            state: Arc::new(Mutex::new(StateManager::new(workspace.clone()))),
+            cron: Arc::new(Mutex::new(crate::cron::CronService::new(
+                &workspace.join("cron").join("jobs.json"),
+                None,
+            ))),
+            channel_manager: Arc::new(RwLock::new(None)),
+            approvals: Arc::new(ApprovalBroker::new(msg_bus.clone())),
+        };
+        let mut agent = Self::with_shared(config, msg_bus, provider.clone(), &shared, None);
+        for (name, profile) in &config.agents.profiles {
+            let Some(profile_config) = config.for_profile(name) else {
+                continue;
+            };
+            let (profile_config, profile_provider) =
+                Self::profile_provider(name, profile, profile_config, config, &provider);
+            let profile_agent = Self::with_shared(
+                &profile_config,
+                msg_bus,
+                profile_provider,
+                &shared,
+                Some((name, profile)),
+            );
+            agent.profiles.insert(name.clone(), Arc::new(profile_agent));
+        }
+        for route in &config.agents.routes {
+            if !agent.profiles.contains_key(&route.agent) {
+                tracing::warn!("route to unknown agent '{}' is ignored", route.agent);
+            }
+        }
+        agent
+    }
+    /// Provider for a profile that picks its own. If it can't be built the
+    /// profile keeps the default provider and model (and its own workspace).
+    fn profile_provider(
+        name: &str,
+        profile: &AgentProfile,
+        mut config: Config,
+        root: &Config,
+        default: &Arc<dyn Provider>,
+    ) -> (Config, Arc<dyn Provider>) {
+        let provider_name = crate::providers::select_provider(&config);
+        if provider_name == crate::providers::select_provider(root) {
+            return (config, default.clone());
+        }
+        let model_default = crate::providers::build_provider(&config, &provider_name)
+            .map(|(_, model)| model.to_string());
+        let built = model_default.and_then(|model_default| {
+            if profile.model.is_none() {
+                config.agents.defaults.model = model_default;
+            }
+            crate::providers::create_provider(&config)
+        });
+        match built {
+            Ok(provider) => (config, provider),
+            Err(err) => {
+                tracing::error!(
+                    "agent '{}': provider '{}' unavailable, using the default one: {}",
+                    name,
+                    provider_name,
+                    err
+                );
+                config.agents.defaults.provider = root.agents.defaults.provider.clone();
+                config.agents.defaults.model = root.agents.defaults.model.clone();
+                (config, default.clone())
+            }
+        }
+    }
+    fn with_shared(
+        config: &Config,
+        msg_bus: &Arc<MessageBus>,
+        provider: Arc<dyn Provider>,
+        shared: &SharedServices,
+        profile: Option<(&str, &AgentProfile)>,
+    ) -> Self {
+        let workspace = config.workspace_path();
+        let sessions = Arc::new(
+            SessionManager::open(&workspace, &config.sessions)
+                .unwrap_or_else(|err| {
+                    tracing::error!("session store unavailable, using JSON files: {}", err);
+                    SessionManager::new(workspace.join("sessions"))
+                })
+                .with_max_messages(config.retention.session_max_messages),
+        );
+        let mut tool_registry = ToolRegistry::with_cron_service(
             workspace.clone(),
             config.agents.defaults.restrict_to_workspace,
             config.tools.web.clone(),
             config.tools.exec.clone(),
+            shared.cron.clone(),
         );
+        if let Some(allowed) = profile.and_then(|(_, p)| p.tools.as_ref()) {
+            tool_registry.retain(allowed);
+        }
         let tool_output_max_chars = config.tools.tool_output_max_chars;
-        let subagent_manager = Arc::new(SubagentManager::new(
-            provider.clone(),
-            config.agents.defaults.model.clone(),
-            msg_bus.clone(),
-            tool_registry.clone(),
-            config.agents.defaults.max_tool_iterations,
-            tool_output_max_chars,
-        ));
+        let model = crate::providers::default_model(config);
+        let provider_name = crate::providers::select_provider(config);
+        let usage = shared.usage.clone();
+        let subagent_manager = Arc::new(
+            SubagentManager::new(
+                provider.clone(),
+                model.clone(),
+                msg_bus.clone(),
+                tool_registry.clone(),
+                config.agents.defaults.max_tool_iterations,
+                tool_output_max_chars,
+            )
+            .with_usage_ledger(usage.clone(), provider_name.clone())
+            .with_generation(config.agents.clone())
+            .with_agent_name(profile.map(|(name, _)| name).unwrap_or_default())
+            .with_permissions(config.tools.permissions.clone()),
+        );
         tool_registry.set_subagent_manager(subagent_manager);
+        tool_registry.set_approval_broker(shared.approvals.clone());
         let tools = Arc::new(Mutex::new(tool_registry));
-        let context_builder = ContextBuilder::new(workspace.clone());
+        let mut context_builder = ContextBuilder::new(workspace.clone());
+        if let Some((_, profile)) = profile
+            && !profile.system_prompt_files.is_empty()
+        {
+            context_builder = context_builder.with_bootstrap_files(&profile.system_prompt_files);
+        }
         Self {
             bus: msg_bus.clone(),
             provider,
-            model: config.agents.defaults.model.clone(),
-            context_window: config.agents.defaults.max_tokens,
-            max_iterations: config.agents.defaults.max_tool_iterations,
+            model,
             sessions,
-            state,
+            state: shared.state.clone(),
             tools,
             context_builder,
             running: AtomicBool::new(false),
-            channel_manager: Arc::new(RwLock::new(None)),
+            channel_manager: shared.channel_manager.clone(),
             tool_output_max_chars,
+            streaming: config.agents.defaults.streaming,
+            usage,
+            provider_name,
+            summarizing: Arc::new(Mutex::new(HashSet::new())),
+            max_concurrent_sessions: config.agents.defaults.max_concurrent_sessions.max(1),
+            active_turns: Mutex::new(HashMap::new()),
+            config: config.clone(),
+            switched_providers: Mutex::new(HashMap::new()),
+            model_lists: Mutex::new(HashMap::new()),
+            profiles: HashMap::new(),
+            approvals: shared.approvals.clone(),
+            traces: TraceLog::new(&workspace, config),
         }
     }
+    /// The agent that handles `msg`: the profile named by the subagent that
+    /// produced it, else the first matching route, else this (default) agent.
+    fn agent_for(&self, msg: &InboundMessage) -> &AgentLoop {
+        if self.profiles.is_empty() {
+            return self;
+        }
+        let name = match msg.metadata.as_ref().and_then(|m| m.get("agent")) {
+            Some(name) => Some(name.as_str()),
+            None => {
+                // System messages carry their origin chat as `channel:chat_id`.
+                let (channel, chat_id) = if msg.channel == "system" {
+                    msg.chat_id.split_once(':').unwrap_or(("cli", "direct"))
+                } else {
+                    (msg.channel.as_str(), msg.chat_id.as_str())
+                };
+                self.config.agents.route(channel, chat_id, &msg.sender_id)
+            }
+        };
+        name.and_then(|name| self.profiles.get(name))
+            .map_or(self, |agent| agent.as_ref())
+    }
     pub fn set_channel_manager(&self, manager: Arc<ChannelManager>) {
         *self.channel_manager.write() = Some(manager);
     }
+    /// Asks `channel`'s user directly (the CLI prompt) instead of sending
+    /// approval requests over the bus.
+    pub fn set_prompter(&self, channel: &str, prompter: Arc<dyn Prompter>) {
+        self.approvals.set_prompter(channel, prompter);
+    }
+    /// Workspaces and session stores of this agent and its profiles, for
+    /// the retention service.
+    pub fn retention_targets(&self) -> Vec<crate::retention::Target> {
+        std::iter::once(self)
+            .chain(self.profiles.values().map(|agent| agent.as_ref()))
+            .map(|agent| crate::retention::Target {
+                workspace: agent.config.workspace_path(),
+                sessions: agent.sessions.clone(),
+            })
+            .collect()
+    }
     pub fn cron_service(&self) -> Arc<Mutex<crate::cron::CronService>> {
         self.tools.lock().cron_service()
     }
-    pub async fn process_direct(&self, content: &str, session_key: &str) -> anyhow::Result<String> {
+    pub async fn process_direct_streaming(
+        &self,
+        content: &str,
+        session_key: &str,
+        on_update: &StreamSink<'_>,
+    ) -> anyhow::Result<String> {
         let msg = InboundMessage {
             channel: "cli".to_string(),
             sender_id: "cli".to_string(),
@@ -79,14 +326,65 @@ impl AgentLoop {
             media: None,
             metadata: None,
         };
-        self.process_message(msg).await
+        let agent = self.agent_for(&msg);
+        if msg.content.starts_with('/') || !agent.streaming {
+            return agent.process_routed(msg).await;
+        }
+        agent
+            .run_agent_loop(Self::user_turn_options(&msg), Some(on_update))
+            .await
+    }
+    fn user_turn_options(msg: &InboundMessage) -> ProcessOptions {
+        ProcessOptions {
+            session_key: msg.session_key.clone(),
+            channel: msg.channel.clone(),
+            chat_id: msg.chat_id.clone(),
+            user_message: msg.content.clone(),
+            default_response: "I've completed processing but have no response to give.".to_string(),
+            enable_summary: true,
+            no_history: false,
+            sender_id: msg.sender_id.clone(),
+            background: constants::is_background_sender(&msg.sender_id),
+            media: msg.media.clone().unwrap_or_default(),
+        }
+    }
+    fn channel_stream(&self, channel: &str, chat_id: &str) -> Option<ChannelStream> {
+        if !self.streaming || constants::is_internal_channel(channel) {
+            return None;
+        }
+        let supported = self
+            .channel_manager
+            .read()
+            .as_ref()
+            .is_some_and(|cm| cm.supports_streaming(channel));
+        if !supported {
+            return None;
+        }
+        Some(ChannelStream {
+            bus: self.bus.clone(),
+            channel: channel.to_string(),
+            chat_id: chat_id.to_string(),
+            last_sent: Mutex::new(None),
+            published: AtomicBool::new(false),
+        })
     }
     pub async fn process_message(&self, msg: InboundMessage) -> anyhow::Result<String> {
+        self.agent_for(&msg).process_routed(msg).await
+    }
+    async fn process_routed(&self, msg: InboundMessage) -> anyhow::Result<String> {
         tracing::info!("Processing message from {}:{}", msg.channel, msg.sender_id);
         if msg.channel == "system" {
             return self.process_system_message(msg).await;
         }
-        if msg.content.starts_with('/') {
+        // `/continue` and `/retry` run a turn instead of replying directly.
+        let replay = match msg.content.trim() {
+            "/continue" if self.sessions.is_interrupted(&msg.session_key) => {
+                Some(CONTINUE_PROMPT.to_string())
+            }
+            "/retry" => self.sessions.pop_turn(&msg.session_key).map(|m| m.content),
+            _ => None,
+        };
+        if msg.content.starts_with('/') && replay.is_none() {
             let response = self.handle_command(&msg).await?;
             if !response.is_empty()
                 && let Err(err) = self
@@ -95,6 +393,7 @@ impl AgentLoop {
                         channel: msg.channel.clone(),
                         chat_id: msg.chat_id.clone(),
                         content: response.clone(),
+                        kind: OutboundKind::Final,
                     })
                     .await
             {
@@ -102,23 +401,27 @@ impl AgentLoop {
             }
             return Ok(response);
         }
-        let opts = ProcessOptions {
-            session_key: msg.session_key.clone(),
-            channel: msg.channel.clone(),
-            chat_id: msg.chat_id.clone(),
-            user_message: msg.content.clone(),
-            default_response: "I've completed processing but have no response to give.".to_string(),
-            enable_summary: true,
-            no_history: false,
+        let mut opts = Self::user_turn_options(&msg);
+        if let Some(user_message) = replay {
+            opts.user_message = user_message;
+        }
+        let stream = self.channel_stream(&msg.channel, &msg.chat_id);
+        let response = match stream.as_ref() {
+            Some(stream) => {
+                let sink = |text: &str| stream.update(text);
+                self.run_agent_loop(opts, Some(&sink)).await?
+            }
+            None => self.run_agent_loop(opts, None).await?,
         };
-        let response = self.run_agent_loop(opts).await?;
-        if !response.is_empty()
+        let streamed = stream.is_some_and(|s| s.published.load(Ordering::SeqCst));
+        if (!response.is_empty() || streamed)
             && let Err(err) = self
                 .bus
                 .publish_outbound(OutboundMessage {
                     channel: msg.channel,
                     chat_id: msg.chat_id,
                     content: response.clone(),
+                    kind: OutboundKind::Final,
                 })
                 .await
         {
@@ -144,16 +447,29 @@ impl AgentLoop {
         } else {
             msg.content.clone()
         };
+        // Subagent announcements also arrive here; only the heartbeat is a
+        // background turn and none of them has a human sender to bill.
+        let background = constants::is_background_sender(&msg.sender_id);
         let response = self
-            .run_agent_loop(ProcessOptions {
-                session_key: format!("system:{}:{}", origin_channel, origin_chat),
-                channel: origin_channel.clone(),
-                chat_id: origin_chat.clone(),
-                user_message: prompt,
-                default_response: "HEARTBEAT_OK".to_string(),
-                enable_summary: false,
-                no_history: true,
-            })
+            .run_agent_loop(
+                ProcessOptions {
+                    session_key: format!("system:{}:{}", origin_channel, origin_chat),
+                    channel: origin_channel.clone(),
+                    chat_id: origin_chat.clone(),
+                    user_message: prompt,
+                    default_response: "HEARTBEAT_OK".to_string(),
+                    enable_summary: false,
+                    no_history: true,
+                    sender_id: if background {
+                        msg.sender_id.clone()
+                    } else {
+                        String::new()
+                    },
+                    background,
+                    media: Vec::new(),
+                },
+                None,
+            )
             .await?;
         if !response.is_empty() && response != "HEARTBEAT_OK" {
             let _ = self
@@ -162,12 +478,17 @@ impl AgentLoop {
                     channel: origin_channel,
                     chat_id: origin_chat,
                     content: response.clone(),
+                    kind: OutboundKind::Final,
                 })
                 .await;
         }
         Ok(response)
     }
-    async fn run_agent_loop(&self, opts: ProcessOptions) -> anyhow::Result<String> {
+    async fn run_agent_loop(
+        &self,
+        opts: ProcessOptions,
+        stream: Option<&StreamSink<'_>>,
+    ) -> anyhow::Result<String> {
         if !opts.channel.is_empty()
             && !opts.chat_id.is_empty()
             && !constants::is_internal_channel(&opts.channel)
@@ -177,92 +498,274 @@ impl AgentLoop {
         }
         self.update_tool_contexts(&opts.channel, &opts.chat_id);
         let history = if !opts.no_history {
-            self.sessions.lock().get_history(&opts.session_key)
+            self.sessions.get_history(&opts.session_key)
         } else {
             vec![]
         };
         let summary = if !opts.no_history {
-            self.sessions.lock().get_summary(&opts.session_key)
+            self.sessions.get_summary(&opts.session_key)
         } else {
             String::new()
         };
-        let mut messages = self.build_messages(
-            history,
-            summary,
-            &opts.user_message,
-            None,
-            &opts.channel,
-        );
+        let mut messages = self.build_messages(history, summary, &opts);
         if !opts.no_history {
+            self.sessions.set_interrupted(&opts.session_key, false);
             self.sessions
-                .lock()
                 .add_message(&opts.session_key, "user", &opts.user_message);
         }
-        let (final_content, iteration, sent_message_tool) =
-            self.run_llm_iteration(&mut messages, &opts).await?;
-        let final_content = if final_content.is_empty() && !sent_message_tool {
-            opts.default_response.clone()
-        } else {
-            final_content
+        let (cancel_tx, mut cancel_rx) = watch::channel(false);
+        self.active_turns
+            .lock()
+            .insert(opts.session_key.clone(), cancel_tx);
+        let trace = self.traces.start_turn();
+        // Dropping the iteration future aborts the pending provider request
+        // and kills any running exec child.
+        let outcome = tokio::select! {
+            result = self.run_llm_iteration(&mut messages, &opts, stream, &trace) => Some(result),
+            Ok(_) = cancel_rx.wait_for(|stop| *stop) => None,
+        };
+        self.active_turns.lock().remove(&opts.session_key);
+        let Some(result) = outcome else {
+            trace.finish("cancelled", CANCELLED_MARKER);
+            return Ok(self.record_cancellation(&opts));
         };
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                trace.finish("error", &err.to_string());
+                return Err(err);
+            }
+        };
+        let mut final_content = outcome.content;
+        if outcome.hit_limit {
+            let mut notice = format!(
+                "⚠️ Stopped after {} tool iterations, the limit for one turn.",
+                outcome.iterations
+            );
+            if !opts.no_history {
+                notice.push_str(" Send /continue to keep going.");
+                self.sessions.set_interrupted(&opts.session_key, true);
+            }
+            if !final_content.is_empty() {
+                final_content.push_str("\n\n");
+            }
+            final_content.push_str(&notice);
+        } else if final_content.is_empty() && !outcome.sent_message_tool {
+            final_content = opts.default_response.clone();
+        }
         if !opts.no_history {
             self.sessions
-                .lock()
                 .add_message(&opts.session_key, "assistant", &final_content);
-            let _ = self.sessions.lock().save(&opts.session_key);
+            let _ = self.sessions.save(&opts.session_key);
         }
         if opts.enable_summary {
-            self.maybe_summarize(&opts.session_key, &opts.channel, &opts.chat_id);
+            self.maybe_summarize(&opts);
         }
+        trace.finish(
+            if outcome.hit_limit { "limit" } else { "ok" },
+            &final_content,
+        );
         tracing::info!(
-            "Response: {} (iterations: {})",
+            "Response: {} (iterations: {}, turn {})",
             final_content.chars().take(120).collect::<String>(),
-            iteration
+            outcome.iterations,
+            trace.id
         );
         Ok(final_content)
     }
+    fn record_cancellation(&self, opts: &ProcessOptions) -> String {
+        tracing::info!("turn in {} cancelled by user", opts.session_key);
+        if !opts.no_history {
+            self.sessions.close_pending_tool_calls(
+                &opts.session_key,
+                "Cancelled by user before completion.",
+            );
+            self.sessions
+                .add_message(&opts.session_key, "assistant", CANCELLED_MARKER);
+            let _ = self.sessions.save(&opts.session_key);
+        }
+        CANCELLED_MARKER.to_string()
+    }
+    /// Signals the running turn of `session_key` to stop. Returns false when
+    /// nothing was running.
+    fn cancel_turn(&self, session_key: &str) -> bool {
+        match self.active_turns.lock().get(session_key) {
+            Some(cancel) => {
+                cancel.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
     fn build_messages(
         &self,
         history: Vec<Message>,
         summary: String,
-        current_message: &str,
-        _media: Option<&[String]>,
-        channel: &str,
+        opts: &ProcessOptions,
     ) -> Vec<Message> {
-        let tool_summaries = self.tools.lock().get_summaries();
-        self.context_builder.build_messages(
+        let tool_summaries = self
+            .tools
+            .lock()
+            .get_summaries(&self.tool_permissions(opts));
+        let mut messages = self.context_builder.build_messages(
             history,
             summary,
-            current_message,
-            channel,
+            &opts.user_message,
+            &opts.channel,
             &tool_summaries,
+        );
+        // Images only go with the turn they arrived in; history keeps the text.
+        if !opts.media.is_empty()
+            && let Some(current) = messages.last_mut()
+        {
+            let model = self
+                .sessions
+                .get_model(&opts.session_key)
+                .map_or_else(|| self.model.clone(), |selection| selection.model);
+            let vision = media::supports_vision(&model, &self.config.agents.defaults.vision_models);
+            media::attach_images(current, &opts.media, vision);
+        }
+        messages
+    }
+    /// Tools the turn's sender may use in its chat. Background turns and
+    /// subagent reports have no sender of their own.
+    fn tool_permissions(&self, opts: &ProcessOptions) -> ToolPermissions {
+        let sender =
+            (!opts.sender_id.is_empty() && !opts.background).then_some(opts.sender_id.as_str());
+        ToolPermissions::resolve(
+            &self.config.tools.permissions,
+            &opts.channel,
+            &opts.chat_id,
+            sender,
         )
     }
     async fn run_llm_iteration(
         &self,
         messages: &mut Vec<Message>,
         opts: &ProcessOptions,
-    ) -> anyhow::Result<(String, i32, bool)> {
+        stream: Option<&StreamSink<'_>>,
+        trace: &TurnTrace,
+    ) -> anyhow::Result<TurnOutcome> {
         let mut iteration = 0;
         let mut final_content = String::new();
         let mut sent_message_tool = false;
-        while iteration < self.max_iterations {
+        let mut finished = false;
+        let active = self.active_model(&opts.session_key);
+        let usage_ctx = UsageContext {
+            session_key: &opts.session_key,
+            channel: &opts.channel,
+            provider: &active.provider_name,
+            model: &active.model,
+            sender_id: &opts.sender_id,
+            background: opts.background,
+        };
+        let settings = self.generation_settings(&opts.channel, &opts.session_key);
+        let max_iterations = settings
+            .max_tool_iterations
+            .unwrap_or(self.config.agents.defaults.max_tool_iterations);
+        let permissions = self.tool_permissions(opts);
+        trace.record(TraceEvent::TurnStart {
+            session_key: opts.session_key.clone(),
+            channel: opts.channel.clone(),
+            chat_id: opts.chat_id.clone(),
+            sender_id: opts.sender_id.clone(),
+            provider: active.provider_name.clone(),
+            model: active.model.clone(),
+            user_message: opts.user_message.clone(),
+            system_prompt_hash: trace.system_prompt(messages),
+        });
+        while iteration < max_iterations {
             iteration += 1;
-            tracing::debug!("LLM iteration {}/{}", iteration, self.max_iterations);
-            let tool_defs = self.tools.lock().to_provider_defs();
-            let mut options = HashMap::new();
-            options.insert("max_tokens".to_string(), serde_json::json!(8192));
-            options.insert("temperature".to_string(), serde_json::json!(0.7));
-            let response = self
-                .provider
-                .chat_with_options(messages, Some(&tool_defs), &self.model, options)
-                .await?;
+            tracing::debug!("LLM iteration {}/{}", iteration, max_iterations);
+            if let Some(notice) = self.usage.budget_exhausted(&usage_ctx) {
+                tracing::warn!("budget exhausted for {}: {}", opts.session_key, notice);
+                final_content = notice;
+                finished = true;
+                break;
+            }
+            let tool_defs = self.tools.lock().to_provider_defs(&permissions);
+            let max_output = settings.max_tokens.unwrap_or_default().max(0) as usize;
+            let budget = context_window::prompt_budget(
+                self.context_size(&active.model),
+                max_output,
+                context_window::estimate_tool_defs(&tool_defs),
+            );
+            let fitted = context_window::fit(messages, budget);
+            if fitted.changed() {
+                tracing::info!(
+                    "context for {} trimmed to {} tokens: {:?}",
+                    opts.session_key,
+                    budget,
+                    fitted
+                );
+            }
+            let options = settings.to_options();
+            trace.request(
+                iteration,
+                &active.model,
+                messages,
+                tool_defs.iter().map(|d| d.function.name.clone()).collect(),
+                &options,
+                fitted.changed(),
+            );
+            let started = Instant::now();
+            let result = match stream {
+                Some(sink) => {
+                    sink("");
+                    let buffer = Mutex::new(String::new());
+                    let on_delta = |delta: &str| {
+                        let mut buffer = buffer.lock();
+                        buffer.push_str(delta);
+                        sink(&buffer);
+                    };
+                    active
+                        .provider
+                        .chat_stream(
+                            messages,
+                            Some(&tool_defs),
+                            &active.model,
+                            options,
+                            &on_delta,
+                        )
+                        .await
+                }
+                None => {
+                    active
+                        .provider
+                        .chat_with_options(messages, Some(&tool_defs), &active.model, options)
+                        .await
+                }
+            };
+            let duration_ms = started.elapsed().as_millis() as u64;
+            let response = match result {
+                Ok(response) => response,
+                Err(err) => {
+                    trace.record(TraceEvent::ProviderError {
+                        iteration,
+                        duration_ms,
+                        error: err.to_string(),
+                    });
+                    return Err(err);
+                }
+            };
+            trace.record(TraceEvent::Response {
+                iteration,
+                duration_ms,
+                content: response.content.clone(),
+                tool_calls: response.tool_calls.clone(),
+                finish_reason: response.finish_reason.clone(),
+                usage: response.usage.clone(),
+            });
+            if let Some(usage) = response.usage.as_ref() {
+                self.usage.record(&usage_ctx, usage);
+            }
             if response.tool_calls.is_empty() {
                 final_content = response.content;
                 if sent_message_tool {
                     final_content.clear();
                 }
                 tracing::info!("LLM response without tool calls (direct answer)");
+                finished = true;
                 break;
             }
             let tool_names: Vec<String> = response
@@ -276,24 +779,46 @@ impl AgentLoop {
                 content: response.content.clone(),
                 tool_calls: response.tool_calls.clone(),
                 tool_call_id: None,
+                images: vec![],
             });
             if !opts.no_history
                 && let Some(last) = messages.last()
             {
                 self.sessions
-                    .lock()
                     .add_full_message(&opts.session_key, last.clone());
             }
             for tc in &response.tool_calls {
                 let tool_name = tc.name.clone().unwrap_or_default();
                 let tool_args = tc.arguments.clone().unwrap_or_default();
                 tracing::info!("Executing tool: {}", tool_name);
+                trace.record(TraceEvent::ToolCall {
+                    iteration,
+                    id: tc.id.clone(),
+                    name: tool_name.clone(),
+                    args: tool_args.clone(),
+                });
+                let started = Instant::now();
                 let tool = { self.tools.lock().get(&tool_name) };
-                let result: ToolResult = if let Some(tool) = tool {
-                    tool.execute(tool_args, &opts.channel, &opts.chat_id).await
-                } else {
-                    ToolResult::error(&format!("Tool not found: {}", tool_name))
+                // Definitions are filtered already, but the model can still
+                // name a tool it wasn't offered.
+                let result: ToolResult = match (tool, permissions.check(&tool_name, &tool_args)) {
+                    (None, _) => ToolResult::error(&format!("Tool not found: {}", tool_name)),
+                    (Some(_), Err(denied)) => {
+                        tracing::warn!("{} denied for {}: {}", tool_name, opts.session_key, denied);
+                        ToolResult::error(&denied)
+                    }
+                    (Some(tool), Ok(())) => {
+                        tool.execute(tool_args, &opts.channel, &opts.chat_id).await
+                    }
                 };
+                trace.record(TraceEvent::ToolResult {
+                    iteration,
+                    id: tc.id.clone(),
+                    name: tool_name.clone(),
+                    duration_ms: started.elapsed().as_millis() as u64,
+                    result: result.for_llm.clone().unwrap_or_default(),
+                    error: result.error.clone(),
+                });
                 if tool_name == "message" && result.error.is_none() && result.silent {
                     sent_message_tool = true;
                 }
@@ -306,6 +831,7 @@ impl AgentLoop {
                             channel: opts.channel.clone(),
                             chat_id: opts.chat_id.clone(),
                             content: for_user.clone(),
+                            kind: OutboundKind::Final,
                         })
                         .await;
                 }
@@ -320,25 +846,135 @@ impl AgentLoop {
                     && let Some(last) = messages.last()
                 {
                     self.sessions
-                        .lock()
                         .add_full_message(&opts.session_key, last.clone());
                 }
             }
         }
-        Ok((final_content, iteration, sent_message_tool))
+        if !finished {
+            tracing::warn!(
+                "{} reached the tool iteration limit ({})",
+                opts.session_key,
+                max_iterations
+            );
+            final_content = self
+                .wrap_up(
+                    messages,
+                    &active,
+                    &settings,
+                    &usage_ctx,
+                    trace,
+                    iteration + 1,
+                )
+                .await;
+        }
+        Ok(TurnOutcome {
+            content: final_content,
+            iterations: iteration,
+            sent_message_tool,
+            hit_limit: !finished,
+        })
+    }
+    /// Last call, without tools, once the iteration limit is reached: the
+    /// model reports what it got done and what is left instead of the turn
+    /// ending with nothing to say.
+    async fn wrap_up(
+        &self,
+        messages: &[Message],
+        active: &ActiveModel,
+        settings: &GenerationSettings,
+        usage_ctx: &UsageContext<'_>,
+        trace: &TurnTrace,
+        iteration: i32,
+    ) -> String {
+        if let Some(notice) = self.usage.budget_exhausted(usage_ctx) {
+            return notice;
+        }
+        let mut request = messages.to_vec();
+        request.push(Message::user(WRAP_UP_PROMPT));
+        let max_output = settings.max_tokens.unwrap_or_default().max(0) as usize;
+        let budget = context_window::prompt_budget(self.context_size(&active.model), max_output, 0);
+        let fitted = context_window::fit(&mut request, budget);
+        let options = settings.to_options();
+        trace.request(
+            iteration,
+            &active.model,
+            &request,
+            vec![],
+            &options,
+            fitted.changed(),
+        );
+        let started = Instant::now();
+        let result = active
+            .provider
+            .chat_with_options(&mut request, None, &active.model, options)
+            .await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+        match result {
+            Ok(response) => {
+                trace.record(TraceEvent::Response {
+                    iteration,
+                    duration_ms,
+                    content: response.content.clone(),
+                    tool_calls: vec![],
+                    finish_reason: response.finish_reason.clone(),
+                    usage: response.usage.clone(),
+                });
+                if let Some(usage) = response.usage.as_ref() {
+                    self.usage.record(usage_ctx, usage);
+                }
+                response.content
+            }
+            Err(err) => {
+                tracing::warn!("wrap-up call for {} failed: {}", usage_ctx.session_key, err);
+                trace.record(TraceEvent::ProviderError {
+                    iteration,
+                    duration_ms,
+                    error: err.to_string(),
+                });
+                String::new()
+            }
+        }
     }
     fn update_tool_contexts(&self, _channel: &str, _chat_id: &str) {}
-    fn maybe_summarize(&self, session_key: &str, _channel: &str, _chat_id: &str) {
-        let history = self.sessions.lock().get_history(session_key);
-        let token_estimate = self.estimate_tokens(&history);
-        let threshold = self.context_window * 75 / 100;
-        if history.len() > 20 || token_estimate > threshold {
-            tracing::debug!("Session {} exceeds threshold, would summarize", session_key);
+    /// Folds older turns into the session summary once the history grows past
+    /// the threshold. Runs in the background so the reply isn't delayed.
+    fn maybe_summarize(&self, opts: &ProcessOptions) {
+        let history = self.sessions.get_history(&opts.session_key);
+        let active = self.active_model(&opts.session_key);
+        let token_estimate = context_window::estimate_tokens(&history);
+        let threshold = self.context_size(&active.model) * 75 / 100;
+        if history.len() <= SUMMARY_MESSAGE_THRESHOLD && token_estimate <= threshold {
+            return;
+        }
+        let Some(cut) = summary_cut(&history) else {
+            return;
+        };
+        if !self.summarizing.lock().insert(opts.session_key.clone()) {
+            return;
         }
+        let job = SummaryJob {
+            provider: active.provider,
+            model: active.model,
+            sessions: self.sessions.clone(),
+            usage: self.usage.clone(),
+            provider_name: active.provider_name,
+            session_key: opts.session_key.clone(),
+            channel: opts.channel.clone(),
+            sender_id: opts.sender_id.clone(),
+            background: opts.background,
+            older: history[..cut].to_vec(),
+        };
+        let summarizing = self.summarizing.clone();
+        tokio::spawn(async move {
+            let key = job.session_key.clone();
+            if let Err(err) = job.run().await {
+                tracing::warn!("summarization of {} failed: {}", key, err);
+            }
+            summarizing.lock().remove(&key);
+        });
     }
-    fn estimate_tokens(&self, messages: &[Message]) -> i32 {
-        let total_chars: usize = messages.iter().map(|m| m.content.chars().count()).sum();
-        (total_chars as f32 * 0.4) as i32
+    fn context_size(&self, model: &str) -> usize {
+        context_window::context_size(model, &self.config.agents.defaults.context_windows)
     }
     fn truncate_tool_message(&self, content: String) -> String {
         if self.tool_output_max_chars == 0 {
@@ -364,36 +1000,131 @@ impl AgentLoop {
         let cmd = parts[0];
         let args = &parts[1..];
         match cmd {
-            "/help" | "/start" => {
-                Ok("Available commands: /help, /model, /status, /show, /list, /switch".to_string())
-            }
-            "/model" => Ok(format!("Current model: {}", self.model)),
-            "/status" => Ok("Agent is running".to_string()),
-            "/show" => self.handle_show_command(args).await,
-            "/list" => self.handle_list_command(args).await,
-            "/switch" => self.handle_switch_command(args).await,
+            "/help" | "/start" => Ok(
+                "Available commands: /help, /model, /status, /usage, /new, /undo, /retry, /forget, /stop, /continue, /approve, /deny, /show, /list, /switch, /set"
+                    .to_string(),
+            ),
+            "/model" => Ok(self.describe_model(&msg.session_key)),
+            "/status" => Ok(match self.provider.status() {
+                Some(backend) => format!("Agent is running\n{}", backend),
+                None => "Agent is running".to_string(),
+            }),
+            // The cancelled turn itself replies with the cancellation notice.
+            "/stop" => Ok(if self.cancel_turn(&msg.session_key) {
+                String::new()
+            } else {
+                "Nothing to stop.".to_string()
+            }),
+            "/new" => Ok(if self.sessions.archive(&msg.session_key)? {
+                "Started a new conversation; the previous one was archived.".to_string()
+            } else {
+                "This conversation is already empty.".to_string()
+            }),
+            "/undo" => Ok(match self.sessions.pop_turn(&msg.session_key) {
+                Some(user) => {
+                    self.sessions.save(&msg.session_key)?;
+                    format!("↩️ Removed the last exchange: {}", preview(&user.content))
+                }
+                None => "Nothing to undo.".to_string(),
+            }),
+            "/forget" => self.forget(msg),
+            // Reached only when there was no turn to retry.
+            "/retry" => Ok("Nothing to retry.".to_string()),
+            // Reached only when the last turn didn't stop at the limit.
+            "/continue" => Ok("Nothing to continue: the last turn wasn't cut short.".to_string()),
+            "/approve" | "/deny" => Ok(self.answer_approval(msg, cmd, args)),
+            "/usage" => Ok(crate::usage::chat_report(
+                &self.usage.load(),
+                &msg.session_key,
+            )),
+            "/show" => self.handle_show_command(msg, args).await,
+            "/list" => self.handle_list_command(msg, args).await,
+            "/switch" => self.handle_switch_command(msg, args).await,
+            "/set" => Ok(self.set_setting(msg, args)),
             _ => Ok(format!("Unknown command: {}", cmd)),
         }
     }
-    async fn handle_show_command(&self, args: &[&str]) -> anyhow::Result<String> {
+    /// Deletes what is stored about the caller's chat: its session with the
+    /// archived copies, the subagent and system sessions run on its behalf,
+    /// the traces of their turns and downloaded media. Memory notes are
+    /// shared and stay.
+    fn forget(&self, msg: &InboundMessage) -> anyhow::Result<String> {
+        let keys = [
+            msg.session_key.clone(),
+            format!("subagent:{}:{}", msg.channel, msg.chat_id),
+            format!("system:{}:{}", msg.channel, msg.chat_id),
+            format!("system:heartbeat:{}:{}", msg.channel, msg.chat_id),
+        ];
+        let mut sessions = 0;
+        let mut archives = 0;
+        for key in &keys {
+            sessions += usize::from(self.sessions.delete(key)?);
+            archives += self.sessions.delete_archives(Some(key), None)?;
+        }
+        let traces = crate::trace::forget_sessions(&self.config.workspace_path(), &keys)?;
+        let media = crate::retention::forget_media(&msg.channel, &msg.chat_id)?;
+        let removed: Vec<String> = [
+            (sessions, "session"),
+            (archives, "archived conversation"),
+            (traces, "trace"),
+            (media, "media file"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{} {}{}", count, what, if count == 1 { "" } else { "s" }))
+        .collect();
+        Ok(if removed.is_empty() {
+            "Nothing stored for this chat.".to_string()
+        } else {
+            format!("🧹 Forgot this chat: deleted {}.", removed.join(", "))
+        })
+    }
+    fn answer_approval(&self, msg: &InboundMessage, cmd: &str, args: &[&str]) -> String {
+        let decision = match (cmd, args) {
+            ("/approve", [_]) => Decision::Approve,
+            ("/approve", [_, "always"]) => Decision::ApproveAlways,
+            ("/deny", [_]) => Decision::Deny,
+            _ => return "Usage: /approve <id> [always] | /deny <id>".to_string(),
+        };
+        let id = args[0];
+        if !self
+            .approvals
+            .resolve(&msg.channel, &msg.chat_id, id, decision)
+        {
+            return format!("No pending approval {} in this chat.", id);
+        }
+        match decision {
+            Decision::Deny => format!("❌ Denied {}.", id),
+            _ => format!("✅ Approved {}.", id),
+        }
+    }
+    async fn handle_show_command(
+        &self,
+        msg: &InboundMessage,
+        args: &[&str],
+    ) -> anyhow::Result<String> {
         if args.is_empty() {
-            return Ok("Usage: /show [model|channel]".to_string());
+            return Ok("Usage: /show [model|settings|channel]".to_string());
         }
         match args[0] {
-            "model" => Ok(format!("Current model: {}", self.model)),
+            "model" => Ok(self.describe_model(&msg.session_key)),
+            "settings" => Ok(self.describe_settings(msg)),
             "channel" => Ok("Current channel: cli".to_string()),
             _ => Ok(format!("Unknown show target: {}", args[0])),
         }
     }
-    async fn handle_list_command(&self, args: &[&str]) -> anyhow::Result<String> {
+    async fn handle_list_command(
+        &self,
+        msg: &InboundMessage,
+        args: &[&str],
+    ) -> anyhow::Result<String> {
         if args.is_empty() {
-            return Ok("Usage: /list [models|channels]".to_string());
+            return Ok("Usage: /list [models [filter]|channels]".to_string());
         }
         match args[0] {
-            "models" => Ok(
-                "Available models: glm-4.7, claude-3-5-sonnet, gpt-4o (configured in config)"
-                    .to_string(),
-            ),
+            "models" => Ok(self
+                .list_models(&msg.session_key, args.get(1).copied())
+                .await),
             "channels" => {
                 let manager = self.channel_manager.read();
                 if let Some(cm) = manager.as_ref() {
@@ -409,45 +1140,446 @@ impl AgentLoop {
             _ => Ok(format!("Unknown list target: {}", args[0])),
         }
     }
-    async fn handle_switch_command(&self, args: &[&str]) -> anyhow::Result<String> {
+    async fn handle_switch_command(
+        &self,
+        msg: &InboundMessage,
+        args: &[&str],
+    ) -> anyhow::Result<String> {
         if args.len() < 3 || args[1] != "to" {
             return Ok("Usage: /switch [model|channel] to <name>".to_string());
         }
         match args[0] {
-            "model" => Ok(format!("Switch requested: {} -> {}", self.model, args[2])),
+            "model" => Ok(self.switch_model(&msg.session_key, &args[2..])),
             "channel" => Ok(format!("Switched target channel to {}", args[2])),
             _ => Ok(format!("Unknown switch target: {}", args[0])),
         }
     }
-    pub async fn run(&self) -> anyhow::Result<()> {
+    /// Generation settings for a turn: config defaults, then the channel's
+    /// overrides, then the session's `/set` values.
+    fn generation_settings(&self, channel: &str, session_key: &str) -> GenerationSettings {
+        let mut settings = self.config.agents.generation_for_channel(channel);
+        settings.overlay(&self.sessions.get_settings(session_key));
+        settings
+    }
+    fn describe_settings(&self, msg: &InboundMessage) -> String {
+        let defaults = self.config.agents.defaults.generation();
+        let channel = self.config.agents.channels.get(&msg.channel);
+        let session = self.sessions.get_settings(&msg.session_key);
+        let mut out = "Generation settings:".to_string();
+        for param in GenerationSettings::PARAMS {
+            let (value, source) = if let Some(value) = session.get(param) {
+                (value, "session")
+            } else if let Some(value) = channel.and_then(|c| c.get(param)) {
+                (value, "channel")
+            } else if let Some(value) = defaults.get(param) {
+                (value, "default")
+            } else {
+                ("unset".to_string(), "default")
+            };
+            out.push_str(&format!("\n• {}: {} ({})", param, value, source));
+        }
+        out
+    }
+    /// `/set <param> <value>`; `default` clears one override, `/set reset` all.
+    fn set_setting(&self, msg: &InboundMessage, args: &[&str]) -> String {
+        let mut settings = self.sessions.get_settings(&msg.session_key);
+        let reply = match args {
+            ["reset"] => {
+                settings = GenerationSettings::default();
+                "Settings for this chat reset to defaults.".to_string()
+            }
+            [param, value @ ..] if !value.is_empty() => {
+                let value = value.join(" ");
+                let value = (value != "default").then_some(value.as_str());
+                if let Err(err) = settings.set(param, value) {
+                    return format!("Can't set {}: {}", param, err);
+                }
+                match value {
+                    Some(value) => format!("{} set to {} for this chat.", param, value),
+                    None => format!("{} reset to default for this chat.", param),
+                }
+            }
+            _ => {
+                return format!(
+                    "Usage: /set <param> <value|default> or /set reset\nParams: {}",
+                    GenerationSettings::PARAMS.join(", ")
+                );
+            }
+        };
+        self.sessions.set_settings(&msg.session_key, settings);
+        if let Err(err) = self.sessions.save(&msg.session_key) {
+            tracing::warn!("failed to save session {}: {}", msg.session_key, err);
+        }
+        reply
+    }
+    /// The session's `/switch model` choice, or the configured default.
+    fn active_model(&self, session_key: &str) -> ActiveModel {
+        let default = ActiveModel {
+            provider: self.provider.clone(),
+            provider_name: self.provider_name.clone(),
+            model: self.model.clone(),
+        };
+        match self.sessions.get_model(session_key) {
+            None => default,
+            Some(ModelSelection {
+                provider: None,
+                model,
+            }) => ActiveModel { model, ..default },
+            Some(ModelSelection {
+                provider: Some(name),
+                model,
+            }) => match self.provider_named(&name) {
+                Ok(provider) => ActiveModel {
+                    provider,
+                    provider_name: name,
+                    model,
+                },
+                Err(err) => {
+                    tracing::warn!(
+                        "session {} uses provider '{}' which is unavailable: {}",
+                        session_key,
+                        name,
+                        err
+                    );
+                    default
+                }
+            },
+        }
+    }
+    fn provider_named(&self, name: &str) -> anyhow::Result<Arc<dyn Provider>> {
+        if name == self.provider_name {
+            return Ok(self.provider.clone());
+        }
+        if let Some(provider) = self.switched_providers.lock().get(name) {
+            return Ok(provider.clone());
+        }
+        let (provider, _) = crate::providers::build_provider(&self.config, name)?;
+        self.switched_providers
+            .lock()
+            .insert(name.to_string(), provider.clone());
+        Ok(provider)
+    }
+    fn describe_model(&self, session_key: &str) -> String {
+        let active = self.active_model(session_key);
+        let origin = if self.sessions.get_model(session_key).is_some() {
+            "set for this chat"
+        } else {
+            "default"
+        };
+        format!(
+            "Current model: {} (provider: {}, {})",
+            active.model, active.provider_name, origin
+        )
+    }
+    /// `/switch model to <name> [on <provider>]`, or `default` to reset.
+    fn switch_model(&self, session_key: &str, args: &[&str]) -> String {
+        let selection = match args {
+            ["default"] => None,
+            [model] => Some(ModelSelection {
+                provider: None,
+                model: model.to_string(),
+            }),
+            [model, "on", provider] => {
+                let provider = provider.to_lowercase();
+                if let Err(err) = self.provider_named(&provider) {
+                    return format!("Can't switch to provider '{}': {}", provider, err);
+                }
+                Some(ModelSelection {
+                    provider: Some(provider),
+                    model: model.to_string(),
+                })
+            }
+            _ => return "Usage: /switch model to <name> [on <provider>] | default".to_string(),
+        };
+        self.sessions.set_model(session_key, selection);
+        if let Err(err) = self.sessions.save(session_key) {
+            tracing::warn!("failed to save session {}: {}", session_key, err);
+        }
+        let active = self.active_model(session_key);
+        format!(
+            "Model for this chat: {} (provider: {})",
+            active.model, active.provider_name
+        )
+    }
+    async fn list_models(&self, session_key: &str, filter: Option<&str>) -> String {
+        let active = self.active_model(session_key);
+        let cached = self
+            .model_lists
+            .lock()
+            .get(&active.provider_name)
+            .filter(|(fetched, _)| fetched.elapsed() < MODEL_LIST_TTL)
+            .map(|(_, models)| models.clone());
+        let mut models = match cached {
+            Some(models) => models,
+            None => match active.provider.list_models().await {
+                Ok(mut models) => {
+                    models.sort();
+                    models.dedup();
+                    self.model_lists.lock().insert(
+                        active.provider_name.clone(),
+                        (Instant::now(), models.clone()),
+                    );
+                    models
+                }
+                Err(err) => {
+                    return format!(
+                        "Couldn't list models for {}: {}\nCurrent model: {}",
+                        active.provider_name, err, active.model
+                    );
+                }
+            },
+        };
+        if let Some(filter) = filter {
+            let filter = filter.to_lowercase();
+            models.retain(|m| m.to_lowercase().contains(&filter));
+        }
+        let is_active = |id: &str| {
+            id == active.model
+                || active
+                    .model
+                    .rsplit_once('/')
+                    .is_some_and(|(_, bare)| bare == id)
+        };
+        let mut out = format!("Models on {} ({}):", active.provider_name, models.len());
+        for id in models.iter().take(MODEL_LIST_MAX_SHOWN) {
+            out.push_str("\n• ");
+            out.push_str(id);
+            if is_active(id) {
+                out.push_str(" ← active");
+            }
+        }
+        if models.len() > MODEL_LIST_MAX_SHOWN {
+            out.push_str(&format!(
+                "\n… and {} more; narrow it down with /list models <filter>",
+                models.len() - MODEL_LIST_MAX_SHOWN
+            ));
+        }
+        if !models.iter().any(|id| is_active(id)) {
+            out.push_str(&format!("\nActive: {}", active.model));
+        }
+        out.push_str("\nSwitch with /switch model to <name> [on <provider>]");
+        out
+    }
+    /// Consumes inbound messages until the bus closes. Different sessions are
+    /// processed concurrently (up to `max_concurrent_sessions`); messages of
+    /// one session are handled strictly in arrival order.
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
         self.running.store(true, Ordering::SeqCst);
         let mut rx = self.bus.take_inbound_receiver()?;
+        let permits = Arc::new(Semaphore::new(self.max_concurrent_sessions));
+        // A key is present while a worker owns that session; its queue holds
+        // the messages that arrived meanwhile.
+        let queues: Arc<Mutex<HashMap<String, VecDeque<InboundMessage>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
         while let Some(msg) = rx.recv().await {
             if !self.running.load(Ordering::SeqCst) {
                 break;
             }
-            if let Err(err) = self.process_message(msg).await {
-                tracing::error!("Error processing message: {}", err);
+            if is_out_of_band(&msg.content) {
+                // Bypasses the session queue, which is blocked by the very
+                // turn it has to stop or that waits for the approval.
+                let agent = self.clone();
+                tokio::spawn(async move { agent.handle_inbound(msg).await });
+                continue;
+            }
+            let key = dispatch_key(&msg);
+            {
+                let mut queues = queues.lock();
+                if let Some(queue) = queues.get_mut(&key) {
+                    queue.push_back(msg);
+                    continue;
+                }
+                queues.insert(key.clone(), VecDeque::new());
             }
+            let agent = self.clone();
+            let permits = permits.clone();
+            let queues = queues.clone();
+            tokio::spawn(async move {
+                let mut next = Some(msg);
+                while let Some(msg) = next {
+                    let Ok(_permit) = permits.acquire().await else {
+                        queues.lock().remove(&key);
+                        break;
+                    };
+                    agent.handle_inbound(msg).await;
+                    let mut queues = queues.lock();
+                    next = queues.get_mut(&key).and_then(VecDeque::pop_front);
+                    if next.is_none() {
+                        queues.remove(&key);
+                    }
+                }
+            });
         }
         tracing::info!("Agent loop stopped");
         Ok(())
     }
+    async fn handle_inbound(&self, msg: InboundMessage) {
+        let (channel, chat_id) = (msg.channel.clone(), msg.chat_id.clone());
+        if let Err(err) = self.process_message(msg).await {
+            tracing::error!("Error processing message: {}", err);
+            if !constants::is_internal_channel(&channel) {
+                let notice = OutboundMessage {
+                    channel,
+                    chat_id,
+                    content: format!("⚠️ {}", truncate_error(&err.to_string())),
+                    kind: OutboundKind::Final,
+                };
+                if let Err(err) = self.bus.publish_outbound(notice).await {
+                    tracing::error!("failed to publish error notice: {}", err);
+                }
+            }
+        }
+    }
     pub fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
     }
     pub fn get_startup_info(&self) -> serde_json::Value {
         let tools = self.tools.lock();
         let skills = self.context_builder.get_skills_info();
+        let mut agents: Vec<&String> = self.profiles.keys().collect();
+        agents.sort();
         serde_json::json!({
             "tools": {
                 "count": tools.len(),
                 "names": tools.list_names()
             },
-            "skills": skills
+            "skills": skills,
+            "agents": agents
         })
     }
 }
+const ERROR_NOTICE_MAX_CHARS: usize = 300;
+/// Index where older history is cut off for summarization: the start of a
+/// user turn, so tool calls and their results always stay on one side.
+fn summary_cut(history: &[Message]) -> Option<usize> {
+    let limit = history.len().checked_sub(SUMMARY_KEEP_RECENT)?;
+    (1..=limit).rev().find(|&i| history[i].role == "user")
+}
+struct SummaryJob {
+    provider: Arc<dyn Provider>,
+    model: String,
+    sessions: Arc<SessionManager>,
+    usage: Arc<UsageLedger>,
+    provider_name: String,
+    session_key: String,
+    channel: String,
+    sender_id: String,
+    background: bool,
+    older: Vec<Message>,
+}
+impl SummaryJob {
+    async fn run(self) -> anyhow::Result<()> {
+        let ctx = UsageContext {
+            session_key: &self.session_key,
+            channel: &self.channel,
+            provider: &self.provider_name,
+            model: &self.model,
+            sender_id: &self.sender_id,
+            background: self.background,
+        };
+        if let Some(notice) = self.usage.budget_exhausted(&ctx) {
+            tracing::debug!("skipping summarization of {}: {}", self.session_key, notice);
+            return Ok(());
+        }
+        let previous = self.sessions.get_summary(&self.session_key);
+        let mut transcript = String::new();
+        if !previous.is_empty() {
+            transcript.push_str("Existing summary:\n");
+            transcript.push_str(&previous);
+            transcript.push_str("\n\n");
+        }
+        transcript.push_str("New messages:\n");
+        for msg in &self.older {
+            transcript.push_str(&render_for_summary(msg));
+            transcript.push('\n');
+        }
+        let mut messages = vec![Message::system(SUMMARY_PROMPT), Message::user(&transcript)];
+        let options = HashMap::from([
+            ("max_tokens".to_string(), serde_json::json!(1024)),
+            ("temperature".to_string(), serde_json::json!(0.3)),
+        ]);
+        let response = self
+            .provider
+            .chat_with_options(&mut messages, None, &self.model, options)
+            .await?;
+        if let Some(usage) = response.usage.as_ref() {
+            self.usage.record(&ctx, usage);
+        }
+        let summary = response.content.trim();
+        if summary.is_empty() {
+            anyhow::bail!("provider returned an empty summary");
+        }
+        if !self
+            .sessions
+            .apply_summary(&self.session_key, summary, &self.older)
+        {
+            tracing::info!("history of {} changed, summary dropped", self.session_key);
+            return Ok(());
+        }
+        self.sessions.save(&self.session_key)?;
+        tracing::info!(
+            "summarized {} messages of {}",
+            self.older.len(),
+            self.session_key
+        );
+        Ok(())
+    }
+}
+fn render_for_summary(msg: &Message) -> String {
+    let mut content: String = msg
+        .content
+        .chars()
+        .take(SUMMARY_MESSAGE_MAX_CHARS)
+        .collect();
+    if msg.content.chars().count() > SUMMARY_MESSAGE_MAX_CHARS {
+        content.push_str(" [...]");
+    }
+    let calls: Vec<&str> = msg
+        .tool_calls
+        .iter()
+        .filter_map(|tc| tc.name.as_deref())
+        .collect();
+    if !calls.is_empty() {
+        content.push_str(&format!(" [called tools: {}]", calls.join(", ")));
+    }
+    let role = if msg.role == "tool" {
+        "tool result"
+    } else {
+        msg.role.as_str()
+    };
+    format!("{}: {}", role, content)
+}
+/// Commands handled as soon as they arrive, even while the session's turn
+/// is still running.
+fn is_out_of_band(content: &str) -> bool {
+    let content = content.trim();
+    content == "/stop" || content.starts_with("/approve ") || content.starts_with("/deny ")
+}
+/// Messages sharing this key are never processed concurrently.
+fn dispatch_key(msg: &InboundMessage) -> String {
+    if msg.session_key.is_empty() {
+        format!("{}:{}", msg.channel, msg.chat_id)
+    } else {
+        msg.session_key.clone()
+    }
+}
+fn truncate_error(err: &str) -> String {
+    if err.chars().count() <= ERROR_NOTICE_MAX_CHARS {
+        return err.to_string();
+    }
+    let kept: String = err.chars().take(ERROR_NOTICE_MAX_CHARS).collect();
+    format!("{kept}…")
+}
+/// First line of a message, shortened, for command replies.
+fn preview(content: &str) -> String {
+    const MAX_CHARS: usize = 60;
+    let line = content.lines().next().unwrap_or_default();
+    let mut short: String = line.chars().take(MAX_CHARS).collect();
+    if short.len() < content.len() {
+        short.push('…');
+    }
+    format!("\"{}\"", short)
+}
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -511,4 +1643,805 @@ mod tests {
             .expect("command should work");
         assert_eq!(response, "Channel manager not initialized");
     }
+    struct StreamingProvider;
+    #[async_trait::async_trait]
+    impl Provider for StreamingProvider {
+        async fn chat_with_options(
+            &self,
+            _messages: &mut Vec<Message>,
+            _tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            Err(anyhow::anyhow!("non-streaming path should not be used"))
+        }
+        async fn chat_stream(
+            &self,
+            _messages: &mut Vec<Message>,
+            _tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            _options: HashMap<String, serde_json::Value>,
+            on_delta: &StreamSink<'_>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            on_delta("Hel");
+            on_delta("lo");
+            Ok(crate::providers::LlmResponse {
+                content: "Hello".to_string(),
+                tool_calls: Vec::new(),
+                finish_reason: Some("stop".to_string()),
+                usage: None,
+            })
+        }
+    }
+    #[tokio::test]
+    async fn direct_streaming_reports_accumulated_text() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        let bus = Arc::new(MessageBus::new());
+        let agent = AgentLoop::new(&cfg, &bus, Arc::new(StreamingProvider));
+        let updates = Mutex::new(Vec::new());
+        let on_update = |text: &str| updates.lock().push(text.to_string());
+        let response = agent
+            .process_direct_streaming("hi", "cli:stream", &on_update)
+            .await
+            .expect("streamed turn");
+        assert_eq!(response, "Hello");
+        assert_eq!(updates.into_inner(), vec!["", "Hel", "Hello"]);
+    }
+    #[tokio::test]
+    async fn exhausted_budget_replies_without_calling_provider() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        cfg.agents.defaults.streaming = false;
+        cfg.usage.budgets.global.daily_tokens = Some(0);
+        let bus = Arc::new(MessageBus::new());
+        let agent = AgentLoop::new(&cfg, &bus, Arc::new(StreamingProvider));
+        let response = agent
+            .process_message(InboundMessage {
+                channel: "telegram".to_string(),
+                sender_id: "u1".to_string(),
+                chat_id: "c1".to_string(),
+                content: "hello".to_string(),
+                media: None,
+                session_key: "telegram:c1".to_string(),
+                metadata: None,
+            })
+            .await
+            .expect("budget notice instead of a provider error");
+        assert!(response.contains("global daily token budget"), "{response}");
+    }
+    fn assistant(content: &str) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            images: vec![],
+        }
+    }
+    fn exec_call(id: &str) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: String::new(),
+            tool_calls: vec![crate::providers::ToolCall {
+                id: id.to_string(),
+                tool_type: "function".to_string(),
+                function: None,
+                name: Some("exec".to_string()),
+                arguments: None,
+            }],
+            tool_call_id: None,
+            images: vec![],
+        }
+    }
+    #[test]
+    fn summary_cut_keeps_tool_pairs_together() {
+        let mut history = vec![Message::user("u0"), assistant("a0")];
+        history.push(Message::user("u1"));
+        for i in 0..6 {
+            history.push(exec_call(&format!("c{i}")));
+            history.push(Message::tool("out", &format!("c{i}")));
+        }
+        history.push(assistant("a1"));
+        history.push(Message::user("u2"));
+        history.push(assistant("a2"));
+        // The newest ten messages start inside u1's tool loop, so the cut
+        // moves back to u1 instead of splitting a call from its result.
+        assert_eq!(summary_cut(&history), Some(2));
+        assert_eq!(summary_cut(&history[..8]), None);
+    }
+    struct SummaryProvider;
+    #[async_trait::async_trait]
+    impl Provider for SummaryProvider {
+        async fn chat_with_options(
+            &self,
+            messages: &mut Vec<Message>,
+            tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            assert!(tools.is_none());
+            let transcript = &messages[1].content;
+            assert!(transcript.contains("Existing summary:\nLikes tea."));
+            assert!(transcript.contains("user: I moved to Lisbon"));
+            assert!(transcript.contains("[called tools: exec]"));
+            Ok(crate::providers::LlmResponse {
+                content: "Likes tea. Lives in Lisbon.".to_string(),
+                tool_calls: Vec::new(),
+                finish_reason: Some("stop".to_string()),
+                usage: None,
+            })
+        }
+    }
+    #[tokio::test]
+    async fn summary_job_folds_older_messages_into_persisted_summary() {
+        let tmp = TempDir::new().expect("tempdir");
+        let sessions_dir = tmp.path().join("sessions");
+        let sessions = Arc::new(SessionManager::new(sessions_dir.clone()));
+        let older = vec![
+            Message::user("I moved to Lisbon"),
+            exec_call("c1"),
+            Message::tool("ok", "c1"),
+        ];
+        for msg in &older {
+            sessions.add_full_message("telegram:1", msg.clone());
+        }
+        sessions.add_message("telegram:1", "user", "recent");
+        sessions.apply_summary("telegram:1", "Likes tea.", &[]);
+        let job = SummaryJob {
+            provider: Arc::new(SummaryProvider),
+            model: "m".to_string(),
+            sessions: sessions.clone(),
+            usage: Arc::new(UsageLedger::new(tmp.path(), &Default::default())),
+            provider_name: "openrouter".to_string(),
+            session_key: "telegram:1".to_string(),
+            channel: "telegram".to_string(),
+            sender_id: "u1".to_string(),
+            background: false,
+            older,
+        };
+        job.run().await.expect("summarized");
+        let reloaded = SessionManager::new(sessions_dir);
+        assert_eq!(
+            reloaded.get_summary("telegram:1"),
+            "Likes tea. Lives in Lisbon."
+        );
+        let history = reloaded.get_history("telegram:1");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].content, "recent");
+    }
+    struct GatedProvider {
+        gate: tokio::sync::Notify,
+    }
+    #[async_trait::async_trait]
+    impl Provider for GatedProvider {
+        async fn chat_with_options(
+            &self,
+            messages: &mut Vec<Message>,
+            _tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            let prompt = messages
+                .last()
+                .map(|m| m.content.clone())
+                .unwrap_or_default();
+            match prompt.as_str() {
+                // Only finishes once another session has been served.
+                "slow" => tokio::time::timeout(Duration::from_secs(5), self.gate.notified())
+                    .await
+                    .map_err(|_| anyhow::anyhow!("sessions were processed serially"))?,
+                "fast" => self.gate.notify_one(),
+                _ => {}
+            }
+            Ok(crate::providers::LlmResponse {
+                content: prompt,
+                tool_calls: Vec::new(),
+                finish_reason: Some("stop".to_string()),
+                usage: None,
+            })
+        }
+    }
+    #[tokio::test]
+    async fn sessions_run_concurrently_but_stay_ordered() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        let bus = Arc::new(MessageBus::new());
+        let provider = Arc::new(GatedProvider {
+            gate: tokio::sync::Notify::new(),
+        });
+        let agent = Arc::new(AgentLoop::new(&cfg, &bus, provider));
+        let mut outbound = bus.take_outbound_receiver().expect("outbound");
+        tokio::spawn(agent.clone().run());
+        for (chat, content) in [("a", "slow"), ("b", "fast"), ("a", "after")] {
+            bus.publish_inbound(InboundMessage {
+                channel: "telegram".to_string(),
+                sender_id: "u1".to_string(),
+                chat_id: chat.to_string(),
+                content: content.to_string(),
+                media: None,
+                session_key: format!("telegram:{chat}"),
+                metadata: None,
+            })
+            .await
+            .expect("publish");
+        }
+        let mut replies = Vec::new();
+        while replies.len() < 3 {
+            let msg = tokio::time::timeout(Duration::from_secs(10), outbound.recv())
+                .await
+                .expect("reply in time")
+                .expect("bus open");
+            replies.push(format!("{}:{}", msg.chat_id, msg.content));
+        }
+        assert_eq!(replies, vec!["b:fast", "a:slow", "a:after"]);
+        agent.stop();
+    }
+    struct HangingProvider {
+        started: tokio::sync::Notify,
+    }
+    #[async_trait::async_trait]
+    impl Provider for HangingProvider {
+        async fn chat_with_options(
+            &self,
+            _messages: &mut Vec<Message>,
+            _tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            self.started.notify_one();
+            std::future::pending().await
+        }
+    }
+    #[tokio::test]
+    async fn stop_cancels_running_turn_and_marks_history() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        let bus = Arc::new(MessageBus::new());
+        let provider = Arc::new(HangingProvider {
+            started: tokio::sync::Notify::new(),
+        });
+        let agent = Arc::new(AgentLoop::new(&cfg, &bus, provider.clone()));
+        let mut outbound = bus.take_outbound_receiver().expect("outbound");
+        tokio::spawn(agent.clone().run());
+        let inbound = |content: &str| InboundMessage {
+            channel: "telegram".to_string(),
+            sender_id: "u1".to_string(),
+            chat_id: "c1".to_string(),
+            content: content.to_string(),
+            media: None,
+            session_key: "telegram:c1".to_string(),
+            metadata: None,
+        };
+        bus.publish_inbound(inbound("research everything"))
+            .await
+            .expect("publish");
+        tokio::time::timeout(Duration::from_secs(5), provider.started.notified())
+            .await
+            .expect("turn started");
+        bus.publish_inbound(inbound("/stop"))
+            .await
+            .expect("publish");
+        let reply = tokio::time::timeout(Duration::from_secs(5), outbound.recv())
+            .await
+            .expect("reply in time")
+            .expect("bus open");
+        assert_eq!(reply.content, CANCELLED_MARKER);
+        let history = agent.sessions.get_history("telegram:c1");
+        assert_eq!(
+            history.last().map(|m| m.content.as_str()),
+            Some(CANCELLED_MARKER)
+        );
+        let response = agent.process_message(inbound("/stop")).await.expect("stop");
+        assert_eq!(response, "Nothing to stop.");
+    }
+    #[derive(Default)]
+    struct ModelsProvider {
+        used: Mutex<Vec<String>>,
+        listings: std::sync::atomic::AtomicUsize,
+    }
+    #[async_trait::async_trait]
+    impl Provider for ModelsProvider {
+        async fn chat_with_options(
+            &self,
+            _messages: &mut Vec<Message>,
+            _tools: Option<&[crate::providers::ToolDefinition]>,
+            model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            self.used.lock().push(model.to_string());
+            Ok(crate::providers::LlmResponse {
+                content: "ok".to_string(),
+                tool_calls: Vec::new(),
+                finish_reason: Some("stop".to_string()),
+                usage: None,
+            })
+        }
+        async fn list_models(&self) -> anyhow::Result<Vec<String>> {
+            self.listings.fetch_add(1, Ordering::SeqCst);
+            Ok(vec!["glm-4.7".to_string(), "glm-4-flash".to_string()])
+        }
+    }
+    #[tokio::test]
+    async fn switch_model_is_per_session_and_persisted() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        cfg.agents.defaults.provider = "zhipu".to_string();
+        let bus = Arc::new(MessageBus::new());
+        let provider = Arc::new(ModelsProvider::default());
+        let agent = AgentLoop::new(&cfg, &bus, provider.clone());
+        let send = |chat: &str, content: &str| InboundMessage {
+            channel: "telegram".to_string(),
+            sender_id: "u1".to_string(),
+            chat_id: chat.to_string(),
+            content: content.to_string(),
+            media: None,
+            session_key: format!("telegram:{chat}"),
+            metadata: None,
+        };
+        let reply = agent
+            .process_message(send("a", "/switch model to glm-4-flash"))
+            .await
+            .expect("switch");
+        assert_eq!(reply, "Model for this chat: glm-4-flash (provider: zhipu)");
+        agent.process_message(send("a", "hi")).await.expect("turn");
+        agent.process_message(send("b", "hi")).await.expect("turn");
+        assert_eq!(*provider.used.lock(), vec!["glm-4-flash", "glm-4.7"]);
+        let listing = agent
+            .process_message(send("a", "/list models"))
+            .await
+            .expect("list");
+        assert!(listing.contains("• glm-4-flash ← active"), "{listing}");
+        agent
+            .process_message(send("b", "/list models flash"))
+            .await
+            .expect("list");
+        assert_eq!(provider.listings.load(Ordering::SeqCst), 1);
+        let reply = agent
+            .process_message(send("a", "/switch model to x on nosuch"))
+            .await
+            .expect("switch");
+        assert!(
+            reply.starts_with("Can't switch to provider 'nosuch'"),
+            "{reply}"
+        );
+        let reloaded = SessionManager::new(tmp.path().join("sessions"));
+        assert_eq!(
+            reloaded.get_model("telegram:a").map(|m| m.model),
+            Some("glm-4-flash".to_string())
+        );
+    }
+    #[derive(Default)]
+    struct OptionsProvider {
+        seen: Mutex<Vec<HashMap<String, serde_json::Value>>>,
+    }
+    #[async_trait::async_trait]
+    impl Provider for OptionsProvider {
+        async fn chat_with_options(
+            &self,
+            _messages: &mut Vec<Message>,
+            _tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            self.seen.lock().push(options);
+            Ok(crate::providers::LlmResponse {
+                content: "ok".to_string(),
+                tool_calls: Vec::new(),
+                finish_reason: Some("stop".to_string()),
+                usage: None,
+            })
+        }
+    }
+    #[tokio::test]
+    async fn generation_settings_layer_defaults_channel_and_session() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        cfg.agents.channels.insert(
+            "telegram".to_string(),
+            GenerationSettings {
+                temperature: Some(0.2),
+                max_tokens: Some(1024),
+                ..Default::default()
+            },
+        );
+        let bus = Arc::new(MessageBus::new());
+        let provider = Arc::new(OptionsProvider::default());
+        let agent = AgentLoop::new(&cfg, &bus, provider.clone());
+        let send = |content: &str| InboundMessage {
+            channel: "telegram".to_string(),
+            sender_id: "u1".to_string(),
+            chat_id: "c1".to_string(),
+            content: content.to_string(),
+            media: None,
+            session_key: "telegram:c1".to_string(),
+            metadata: None,
+        };
+        let reply = agent
+            .process_message(send("/set top_p 1.5"))
+            .await
+            .expect("set");
+        assert!(reply.starts_with("Can't set top_p"), "{reply}");
+        for cmd in ["/set max_tokens 256", "/set stop END, STOP"] {
+            agent.process_message(send(cmd)).await.expect("set");
+        }
+        agent.process_message(send("hi")).await.expect("turn");
+        let options = provider.seen.lock().pop().expect("provider called");
+        assert_eq!(options["temperature"], serde_json::json!(0.2));
+        assert_eq!(options["max_tokens"], serde_json::json!(256));
+        assert_eq!(options["stop"], serde_json::json!(["END", "STOP"]));
+        assert!(!options.contains_key("top_p"));
+        let shown = agent
+            .process_message(send("/show settings"))
+            .await
+            .expect("show");
+        assert!(shown.contains("• temperature: 0.2 (channel)"), "{shown}");
+        assert!(shown.contains("• max_tokens: 256 (session)"), "{shown}");
+        assert!(
+            shown.contains("• max_tool_iterations: 20 (default)"),
+            "{shown}"
+        );
+        agent
+            .process_message(send("/set max_tokens default"))
+            .await
+            .expect("set");
+        let reloaded = SessionManager::new(tmp.path().join("sessions"));
+        let stored = reloaded.get_settings("telegram:c1");
+        assert_eq!(stored.max_tokens, None);
+        assert_eq!(
+            stored.stop,
+            Some(vec!["END".to_string(), "STOP".to_string()])
+        );
+    }
+    #[derive(Default)]
+    struct PromptProvider {
+        seen: Mutex<Vec<(String, Vec<String>)>>,
+    }
+    #[async_trait::async_trait]
+    impl Provider for PromptProvider {
+        async fn chat_with_options(
+            &self,
+            messages: &mut Vec<Message>,
+            tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            let mut names: Vec<String> = tools
+                .unwrap_or_default()
+                .iter()
+                .map(|t| t.function.name.clone())
+                .collect();
+            names.sort();
+            self.seen.lock().push((messages[0].content.clone(), names));
+            Ok(crate::providers::LlmResponse {
+                content: "ok".to_string(),
+                tool_calls: Vec::new(),
+                finish_reason: Some("stop".to_string()),
+                usage: None,
+            })
+        }
+    }
+    #[tokio::test]
+    async fn routes_pick_profile_workspace_prompt_and_tools() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        cfg.agents.defaults.streaming = false;
+        cfg.agents.profiles.insert(
+            "family".to_string(),
+            crate::config::AgentProfile {
+                system_prompt_files: vec!["FAMILY.md".to_string()],
+                tools: Some(vec!["web_search".to_string()]),
+                ..Default::default()
+            },
+        );
+        cfg.agents.routes.push(crate::config::AgentRoute {
+            agent: "family".to_string(),
+            channel: Some("telegram".to_string()),
+            chat_id: Some("-100".to_string()),
+            sender: None,
+        });
+        let family_ws = tmp.path().join("agents").join("family");
+        std::fs::create_dir_all(&family_ws).expect("mkdir");
+        std::fs::write(family_ws.join("FAMILY.md"), "Be kind to grandma.").expect("write");
+        let bus = Arc::new(MessageBus::new());
+        let provider = Arc::new(PromptProvider::default());
+        let agent = AgentLoop::new(&cfg, &bus, provider.clone());
+        let send = |chat: &str| InboundMessage {
+            channel: "telegram".to_string(),
+            sender_id: "u1".to_string(),
+            chat_id: chat.to_string(),
+            content: "hi".to_string(),
+            media: None,
+            session_key: format!("telegram:{chat}"),
+            metadata: None,
+        };
+        agent
+            .process_message(send("-100"))
+            .await
+            .expect("family turn");
+        agent
+            .process_message(send("42"))
+            .await
+            .expect("default turn");
+        let seen = provider.seen.lock();
+        assert!(seen[0].0.contains("Be kind to grandma."));
+        assert_eq!(seen[0].1, vec!["web_search"]);
+        assert!(!seen[1].0.contains("grandma"));
+        assert!(seen[1].1.contains(&"exec".to_string()));
+        assert!(
+            family_ws
+                .join("sessions")
+                .join("telegram_-100.json")
+                .exists()
+        );
+        assert!(
+            !tmp.path()
+                .join("sessions")
+                .join("telegram_-100.json")
+                .exists()
+        );
+    }
+    /// Calls a tool whenever tools are offered; answers in text otherwise.
+    #[derive(Default)]
+    struct ToolHappyProvider {
+        prompts: Mutex<Vec<String>>,
+    }
+    #[async_trait::async_trait]
+    impl Provider for ToolHappyProvider {
+        async fn chat_with_options(
+            &self,
+            messages: &mut Vec<Message>,
+            tools: Option<&[crate::providers::ToolDefinition]>,
+            _model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> anyhow::Result<crate::providers::LlmResponse> {
+            let last_user = messages.iter().rev().find(|m| m.role == "user");
+            self.prompts
+                .lock()
+                .push(last_user.map(|m| m.content.clone()).unwrap_or_default());
+            let calls = self.prompts.lock().len();
+            let Some(_) = tools else {
+                return Ok(crate::providers::LlmResponse {
+                    content: "Listed two folders, one left.".to_string(),
+                    tool_calls: vec![],
+                    finish_reason: Some("stop".to_string()),
+                    usage: None,
+                });
+            };
+            Ok(crate::providers::LlmResponse {
+                content: String::new(),
+                tool_calls: exec_call(&format!("call_{calls}")).tool_calls,
+                finish_reason: Some("tool_calls".to_string()),
+                usage: None,
+            })
+        }
+    }
+    #[tokio::test]
+    async fn tool_policy_hides_and_refuses_denied_tools() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        cfg.agents.defaults.max_tool_iterations = 1;
+        cfg.tools.permissions.default_role = "guest".to_string();
+        cfg.tools
+            .permissions
+            .rules
+            .push(crate::config::ToolPermissionRule {
+                role: "owner".to_string(),
+                sender: Some("owner".to_string()),
+                ..Default::default()
+            });
+        let bus = Arc::new(MessageBus::new());
+        let agent = AgentLoop::new(&cfg, &bus, Arc::new(ToolHappyProvider::default()));
+        let opts = |sender: &str| ProcessOptions {
+            channel: "telegram".to_string(),
+            chat_id: "1".to_string(),
+            sender_id: sender.to_string(),
+            ..ProcessOptions::default()
+        };
+        let offered = |sender: &str| {
+            let mut names: Vec<String> = agent
+                .tools
+                .lock()
+                .to_provider_defs(&agent.tool_permissions(&opts(sender)))
+                .into_iter()
+                .map(|d| d.function.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(offered("guest"), ["web_fetch", "web_search"]);
+        assert!(offered("owner").contains(&"exec".to_string()));
+        // The provider calls exec even though it wasn't offered.
+        agent
+            .process_message(InboundMessage {
+                channel: "telegram".to_string(),
+                sender_id: "guest".to_string(),
+                chat_id: "1".to_string(),
+                content: "list files".to_string(),
+                media: None,
+                session_key: "telegram:1".to_string(),
+                metadata: None,
+            })
+            .await
+            .expect("turn");
+        let history = agent.sessions.get_history("telegram:1");
+        let result = history.iter().find(|m| m.role == "tool").expect("result");
+        assert_eq!(
+            result.content,
+            "Tool 'exec' is not allowed for role 'guest' in this chat"
+        );
+    }
+    #[tokio::test]
+    async fn iteration_limit_wraps_up_and_continue_resumes() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        cfg.agents.defaults.max_tool_iterations = 2;
+        let bus = Arc::new(MessageBus::new());
+        let provider = Arc::new(ToolHappyProvider::default());
+        let agent = AgentLoop::new(&cfg, &bus, provider.clone());
+        let inbound = |content: &str| InboundMessage {
+            channel: "cli".to_string(),
+            sender_id: "cli".to_string(),
+            chat_id: "direct".to_string(),
+            content: content.to_string(),
+            media: None,
+            session_key: "cli:limit".to_string(),
+            metadata: None,
+        };
+        let response = agent
+            .process_message(inbound("/continue"))
+            .await
+            .expect("continue");
+        assert!(response.starts_with("Nothing to continue"));
+        let response = agent
+            .process_message(inbound("list all folders"))
+            .await
+            .expect("turn");
+        assert!(response.starts_with("Listed two folders, one left."));
+        assert!(response.contains("Stopped after 2 tool iterations"));
+        assert!(response.contains("/continue"));
+        assert_eq!(
+            provider.prompts.lock().last().map(String::as_str),
+            Some(WRAP_UP_PROMPT)
+        );
+        let history = agent.sessions.get_history("cli:limit");
+        assert!(history.iter().all(|m| m.content != WRAP_UP_PROMPT));
+        assert!(agent.sessions.is_interrupted("cli:limit"));
+        provider.prompts.lock().clear();
+        agent
+            .process_message(inbound("/continue"))
+            .await
+            .expect("continue");
+        assert_eq!(provider.prompts.lock()[0], CONTINUE_PROMPT);
+        assert_eq!(provider.prompts.lock().len(), 3);
+    }
+    #[tokio::test]
+    async fn undo_retry_and_new_work_on_whole_turns() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        cfg.agents.defaults.max_tool_iterations = 2;
+        let bus = Arc::new(MessageBus::new());
+        let provider = Arc::new(ToolHappyProvider::default());
+        let agent = AgentLoop::new(&cfg, &bus, provider.clone());
+        let inbound = |content: &str| InboundMessage {
+            channel: "cli".to_string(),
+            sender_id: "cli".to_string(),
+            chat_id: "direct".to_string(),
+            content: content.to_string(),
+            media: None,
+            session_key: "cli:turns".to_string(),
+            metadata: None,
+        };
+        for content in ["first task", "second task"] {
+            agent.process_message(inbound(content)).await.expect("turn");
+        }
+        let first_turn = agent.sessions.get_history("cli:turns").len() / 2;
+        let response = agent.process_message(inbound("/undo")).await.expect("undo");
+        assert_eq!(response, "↩️ Removed the last exchange: \"second task\"");
+        let history = agent.sessions.get_history("cli:turns");
+        assert_eq!(history.len(), first_turn);
+        assert!(!agent.sessions.is_interrupted("cli:turns"));
+        provider.prompts.lock().clear();
+        agent
+            .process_message(inbound("/retry"))
+            .await
+            .expect("retry");
+        assert_eq!(provider.prompts.lock()[0], "first task");
+        let history = agent.sessions.get_history("cli:turns");
+        assert_eq!(history.len(), first_turn);
+        assert_eq!(history.iter().filter(|m| m.role == "user").count(), 1);
+        let response = agent.process_message(inbound("/new")).await.expect("new");
+        assert!(response.contains("archived"));
+        assert!(agent.sessions.get_history("cli:turns").is_empty());
+        let sessions_dir = tmp.path().join("sessions");
+        assert!(!sessions_dir.join("cli_turns.json").exists());
+        assert_eq!(
+            std::fs::read_dir(sessions_dir.join("archive"))
+                .expect("archive")
+                .count(),
+            1
+        );
+        for (command, reply) in [
+            ("/new", "This conversation is already empty."),
+            ("/undo", "Nothing to undo."),
+            ("/retry", "Nothing to retry."),
+        ] {
+            let response = agent
+                .process_message(inbound(command))
+                .await
+                .expect(command);
+            assert_eq!(response, reply);
+        }
+    }
+    #[tokio::test]
+    async fn forget_deletes_the_chat_and_what_derives_from_it() {
+        let tmp = TempDir::new().expect("tempdir");
+        let mut cfg = Config::default();
+        cfg.agents.defaults.workspace = tmp.path().to_string_lossy().to_string();
+        let bus = Arc::new(MessageBus::new());
+        let agent = AgentLoop::new(&cfg, &bus, Arc::new(ToolHappyProvider::default()));
+        let chat_id = format!("forget-{}", std::process::id());
+        let session_key = format!("telegram:{}", chat_id);
+        let inbound = |content: &str| InboundMessage {
+            channel: "telegram".to_string(),
+            sender_id: "7".to_string(),
+            chat_id: chat_id.clone(),
+            content: content.to_string(),
+            media: None,
+            session_key: session_key.clone(),
+            metadata: None,
+        };
+        for key in [session_key.clone(), format!("system:telegram:{}", chat_id)] {
+            agent.sessions.add_message(&key, "user", "hi");
+            agent.sessions.save(&key).expect("save");
+        }
+        agent.process_message(inbound("/new")).await.expect("new");
+        agent.sessions.add_message(&session_key, "user", "again");
+        agent.sessions.save(&session_key).expect("save");
+        let media = crate::media::media_dir("telegram", &chat_id);
+        std::fs::create_dir_all(&media).expect("media dir");
+        std::fs::write(media.join("photo.jpg"), "x").expect("media");
+        let traced = |key: &str| {
+            let turn = agent.traces.start_turn();
+            turn.record(TraceEvent::TurnStart {
+                session_key: key.to_string(),
+                channel: "telegram".to_string(),
+                chat_id: chat_id.clone(),
+                sender_id: "7".to_string(),
+                provider: "test".to_string(),
+                model: "m".to_string(),
+                user_message: "hi".to_string(),
+                system_prompt_hash: String::new(),
+            });
+            turn.id
+        };
+        traced(&session_key);
+        let other = traced("telegram:someone-else");
+        let response = agent
+            .process_message(inbound("/forget"))
+            .await
+            .expect("forget");
+        assert_eq!(
+            response,
+            "🧹 Forgot this chat: deleted 2 sessions, 1 archived conversation, 1 trace, 1 media file."
+        );
+        assert!(agent.sessions.list().expect("list").is_empty());
+        assert_eq!(crate::trace::recent_turns(tmp.path(), 10), [other]);
+        assert!(!media.exists());
+        let response = agent
+            .process_message(inbound("/forget"))
+            .await
+            .expect("forget");
+        assert_eq!(response, "Nothing stored for this chat.");
+    }
 }
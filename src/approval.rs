@@ -0,0 +1,288 @@
+use crate::bus::{ApprovalPrompt, MessageBus, OutboundKind, OutboundMessage};
+use crate::constants;
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Approve,
+    /// Approve, and stop asking for this command prefix in this chat.
+    ApproveAlways,
+    Deny,
+    TimedOut,
+}
+pub struct ApprovalRequest {
+    pub id: String,
+    pub command: String,
+    pub reason: String,
+    /// Prefix an "always" answer remembers; `None` when it isn't offered.
+    pub remember: Option<String>,
+}
+/// Asks the user of a channel directly instead of through the message bus
+/// (the CLI prompt).
+#[async_trait]
+pub trait Prompter: Send + Sync {
+    /// `None` when no answer can be read anymore.
+    async fn ask(&self, request: &ApprovalRequest) -> Option<Decision>;
+}
+struct Pending {
+    chat: String,
+    reply: oneshot::Sender<Decision>,
+}
+/// Routes approval requests from tools to the user of the chat the turn
+/// belongs to, and their answers (`/approve`, `/deny`) back.
+pub struct ApprovalBroker {
+    bus: Arc<MessageBus>,
+    pending: Mutex<HashMap<String, Pending>>,
+    /// Approved command prefixes by `channel:chat_id`; kept until restart.
+    remembered: Mutex<HashMap<String, HashSet<String>>>,
+    prompters: RwLock<HashMap<String, Arc<dyn Prompter>>>,
+}
+/// Removes a request from `pending` even when the waiting turn is cancelled.
+struct PendingGuard<'a> {
+    broker: &'a ApprovalBroker,
+    id: String,
+}
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.broker.pending.lock().remove(&self.id);
+    }
+}
+fn chat_key(channel: &str, chat_id: &str) -> String {
+    format!("{}:{}", channel, chat_id)
+}
+impl ApprovalBroker {
+    pub fn new(bus: Arc<MessageBus>) -> Self {
+        Self {
+            bus,
+            pending: Mutex::new(HashMap::new()),
+            remembered: Mutex::new(HashMap::new()),
+            prompters: RwLock::new(HashMap::new()),
+        }
+    }
+    pub fn set_prompter(&self, channel: &str, prompter: Arc<dyn Prompter>) {
+        self.prompters.write().insert(channel.to_string(), prompter);
+    }
+    /// Whether someone can answer for `channel`. Cron, heartbeat and other
+    /// internal turns have nobody to ask.
+    pub fn can_ask(&self, channel: &str) -> bool {
+        self.prompters.read().contains_key(channel) || !constants::is_internal_channel(channel)
+    }
+    pub fn is_remembered(&self, channel: &str, chat_id: &str, prefix: &str) -> bool {
+        self.remembered
+            .lock()
+            .get(&chat_key(channel, chat_id))
+            .is_some_and(|prefixes| prefixes.contains(prefix))
+    }
+    /// Asks the chat's user and waits up to `timeout` for the answer.
+    pub async fn request(
+        &self,
+        channel: &str,
+        chat_id: &str,
+        command: &str,
+        reason: &str,
+        remember: Option<String>,
+        timeout: Duration,
+    ) -> Decision {
+        let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+        let request = ApprovalRequest {
+            id: id.clone(),
+            command: command.to_string(),
+            reason: reason.to_string(),
+            remember,
+        };
+        let prompter = self.prompters.read().get(channel).cloned();
+        let decision = match prompter {
+            Some(prompter) => tokio::time::timeout(timeout, prompter.ask(&request))
+                .await
+                .unwrap_or(Some(Decision::TimedOut))
+                .unwrap_or(Decision::Deny),
+            None => self.ask_over_bus(channel, chat_id, &request, timeout).await,
+        };
+        if decision == Decision::ApproveAlways
+            && let Some(prefix) = request.remember
+        {
+            self.remembered
+                .lock()
+                .entry(chat_key(channel, chat_id))
+                .or_default()
+                .insert(prefix);
+        }
+        tracing::info!(
+            "exec approval {} in {}:{}: {:?}",
+            id,
+            channel,
+            chat_id,
+            decision
+        );
+        decision
+    }
+    async fn ask_over_bus(
+        &self,
+        channel: &str,
+        chat_id: &str,
+        request: &ApprovalRequest,
+        timeout: Duration,
+    ) -> Decision {
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().insert(
+            request.id.clone(),
+            Pending {
+                chat: chat_key(channel, chat_id),
+                reply: tx,
+            },
+        );
+        let _guard = PendingGuard {
+            broker: self,
+            id: request.id.clone(),
+        };
+        let prompt = OutboundMessage {
+            channel: channel.to_string(),
+            chat_id: chat_id.to_string(),
+            content: format!(
+                "⚠️ Approval needed: {}\n`{}`\nReply /approve {} or /deny {}; no answer within {}s counts as deny.",
+                request.reason,
+                request.command,
+                request.id,
+                request.id,
+                timeout.as_secs()
+            ),
+            kind: OutboundKind::Approval(ApprovalPrompt {
+                id: request.id.clone(),
+                remember: request.remember.clone(),
+            }),
+        };
+        if let Err(err) = self.bus.publish_outbound(prompt).await {
+            tracing::warn!("failed to send approval request: {}", err);
+            return Decision::Deny;
+        }
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(decision)) => decision,
+            Ok(Err(_)) => Decision::Deny,
+            Err(_) => Decision::TimedOut,
+        }
+    }
+    /// Delivers an answer from `channel:chat_id`. Only the chat that was
+    /// asked can answer.
+    pub fn resolve(&self, channel: &str, chat_id: &str, id: &str, decision: Decision) -> bool {
+        let mut pending = self.pending.lock();
+        if pending
+            .get(id)
+            .is_none_or(|p| p.chat != chat_key(channel, chat_id))
+        {
+            return false;
+        }
+        pending
+            .remove(id)
+            .is_some_and(|p| p.reply.send(decision).is_ok())
+    }
+}
+/// Prefix an "always" approval of `command` would cover: the program, plus
+/// the subcommand for tools like `git` or `docker`. Chained, piped,
+/// redirected, multi-line or grouped commands are never remembered. Takes
+/// the command as the model sent it, since normalising hides line breaks.
+pub fn remember_prefix(command: &str) -> Option<String> {
+    const SUBCOMMAND_TOOLS: &[&str] = &[
+        "apt",
+        "brew",
+        "cargo",
+        "docker",
+        "git",
+        "go",
+        "kubectl",
+        "npm",
+        "pip",
+        "pnpm",
+        "systemctl",
+        "yarn",
+    ];
+    if command.chars().any(|c| {
+        matches!(
+            c,
+            ';' | '|' | '&' | '>' | '<' | '`' | '$' | '\n' | '\r' | '(' | '{'
+        )
+    }) {
+        return None;
+    }
+    let command = command.to_ascii_lowercase();
+    let mut words = command.split_whitespace();
+    let program = words.next()?;
+    match words.next() {
+        Some(sub) if SUBCOMMAND_TOOLS.contains(&program) && !sub.starts_with('-') => {
+            Some(format!("{} {}", program, sub))
+        }
+        _ => Some(program.to_string()),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn answers_only_count_from_the_asked_chat() {
+        let bus = Arc::new(MessageBus::new());
+        let mut outbound = bus.take_outbound_receiver().expect("outbound");
+        let broker = Arc::new(ApprovalBroker::new(bus.clone()));
+        let waiting = tokio::spawn({
+            let broker = broker.clone();
+            async move {
+                broker
+                    .request(
+                        "telegram",
+                        "1",
+                        "git push",
+                        "needs approval",
+                        remember_prefix("git push origin"),
+                        Duration::from_secs(5),
+                    )
+                    .await
+            }
+        });
+        let prompt = outbound.recv().await.expect("prompt");
+        let OutboundKind::Approval(approval) = prompt.kind else {
+            panic!("expected an approval prompt");
+        };
+        assert_eq!(approval.remember.as_deref(), Some("git push"));
+        assert!(!broker.resolve("telegram", "2", &approval.id, Decision::Approve));
+        assert!(broker.resolve("telegram", "1", &approval.id, Decision::ApproveAlways));
+        assert_eq!(waiting.await.expect("join"), Decision::ApproveAlways);
+        assert!(broker.is_remembered("telegram", "1", "git push"));
+        assert!(!broker.is_remembered("telegram", "2", "git push"));
+        assert!(broker.pending.lock().is_empty());
+        assert_eq!(remember_prefix("ls -la > out.txt"), None);
+        assert_eq!(remember_prefix("rm notes.txt").as_deref(), Some("rm"));
+        assert_eq!(
+            remember_prefix("Git  Push origin").as_deref(),
+            Some("git push")
+        );
+    }
+    #[test]
+    fn multi_line_and_grouped_commands_are_never_remembered() {
+        assert_eq!(remember_prefix("git status\nrm -r ~/data"), None);
+        assert_eq!(remember_prefix("git status\r\nreboot"), None);
+        assert_eq!(remember_prefix("(rm -r ~/data)"), None);
+        assert_eq!(remember_prefix("{ reboot; }"), None);
+        assert_eq!(remember_prefix("echo `reboot`"), None);
+    }
+    #[tokio::test]
+    async fn unanswered_requests_time_out() {
+        let bus = Arc::new(MessageBus::new());
+        let _outbound = bus.take_outbound_receiver().expect("outbound");
+        let broker = ApprovalBroker::new(bus);
+        let decision = broker
+            .request(
+                "telegram",
+                "1",
+                "rm x",
+                "why",
+                None,
+                Duration::from_millis(50),
+            )
+            .await;
+        assert_eq!(decision, Decision::TimedOut);
+        assert!(broker.pending.lock().is_empty());
+        assert!(!broker.can_ask("cron"));
+    }
+}
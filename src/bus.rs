@@ -16,11 +16,29 @@ pub struct InboundMessage {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub metadata: Option<HashMap<String, String>>,
 }
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboundKind {
+    #[default]
+    Final,
+    Partial,
+    /// Asks the user to approve an action; the channel offers the choices.
+    Approval(ApprovalPrompt),
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalPrompt {
+    pub id: String,
+    /// Command prefix an "always" answer would remember, if offered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remember: Option<String>,
+}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutboundMessage {
     pub channel: String,
     pub chat_id: String,
     pub content: String,
+    #[serde(default)]
+    pub kind: OutboundKind,
 }
 #[derive(Debug, Error)]
 pub enum BusError {
@@ -80,6 +98,15 @@ impl MessageBus {
             .await
             .map_err(|_| BusError::OutboundSendFailed)
     }
+    pub fn try_publish_outbound(&self, msg: OutboundMessage) -> Result<(), BusError> {
+        if *self.inner.closed.read() {
+            return Err(BusError::Closed);
+        }
+        self.inner
+            .outbound_tx
+            .try_send(msg)
+            .map_err(|_| BusError::OutboundSendFailed)
+    }
     pub fn take_inbound_receiver(&self) -> Result<mpsc::Receiver<InboundMessage>, BusError> {
         self.inner
             .inbound_rx
@@ -122,6 +149,7 @@ mod tests {
             channel: "test".to_string(),
             chat_id: "chat".to_string(),
             content: "world".to_string(),
+            kind: OutboundKind::Final,
         }
     }
     #[tokio::test]
@@ -162,4 +190,14 @@ mod tests {
             .expect_err("second take should fail");
         assert!(matches!(err, BusError::OutboundReceiverTaken));
     }
+    #[tokio::test]
+    async fn try_publish_outbound_delivers_partial() {
+        let bus = MessageBus::new();
+        let mut rx = bus.take_outbound_receiver().expect("receiver should exist");
+        let mut msg = outbound();
+        msg.kind = OutboundKind::Partial;
+        bus.try_publish_outbound(msg).expect("send should succeed");
+        let got = rx.recv().await.expect("message should arrive");
+        assert_eq!(got.kind, OutboundKind::Partial);
+    }
 }
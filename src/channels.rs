@@ -1,4 +1,4 @@
-use crate::bus::{InboundMessage, MessageBus, OutboundMessage};
+use crate::bus::{ApprovalPrompt, InboundMessage, MessageBus, OutboundKind, OutboundMessage};
 use crate::config::Config;
 use crate::voice::GroqTranscriber;
 use anyhow::{Result, anyhow};
@@ -15,6 +15,9 @@ pub trait Channel: Send + Sync {
     async fn start(&self) -> Result<()>;
     async fn stop(&self) -> Result<()>;
     async fn send(&self, msg: &OutboundMessage) -> Result<()>;
+    fn supports_streaming(&self) -> bool {
+        false
+    }
 }
 #[derive(Debug)]
 struct BaseChannel {
@@ -42,7 +45,7 @@ fn split_compound_sender(sender: &str) -> (&str, &str) {
         (sender, "")
     }
 }
-fn is_allowed_sender(allow_list: &[String], sender_id: &str) -> bool {
+pub(crate) fn is_allowed_sender(allow_list: &[String], sender_id: &str) -> bool {
     if allow_list.is_empty() {
         return true;
     }
@@ -71,7 +74,9 @@ struct TelegramChannel {
     client: Client,
     transcriber: Option<GroqTranscriber>,
     task: Mutex<Option<JoinHandle<()>>>,
+    streams: Mutex<HashMap<String, i64>>,
 }
+const TELEGRAM_MAX_MESSAGE_CHARS: usize = 4096;
 impl TelegramChannel {
     fn new(cfg: &Config, bus: Arc<MessageBus>) -> Result<Self> {
         let telegram = &cfg.channels.telegram;
@@ -91,13 +96,16 @@ impl TelegramChannel {
                 "telegram allow_from is required for private mode (add your user id/username)"
             ));
         }
+        let client = crate::http::build_client(Some(&telegram.proxy), &telegram.http)
+            .map_err(|err| anyhow!("telegram: {}", err))?;
         Ok(Self {
             base: BaseChannel::new(allow_list),
             token: telegram.token.clone(),
             bus,
-            client: Client::new(),
+            client,
             transcriber: resolve_transcriber(cfg),
             task: Mutex::new(None),
+            streams: Mutex::new(HashMap::new()),
         })
     }
     fn api_url(&self, method: &str) -> String {
@@ -109,7 +117,7 @@ impl TelegramChannel {
         chat_id: &str,
         text: &str,
         parse_mode: Option<&str>,
-    ) -> Result<()> {
+    ) -> Result<Option<i64>> {
         let mut payload = serde_json::json!({
             "chat_id": chat_id,
             "text": text,
@@ -122,9 +130,163 @@ impl TelegramChannel {
             let body = resp.text().await.unwrap_or_default();
             return Err(anyhow!("telegram sendMessage failed: {}", body));
         }
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        Ok(body["result"]["message_id"].as_i64())
+    }
+    async fn edit_message_impl(
+        client: &Client,
+        url: &str,
+        chat_id: &str,
+        message_id: i64,
+        text: &str,
+        parse_mode: Option<&str>,
+    ) -> Result<()> {
+        let mut payload = serde_json::json!({
+            "chat_id": chat_id,
+            "message_id": message_id,
+            "text": text,
+        });
+        if let Some(mode) = parse_mode {
+            payload["parse_mode"] = serde_json::json!(mode);
+        }
+        let resp = client.post(url).json(&payload).send().await?;
+        if !resp.status().is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            if body.contains("message is not modified") {
+                return Ok(());
+            }
+            return Err(anyhow!("telegram editMessageText failed: {}", body));
+        }
+        Ok(())
+    }
+    async fn send_final(&self, msg: &OutboundMessage) -> Result<()> {
+        let html = markdown_to_telegram_html(&msg.content);
+        let send_url = self.api_url("sendMessage");
+        if let Err(err) =
+            Self::send_message_impl(&self.client, &send_url, &msg.chat_id, &html, Some("HTML"))
+                .await
+        {
+            tracing::warn!("telegram html send failed, fallback to plain text: {}", err);
+            Self::send_message_impl(&self.client, &send_url, &msg.chat_id, &msg.content, None)
+                .await?;
+        }
+        Ok(())
+    }
+    /// Sends an approval request with Approve / Always / Deny buttons.
+    async fn send_approval(&self, msg: &OutboundMessage, prompt: &ApprovalPrompt) -> Result<()> {
+        let mut row = vec![serde_json::json!({
+            "text": "✅ Approve",
+            "callback_data": format!("approval:{}:approve", prompt.id),
+        })];
+        if let Some(prefix) = &prompt.remember {
+            row.push(serde_json::json!({
+                "text": format!("✅ Always: {}", prefix),
+                "callback_data": format!("approval:{}:always", prompt.id),
+            }));
+        }
+        row.push(serde_json::json!({
+            "text": "❌ Deny",
+            "callback_data": format!("approval:{}:deny", prompt.id),
+        }));
+        let payload = serde_json::json!({
+            "chat_id": msg.chat_id,
+            "text": markdown_to_telegram_html(&msg.content),
+            "parse_mode": "HTML",
+            "reply_markup": { "inline_keyboard": [row] },
+        });
+        let resp = self
+            .client
+            .post(self.api_url("sendMessage"))
+            .json(&payload)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            tracing::warn!("telegram approval buttons failed, sending text: {}", body);
+            return self.send_final(msg).await;
+        }
+        Ok(())
+    }
+    async fn send_partial(&self, msg: &OutboundMessage) -> Result<()> {
+        let text = truncate_for_telegram(&msg.content);
+        let existing = self.streams.lock().get(&msg.chat_id).copied();
+        if let Some(message_id) = existing {
+            let edit_url = self.api_url("editMessageText");
+            if let Err(err) = Self::edit_message_impl(
+                &self.client,
+                &edit_url,
+                &msg.chat_id,
+                message_id,
+                &text,
+                None,
+            )
+            .await
+            {
+                tracing::debug!("telegram partial edit failed: {}", err);
+            }
+            return Ok(());
+        }
+        let send_url = self.api_url("sendMessage");
+        if let Some(message_id) =
+            Self::send_message_impl(&self.client, &send_url, &msg.chat_id, &text, None).await?
+        {
+            self.streams.lock().insert(msg.chat_id.clone(), message_id);
+        }
+        Ok(())
+    }
+    async fn finish_stream(&self, msg: &OutboundMessage, message_id: i64) -> Result<()> {
+        if msg.content.trim().is_empty() {
+            let payload = serde_json::json!({
+                "chat_id": msg.chat_id,
+                "message_id": message_id,
+            });
+            self.client
+                .post(self.api_url("deleteMessage"))
+                .json(&payload)
+                .send()
+                .await?;
+            return Ok(());
+        }
+        if msg.content.chars().count() > TELEGRAM_MAX_MESSAGE_CHARS {
+            return self.send_final(msg).await;
+        }
+        let edit_url = self.api_url("editMessageText");
+        let html = markdown_to_telegram_html(&msg.content);
+        if let Err(err) = Self::edit_message_impl(
+            &self.client,
+            &edit_url,
+            &msg.chat_id,
+            message_id,
+            &html,
+            Some("HTML"),
+        )
+        .await
+        {
+            tracing::warn!("telegram html edit failed, fallback to plain text: {}", err);
+            if Self::edit_message_impl(
+                &self.client,
+                &edit_url,
+                &msg.chat_id,
+                message_id,
+                &msg.content,
+                None,
+            )
+            .await
+            .is_err()
+            {
+                return self.send_final(msg).await;
+            }
+        }
         Ok(())
     }
 }
+fn truncate_for_telegram(text: &str) -> String {
+    if text.chars().count() <= TELEGRAM_MAX_MESSAGE_CHARS {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(TELEGRAM_MAX_MESSAGE_CHARS - 1).collect();
+    format!("{kept}…")
+}
 #[async_trait]
 impl Channel for TelegramChannel {
     async fn start(&self) -> Result<()> {
@@ -156,6 +318,17 @@ impl Channel for TelegramChannel {
                         {
                             for update in body.result {
                                 offset = update.update_id + 1;
+                                if let Some(query) = update.callback_query {
+                                    handle_callback_query(
+                                        &client,
+                                        &token,
+                                        &allow_list,
+                                        &bus,
+                                        query,
+                                    )
+                                    .await;
+                                    continue;
+                                }
                                 if let Some(msg) = update.message {
                                     let sender_id = build_sender_id(&msg);
                                     if !is_allowed_sender(&allow_list, &sender_id) {
@@ -211,17 +384,20 @@ impl Channel for TelegramChannel {
         Ok(())
     }
     async fn send(&self, msg: &OutboundMessage) -> Result<()> {
-        let html = markdown_to_telegram_html(&msg.content);
-        let send_url = self.api_url("sendMessage");
-        if let Err(err) =
-            Self::send_message_impl(&self.client, &send_url, &msg.chat_id, &html, Some("HTML"))
-                .await
-        {
-            tracing::warn!("telegram html send failed, fallback to plain text: {}", err);
-            Self::send_message_impl(&self.client, &send_url, &msg.chat_id, &msg.content, None)
-                .await?;
+        match &msg.kind {
+            OutboundKind::Partial => return self.send_partial(msg).await,
+            OutboundKind::Approval(prompt) => return self.send_approval(msg, prompt).await,
+            OutboundKind::Final => {}
         }
-        Ok(())
+        let streamed = self.streams.lock().remove(&msg.chat_id);
+        match streamed {
+            Some(message_id) => self.finish_stream(msg, message_id).await,
+            None if msg.content.is_empty() => Ok(()),
+            None => self.send_final(msg).await,
+        }
+    }
+    fn supports_streaming(&self) -> bool {
+        true
     }
 }
 #[derive(Deserialize)]
@@ -233,6 +409,19 @@ struct TelegramGetUpdatesResponse {
 struct TelegramUpdate {
     update_id: i64,
     message: Option<TelegramMessage>,
+    callback_query: Option<TelegramCallbackQuery>,
+}
+#[derive(Deserialize)]
+struct TelegramCallbackQuery {
+    id: String,
+    from: TelegramUser,
+    message: Option<TelegramCallbackMessage>,
+    data: Option<String>,
+}
+#[derive(Deserialize)]
+struct TelegramCallbackMessage {
+    message_id: i64,
+    chat: TelegramChat,
 }
 #[derive(Deserialize)]
 struct TelegramMessage {
@@ -244,6 +433,8 @@ struct TelegramMessage {
     audio: Option<TelegramAudio>,
     video_note: Option<serde_json::Value>,
     document: Option<TelegramDocument>,
+    /// The same photo in increasing sizes.
+    photo: Option<Vec<TelegramPhotoSize>>,
 }
 #[derive(Deserialize)]
 struct TelegramChat {
@@ -265,6 +456,11 @@ struct TelegramAudio {
 #[derive(Deserialize)]
 struct TelegramDocument {
     file_id: String,
+    mime_type: Option<String>,
+}
+#[derive(Deserialize)]
+struct TelegramPhotoSize {
+    file_id: String,
 }
 #[derive(Deserialize)]
 struct TelegramGetFileResponse {
@@ -276,13 +472,82 @@ struct TelegramFile {
     file_path: Option<String>,
 }
 fn build_sender_id(msg: &TelegramMessage) -> String {
-    if let Some(from) = &msg.from {
-        if let Some(username) = &from.username {
-            return format!("{}|{}", from.id, username);
-        }
-        return from.id.to_string();
+    msg.from
+        .as_ref()
+        .map_or_else(|| "unknown".to_string(), user_sender_id)
+}
+fn user_sender_id(user: &TelegramUser) -> String {
+    match &user.username {
+        Some(username) => format!("{}|{}", user.id, username),
+        None => user.id.to_string(),
+    }
+}
+/// Chat command for an approval button's `callback_data`.
+fn approval_command(data: &str) -> Option<String> {
+    let (id, answer) = data.strip_prefix("approval:")?.split_once(':')?;
+    match answer {
+        "approve" => Some(format!("/approve {}", id)),
+        "always" => Some(format!("/approve {} always", id)),
+        "deny" => Some(format!("/deny {}", id)),
+        _ => None,
+    }
+}
+/// Turns an approval button press into the matching `/approve` or `/deny`
+/// message and removes the buttons so they can't be pressed twice.
+async fn handle_callback_query(
+    client: &Client,
+    token: &str,
+    allow_list: &[String],
+    bus: &MessageBus,
+    query: TelegramCallbackQuery,
+) {
+    let api = |method: &str| format!("https://api.telegram.org/bot{}/{}", token, method);
+    let sender_id = user_sender_id(&query.from);
+    let allowed = is_allowed_sender(allow_list, &sender_id);
+    let answer = serde_json::json!({ "callback_query_id": query.id });
+    if let Err(err) = client
+        .post(api("answerCallbackQuery"))
+        .json(&answer)
+        .send()
+        .await
+    {
+        tracing::debug!("telegram answerCallbackQuery failed: {}", err);
+    }
+    let (Some(message), Some(content)) = (
+        query.message,
+        query.data.as_deref().and_then(approval_command),
+    ) else {
+        return;
+    };
+    if !allowed {
+        return;
+    }
+    let chat_id = message.chat.id.to_string();
+    let clear = serde_json::json!({
+        "chat_id": chat_id,
+        "message_id": message.message_id,
+        "reply_markup": { "inline_keyboard": [] },
+    });
+    if let Err(err) = client
+        .post(api("editMessageReplyMarkup"))
+        .json(&clear)
+        .send()
+        .await
+    {
+        tracing::debug!("telegram editMessageReplyMarkup failed: {}", err);
+    }
+    let inbound = InboundMessage {
+        channel: "telegram".to_string(),
+        sender_id: query.from.id.to_string(),
+        chat_id: chat_id.clone(),
+        content,
+        session_key: format!("telegram:{}", chat_id),
+        media: None,
+        metadata: None,
+    };
+    if let Err(err) = bus.publish_inbound(inbound).await {
+        tracing::error!("failed to publish telegram approval answer: {}", err);
     }
-    "unknown".to_string()
 }
 async fn build_message_content_with_media(
     client: &Client,
@@ -292,6 +557,7 @@ async fn build_message_content_with_media(
 ) -> (String, Option<Vec<String>>) {
     let mut content = String::new();
     let mut media_paths = Vec::new();
+    let dir = crate::media::media_dir("telegram", &msg.chat.id.to_string());
     if let Some(text) = &msg.text {
         content.push_str(text);
     }
@@ -302,7 +568,8 @@ async fn build_message_content_with_media(
         content.push_str(caption);
     }
     if let Some(voice) = &msg.voice
-        && let Some(path) = download_telegram_file(client, token, &voice.file_id, ".ogg").await
+        && let Some(path) =
+            download_telegram_file(client, token, &voice.file_id, &dir, ".ogg").await
     {
         media_paths.push(path.clone());
         if !content.is_empty() {
@@ -326,7 +593,8 @@ async fn build_message_content_with_media(
         content.push_str(&voice_text);
     }
     if let Some(audio) = &msg.audio
-        && let Some(path) = download_telegram_file(client, token, &audio.file_id, ".mp3").await
+        && let Some(path) =
+            download_telegram_file(client, token, &audio.file_id, &dir, ".mp3").await
     {
         media_paths.push(path);
         if !content.is_empty() {
@@ -334,14 +602,31 @@ async fn build_message_content_with_media(
         }
         content.push_str("[audio]");
     }
+    if let Some(photo) = msg.photo.as_ref().and_then(|sizes| sizes.last())
+        && let Some(path) =
+            download_telegram_file(client, token, &photo.file_id, &dir, ".jpg").await
+    {
+        media_paths.push(path);
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content.push_str("[image]");
+    }
     if let Some(doc) = &msg.document
-        && let Some(path) = download_telegram_file(client, token, &doc.file_id, "").await
+        && let Some(path) = download_telegram_file(client, token, &doc.file_id, &dir, "").await
     {
+        // Images sent "as file" keep their extension, so they reach vision
+        // models like photos do.
+        let is_image = doc
+            .mime_type
+            .as_deref()
+            .is_some_and(|mime| mime.starts_with("image/"))
+            && crate::media::image_mime(&path).is_some();
         media_paths.push(path);
         if !content.is_empty() {
             content.push('\n');
         }
-        content.push_str("[file]");
+        content.push_str(if is_image { "[image]" } else { "[file]" });
     }
     if content.is_empty() {
         if msg.video_note.is_some() {
@@ -363,6 +648,7 @@ async fn download_telegram_file(
     client: &Client,
     token: &str,
     file_id: &str,
+    dir: &std::path::Path,
     ext: &str,
 ) -> Option<String> {
     let get_file_url = format!(
@@ -384,7 +670,8 @@ async fn download_telegram_file(
     if !ext.is_empty() && !name.ends_with(ext) {
         name.push_str(ext);
     }
-    let local_path = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(dir).ok()?;
+    let local_path = dir.join(name);
     std::fs::write(&local_path, bytes).ok()?;
     Some(local_path.to_string_lossy().to_string())
 }
@@ -400,7 +687,15 @@ fn resolve_transcriber(cfg: &Config) -> Option<GroqTranscriber> {
                 .ok()
                 .filter(|v| !v.trim().is_empty())
         });
-    key.map(GroqTranscriber::new)
+    let key = key?;
+    let groq = &cfg.providers.groq;
+    match crate::http::build_client(groq.proxy.as_deref(), &groq.http) {
+        Ok(client) => Some(GroqTranscriber::new(key, client)),
+        Err(err) => {
+            tracing::warn!("voice transcription disabled: {}", err);
+            None
+        }
+    }
 }
 fn markdown_to_telegram_html(input: &str) -> String {
     let mut out = String::with_capacity(input.len() * 2);
@@ -571,6 +866,11 @@ impl ChannelManager {
         names.sort();
         names
     }
+    pub fn supports_streaming(&self, channel: &str) -> bool {
+        self.channels
+            .get(channel)
+            .is_some_and(|c| c.supports_streaming())
+    }
     pub async fn start_all(&self) -> Result<()> {
         for channel in self.channels.values() {
             channel.start().await?;
@@ -580,6 +880,9 @@ impl ChannelManager {
         let task = tokio::spawn(async move {
             while let Some(msg) = out_rx.recv().await {
                 if let Some(channel) = channels.get(&msg.channel) {
+                    if msg.kind == OutboundKind::Partial && !channel.supports_streaming() {
+                        continue;
+                    }
                     if let Err(err) = channel.send(&msg).await {
                         tracing::error!(
                             "channel send failed: channel={} error={}",
@@ -684,4 +987,25 @@ mod tests {
             "See <a href=\"https://google.com\">Google</a> for more"
         );
     }
+    #[test]
+    fn telegram_partial_text_is_truncated_to_message_limit() {
+        let long = "a".repeat(TELEGRAM_MAX_MESSAGE_CHARS + 10);
+        let truncated = truncate_for_telegram(&long);
+        assert_eq!(truncated.chars().count(), TELEGRAM_MAX_MESSAGE_CHARS);
+        assert!(truncated.ends_with('…'));
+        assert_eq!(truncate_for_telegram("short"), "short");
+    }
+    #[test]
+    fn approval_buttons_map_to_chat_commands() {
+        assert_eq!(
+            approval_command("approval:ab12:always").as_deref(),
+            Some("/approve ab12 always")
+        );
+        assert_eq!(
+            approval_command("approval:ab12:deny").as_deref(),
+            Some("/deny ab12")
+        );
+        assert_eq!(approval_command("approval:ab12:maybe"), None);
+        assert_eq!(approval_command("other"), None);
+    }
 }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Config {
@@ -18,11 +19,366 @@ pub struct Config {
     pub heartbeat: HeartbeatConfig,
     #[serde(default)]
     pub devices: DevicesConfig,
+    #[serde(default)]
+    pub usage: UsageConfig,
+    #[serde(default)]
+    pub trace: TraceConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+}
+/// What the gateway deletes in the background, and how often it checks.
+/// Zero ages keep things forever.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Sessions (and their archived copies) idle for longer are deleted.
+    #[serde(default)]
+    pub session_max_age_days: u64,
+    /// Messages kept per session; the oldest whole turns go first.
+    #[serde(default = "default_session_max_messages")]
+    pub session_max_messages: usize,
+    /// Daily notes under `memory/` older than this are deleted.
+    #[serde(default)]
+    pub notes_max_age_days: u64,
+    /// Photos, voice messages and files downloaded from channels.
+    #[serde(default = "default_media_max_age_hours")]
+    pub media_max_age_hours: u64,
+    /// Turn traces under `traces/`, by the day the turn started.
+    #[serde(default = "default_trace_max_age_days")]
+    pub trace_max_age_days: u64,
+    #[serde(default = "default_retention_interval_minutes")]
+    pub interval_minutes: u64,
+}
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            session_max_age_days: 0,
+            session_max_messages: default_session_max_messages(),
+            notes_max_age_days: 0,
+            media_max_age_hours: default_media_max_age_hours(),
+            trace_max_age_days: default_trace_max_age_days(),
+            interval_minutes: default_retention_interval_minutes(),
+        }
+    }
+}
+fn default_session_max_messages() -> usize {
+    200
+}
+fn default_media_max_age_hours() -> u64 {
+    24
+}
+fn default_trace_max_age_days() -> u64 {
+    14
+}
+fn default_retention_interval_minutes() -> u64 {
+    60
+}
+/// How session histories under `<workspace>/sessions` are stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionsConfig {
+    /// `json` (a file per session, rewritten on every save) or `sqlite`
+    /// (`sessions.db`, only new messages are written).
+    #[serde(default = "default_session_store")]
+    pub store: String,
+    /// Sessions kept in memory; beyond this the least recently used idle
+    /// ones are unloaded until their next message.
+    #[serde(default = "default_session_cache_size")]
+    pub cache_size: usize,
+}
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            store: default_session_store(),
+            cache_size: default_session_cache_size(),
+        }
+    }
+}
+fn default_session_store() -> String {
+    "json".to_string()
+}
+fn default_session_cache_size() -> usize {
+    256
+}
+/// Per-turn JSONL traces under `<workspace>/traces`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Extra regexes masked in traces, on top of the config's secrets and
+    /// well-known API key formats.
+    #[serde(default)]
+    pub redact: Vec<String>,
+}
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            redact: Vec::new(),
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct UsageConfig {
+    /// USD prices keyed by model name (with or without a `provider/` prefix).
+    #[serde(default)]
+    pub prices: HashMap<String, ModelPrice>,
+    #[serde(default)]
+    pub budgets: BudgetsConfig,
+}
+/// Spending caps checked before every provider call. Periods are UTC days and
+/// months; cost limits only count calls whose model has a configured price.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BudgetsConfig {
+    /// Shared cap for all interactive turns.
+    #[serde(default)]
+    pub global: BudgetLimits,
+    /// Cap applied to each sender individually.
+    #[serde(default)]
+    pub per_sender: BudgetLimits,
+    /// Separate cap for heartbeat and cron turns.
+    #[serde(default)]
+    pub background: BudgetLimits,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BudgetLimits {
+    #[serde(default)]
+    pub daily_tokens: Option<u64>,
+    #[serde(default)]
+    pub monthly_tokens: Option<u64>,
+    #[serde(default)]
+    pub daily_cost_usd: Option<f64>,
+    #[serde(default)]
+    pub monthly_cost_usd: Option<f64>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub input_per_million: f64,
+    #[serde(default)]
+    pub output_per_million: f64,
+}
+impl ModelPrice {
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.input_per_million
+            + completion_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct AgentsConfig {
     #[serde(default)]
     pub defaults: AgentDefaults,
+    /// Generation overrides keyed by channel name (`telegram`, `cli`, ...).
+    #[serde(default)]
+    pub channels: HashMap<String, GenerationSettings>,
+    /// Named agents; chats not matched by `routes` use the defaults.
+    #[serde(default)]
+    pub profiles: HashMap<String, AgentProfile>,
+    #[serde(default)]
+    pub routes: Vec<AgentRoute>,
+}
+/// A named agent. Unset fields fall back to `agents.defaults` / `tools.exec`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AgentProfile {
+    /// Defaults to `<default workspace>/agents/<name>`.
+    #[serde(default)]
+    pub workspace: Option<String>,
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub restrict_to_workspace: Option<bool>,
+    /// Workspace files appended to the system prompt, replacing the default
+    /// `AGENTS.md`, `SOUL.md`, `USER.md`, `IDENTITY.md`.
+    #[serde(default)]
+    pub system_prompt_files: Vec<String>,
+    /// Tool allowlist; `None` keeps every tool.
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+    #[serde(default)]
+    pub exec: Option<ExecToolsConfig>,
+}
+/// Sends matching chats to `agent`. Unset fields match anything; the first
+/// matching route wins.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AgentRoute {
+    pub agent: String,
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub chat_id: Option<String>,
+    #[serde(default)]
+    pub sender: Option<String>,
+}
+impl AgentRoute {
+    fn matches(&self, channel: &str, chat_id: &str, sender: &str) -> bool {
+        let field = |want: &Option<String>, got: &str| want.as_deref().is_none_or(|w| w == got);
+        field(&self.channel, channel)
+            && field(&self.chat_id, chat_id)
+            && field(&self.sender, sender)
+    }
+}
+impl AgentsConfig {
+    /// Profile name for a chat, `None` for the default agent.
+    pub fn route(&self, channel: &str, chat_id: &str, sender: &str) -> Option<&str> {
+        self.routes
+            .iter()
+            .filter(|r| self.profiles.contains_key(&r.agent))
+            .find(|r| r.matches(channel, chat_id, sender))
+            .map(|r| r.agent.as_str())
+    }
+    /// Defaults with the channel's overrides applied.
+    pub fn generation_for_channel(&self, channel: &str) -> GenerationSettings {
+        let mut settings = self.defaults.generation();
+        if let Some(overrides) = self.channels.get(channel) {
+            settings.overlay(overrides);
+        }
+        settings
+    }
+}
+/// Generation parameters. Every field is optional so each layer (defaults,
+/// channel, session) only overrides what it sets.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tool_iterations: Option<i32>,
+}
+impl GenerationSettings {
+    pub const PARAMS: [&'static str; 6] = [
+        "temperature",
+        "max_tokens",
+        "top_p",
+        "stop",
+        "reasoning_effort",
+        "max_tool_iterations",
+    ];
+    /// Copies every field that is set in `other`.
+    pub fn overlay(&mut self, other: &GenerationSettings) {
+        if other.temperature.is_some() {
+            self.temperature = other.temperature;
+        }
+        if other.max_tokens.is_some() {
+            self.max_tokens = other.max_tokens;
+        }
+        if other.top_p.is_some() {
+            self.top_p = other.top_p;
+        }
+        if other.stop.is_some() {
+            self.stop = other.stop.clone();
+        }
+        if other.reasoning_effort.is_some() {
+            self.reasoning_effort = other.reasoning_effort.clone();
+        }
+        if other.max_tool_iterations.is_some() {
+            self.max_tool_iterations = other.max_tool_iterations;
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    /// Display value of `param`, `None` when unset or unknown.
+    pub fn get(&self, param: &str) -> Option<String> {
+        match param {
+            "temperature" => self.temperature.map(|v| v.to_string()),
+            "max_tokens" => self.max_tokens.map(|v| v.to_string()),
+            "top_p" => self.top_p.map(|v| v.to_string()),
+            "stop" => self.stop.as_ref().map(|v| v.join(", ")),
+            "reasoning_effort" => self.reasoning_effort.clone(),
+            "max_tool_iterations" => self.max_tool_iterations.map(|v| v.to_string()),
+            _ => None,
+        }
+    }
+    /// Parses and sets `param`; `None` clears it so the lower layer applies.
+    /// `stop` takes a comma-separated list.
+    pub fn set(&mut self, param: &str, value: Option<&str>) -> anyhow::Result<()> {
+        fn parse<T: std::str::FromStr>(param: &str, raw: &str) -> anyhow::Result<T> {
+            raw.parse()
+                .map_err(|_| anyhow::anyhow!("invalid value for {}: {}", param, raw))
+        }
+        let value = value.map(str::trim);
+        match param {
+            "temperature" => {
+                self.temperature = value.map(|v| parse::<f64>(param, v)).transpose()?;
+                if self.temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+                    anyhow::bail!("temperature must be between 0 and 2");
+                }
+            }
+            "max_tokens" => {
+                self.max_tokens = value.map(|v| parse::<i32>(param, v)).transpose()?;
+                if self.max_tokens.is_some_and(|t| t <= 0) {
+                    anyhow::bail!("max_tokens must be positive");
+                }
+            }
+            "top_p" => {
+                self.top_p = value.map(|v| parse::<f64>(param, v)).transpose()?;
+                if self.top_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+                    anyhow::bail!("top_p must be between 0 and 1");
+                }
+            }
+            "stop" => {
+                self.stop = value.map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                });
+            }
+            "reasoning_effort" => {
+                let effort = value.map(str::to_lowercase);
+                if let Some(effort) = effort.as_deref()
+                    && !matches!(effort, "minimal" | "low" | "medium" | "high")
+                {
+                    anyhow::bail!("reasoning_effort must be minimal, low, medium or high");
+                }
+                self.reasoning_effort = effort;
+            }
+            "max_tool_iterations" => {
+                self.max_tool_iterations = value.map(|v| parse::<i32>(param, v)).transpose()?;
+                if self.max_tool_iterations.is_some_and(|n| n <= 0) {
+                    anyhow::bail!("max_tool_iterations must be positive");
+                }
+            }
+            _ => anyhow::bail!(
+                "unknown setting '{}'; expected one of: {}",
+                param,
+                Self::PARAMS.join(", ")
+            ),
+        }
+        Ok(())
+    }
+    /// Provider request options (everything except `max_tool_iterations`).
+    pub fn to_options(&self) -> HashMap<String, serde_json::Value> {
+        let mut options = HashMap::new();
+        if let Some(v) = self.temperature {
+            options.insert("temperature".to_string(), serde_json::json!(v));
+        }
+        if let Some(v) = self.max_tokens {
+            options.insert("max_tokens".to_string(), serde_json::json!(v));
+        }
+        if let Some(v) = self.top_p {
+            options.insert("top_p".to_string(), serde_json::json!(v));
+        }
+        if let Some(v) = self.stop.as_ref().filter(|v| !v.is_empty()) {
+            options.insert("stop".to_string(), serde_json::json!(v));
+        }
+        if let Some(v) = self.reasoning_effort.as_ref() {
+            options.insert("reasoning_effort".to_string(), serde_json::json!(v));
+        }
+        options
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentDefaults {
@@ -40,6 +396,46 @@ pub struct AgentDefaults {
     pub temperature: f64,
     #[serde(default = "default_max_tool_iterations")]
     pub max_tool_iterations: i32,
+    #[serde(default = "default_true")]
+    pub streaming: bool,
+    #[serde(default)]
+    pub fallbacks: Vec<FallbackTarget>,
+    /// How many sessions the gateway processes at the same time.
+    #[serde(default = "default_max_concurrent_sessions")]
+    pub max_concurrent_sessions: usize,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    #[serde(default)]
+    pub reasoning_effort: Option<String>,
+    /// Context sizes in tokens by model name, for models the built-in table
+    /// doesn't know or gets wrong.
+    #[serde(default)]
+    pub context_windows: HashMap<String, usize>,
+    /// Whether a model accepts images, by model name, overriding the
+    /// built-in list.
+    #[serde(default)]
+    pub vision_models: HashMap<String, bool>,
+}
+impl AgentDefaults {
+    /// The configured generation parameters as the bottom settings layer.
+    pub fn generation(&self) -> GenerationSettings {
+        GenerationSettings {
+            temperature: Some(self.temperature),
+            max_tokens: Some(self.max_tokens),
+            top_p: self.top_p,
+            stop: (!self.stop.is_empty()).then(|| self.stop.clone()),
+            reasoning_effort: self.reasoning_effort.clone(),
+            max_tool_iterations: Some(self.max_tool_iterations),
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FallbackTarget {
+    pub provider: String,
+    #[serde(default)]
+    pub model: String,
 }
 impl Default for AgentDefaults {
     fn default() -> Self {
@@ -51,6 +447,14 @@ impl Default for AgentDefaults {
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
             max_tool_iterations: default_max_tool_iterations(),
+            streaming: true,
+            fallbacks: Vec::new(),
+            max_concurrent_sessions: default_max_concurrent_sessions(),
+            top_p: None,
+            stop: Vec::new(),
+            reasoning_effort: None,
+            context_windows: HashMap::new(),
+            vision_models: HashMap::new(),
         }
     }
 }
@@ -72,6 +476,9 @@ fn default_temperature() -> f64 {
 fn default_max_tool_iterations() -> i32 {
     20
 }
+fn default_max_concurrent_sessions() -> usize {
+    4
+}
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ChannelsConfig {
     #[serde(default)]
@@ -87,6 +494,19 @@ pub struct TelegramConfig {
     pub proxy: String,
     #[serde(default)]
     pub allow_from: Vec<String>,
+    #[serde(default)]
+    pub http: HttpClientConfig,
+}
+/// Timeouts and user agent for an outgoing HTTP client; unset values use
+/// the built-in defaults (30s without data, 10s connect).
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct HttpClientConfig {
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ProvidersConfig {
@@ -104,6 +524,9 @@ pub struct ProvidersConfig {
     pub gemini: ProviderConfig,
     #[serde(default)]
     pub deepseek: ProviderConfig,
+    /// Named OpenAI-compatible endpoints (Ollama, vLLM, LM Studio, ...).
+    #[serde(default)]
+    pub custom: HashMap<String, ProviderConfig>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ProviderConfig {
@@ -117,6 +540,41 @@ pub struct ProviderConfig {
     pub auth_method: Option<String>,
     #[serde(default)]
     pub connect_mode: Option<String>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub http: HttpClientConfig,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_retry_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_retry_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_retry_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_retry_max_retries(),
+            initial_backoff_ms: default_retry_initial_backoff_ms(),
+            max_backoff_ms: default_retry_max_backoff_ms(),
+        }
+    }
+}
+fn default_retry_max_retries() -> u32 {
+    3
+}
+fn default_retry_initial_backoff_ms() -> u64 {
+    500
+}
+fn default_retry_max_backoff_ms() -> u64 {
+    30_000
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GatewayConfig {
@@ -168,6 +626,8 @@ pub struct ToolsConfig {
     pub exec: ExecToolsConfig,
     #[serde(default = "default_tool_output_max_chars")]
     pub tool_output_max_chars: usize,
+    #[serde(default)]
+    pub permissions: ToolPermissionsConfig,
 }
 impl Default for ToolsConfig {
     fn default() -> Self {
@@ -175,9 +635,95 @@ impl Default for ToolsConfig {
             web: WebToolsConfig::default(),
             exec: ExecToolsConfig::default(),
             tool_output_max_chars: default_tool_output_max_chars(),
+            permissions: ToolPermissionsConfig::default(),
+        }
+    }
+}
+/// Which tools each chat and sender may use. The first rule matching a
+/// message picks its role; without a match `default_role` applies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolPermissionsConfig {
+    #[serde(default = "default_tool_role")]
+    pub default_role: String,
+    /// Custom roles; these also replace the built-in `owner`, `guest` and
+    /// `read_only` roles of the same name.
+    #[serde(default)]
+    pub roles: HashMap<String, ToolRole>,
+    #[serde(default)]
+    pub rules: Vec<ToolPermissionRule>,
+}
+impl Default for ToolPermissionsConfig {
+    fn default() -> Self {
+        Self {
+            default_role: default_tool_role(),
+            roles: HashMap::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+fn default_tool_role() -> String {
+    "owner".to_string()
+}
+impl ToolPermissionsConfig {
+    /// The role called `name`, configured or built in.
+    pub fn role(&self, name: &str) -> Option<ToolRole> {
+        if let Some(role) = self.roles.get(name) {
+            return Some(role.clone());
+        }
+        let allow = |tools: &[&str]| ToolRole {
+            allow: Some(tools.iter().map(|t| t.to_string()).collect()),
+            ..ToolRole::default()
+        };
+        match name {
+            "owner" => Some(ToolRole::default()),
+            "read_only" => Some(allow(&["read_file", "list_dir", "web_search", "web_fetch"])),
+            "guest" => Some(allow(&["web_search", "web_fetch"])),
+            _ => None,
         }
     }
 }
+/// What a role may use. The default allows every tool and command.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolRole {
+    /// Tools the role may use; unset allows all of them.
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Commands `exec` may run must start with one of these; unset leaves
+    /// commands to the `tools.exec` rules alone.
+    #[serde(default)]
+    pub exec_allow_prefixes: Option<Vec<String>>,
+    #[serde(default)]
+    pub exec_deny_prefixes: Vec<String>,
+}
+/// Gives matching messages `role`. Unset fields match anything.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ToolPermissionRule {
+    pub role: String,
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub chat_id: Option<String>,
+    #[serde(default)]
+    pub sender: Option<String>,
+}
+impl ToolPermissionRule {
+    /// Whether the rule applies to `channel` and `chat_id`, whoever the
+    /// sender is.
+    pub fn matches_chat(&self, channel: &str, chat_id: &str) -> bool {
+        let field = |want: &Option<String>, got: &str| want.as_deref().is_none_or(|w| w == got);
+        field(&self.channel, channel) && field(&self.chat_id, chat_id)
+    }
+    /// Like [`Self::matches_chat`], and `sender` matches the rule's sender
+    /// the way `allow_from` does, so `"42"` or `"@alice"` match `"42|alice"`.
+    pub fn matches(&self, channel: &str, chat_id: &str, sender: &str) -> bool {
+        self.matches_chat(channel, chat_id)
+            && self.sender.as_ref().is_none_or(|want| {
+                crate::channels::is_allowed_sender(std::slice::from_ref(want), sender)
+            })
+    }
+}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebToolsConfig {
     #[serde(default)]
@@ -190,6 +736,10 @@ pub struct WebToolsConfig {
     pub fetch_hard_max_chars: usize,
     #[serde(default = "default_web_fetch_hard_max_bytes")]
     pub fetch_hard_max_bytes: usize,
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub http: HttpClientConfig,
 }
 impl Default for WebToolsConfig {
     fn default() -> Self {
@@ -199,6 +749,8 @@ impl Default for WebToolsConfig {
             fetch_default_max_chars: default_web_fetch_default_max_chars(),
             fetch_hard_max_chars: default_web_fetch_hard_max_chars(),
             fetch_hard_max_bytes: default_web_fetch_hard_max_bytes(),
+            proxy: None,
+            http: HttpClientConfig::default(),
         }
     }
 }
@@ -249,6 +801,16 @@ pub struct ExecToolsConfig {
     pub stdout_max_bytes: usize,
     #[serde(default = "default_exec_stderr_max_bytes")]
     pub stderr_max_bytes: usize,
+    /// How long to wait for the user to approve a command.
+    #[serde(default = "default_exec_approval_timeout_secs")]
+    pub approval_timeout_secs: u64,
+    /// Offer "always" approvals, remembered per chat and command prefix.
+    #[serde(default)]
+    pub remember_approvals: bool,
+    /// Honor the model's `confirm` flag where no user can be asked (cron,
+    /// heartbeat). When off such commands are refused.
+    #[serde(default = "default_true")]
+    pub llm_confirm: bool,
 }
 impl Default for ExecToolsConfig {
     fn default() -> Self {
@@ -259,9 +821,15 @@ impl Default for ExecToolsConfig {
             always_deny_prefixes: default_exec_always_deny_prefixes(),
             stdout_max_bytes: default_exec_stdout_max_bytes(),
             stderr_max_bytes: default_exec_stderr_max_bytes(),
+            approval_timeout_secs: default_exec_approval_timeout_secs(),
+            remember_approvals: false,
+            llm_confirm: true,
         }
     }
 }
+fn default_exec_approval_timeout_secs() -> u64 {
+    120
+}
 fn default_exec_auto_allow_prefixes() -> Vec<String> {
     [
         "ls",
@@ -466,6 +1034,55 @@ impl Config {
     pub fn workspace_path(&self) -> PathBuf {
         expand_home(&self.agents.defaults.workspace)
     }
+    /// The default agent's config, named `default`, then each profile's (see
+    /// [`Self::for_profile`]) by name. Profiles sharing a workspace already
+    /// listed are left out, so every session store appears once.
+    pub fn agent_configs(&self) -> Vec<(String, Config)> {
+        let mut names: Vec<&String> = self.agents.profiles.keys().collect();
+        names.sort();
+        let mut configs = vec![("default".to_string(), self.clone())];
+        for name in names {
+            let Some(config) = self.for_profile(name) else {
+                continue;
+            };
+            let workspace = config.workspace_path();
+            if configs.iter().all(|(_, c)| c.workspace_path() != workspace) {
+                configs.push((name.clone(), config));
+            }
+        }
+        configs
+    }
+    /// The config as seen by profile `name`: its overrides applied to the
+    /// defaults, with no profiles or routes of its own.
+    pub fn for_profile(&self, name: &str) -> Option<Config> {
+        let profile = self.agents.profiles.get(name)?;
+        let mut config = self.clone();
+        config.agents.profiles.clear();
+        config.agents.routes.clear();
+        let defaults = &mut config.agents.defaults;
+        defaults.workspace = match profile.workspace.as_ref() {
+            Some(workspace) => workspace.clone(),
+            None => self
+                .workspace_path()
+                .join("agents")
+                .join(name)
+                .to_string_lossy()
+                .to_string(),
+        };
+        if let Some(provider) = profile.provider.as_ref() {
+            defaults.provider = provider.clone();
+        }
+        if let Some(model) = profile.model.as_ref() {
+            defaults.model = model.clone();
+        }
+        if let Some(restrict) = profile.restrict_to_workspace {
+            defaults.restrict_to_workspace = restrict;
+        }
+        if let Some(exec) = profile.exec.as_ref() {
+            config.tools.exec = exec.clone();
+        }
+        Some(config)
+    }
 }
 fn expand_home(path: &str) -> PathBuf {
     if path.starts_with('~')
@@ -496,7 +1113,16 @@ fn normalize_keys(value: serde_json::Value) -> serde_json::Value {
         serde_json::Value::Object(map) => {
             let normalized = map
                 .into_iter()
-                .map(|(k, v)| (camel_to_snake(&k), normalize_keys(v)))
+                .map(|(k, v)| {
+                    let key = camel_to_snake(&k);
+                    let value = match key.as_str() {
+                        // User-chosen names: keep them verbatim.
+                        "headers" | "context_windows" | "vision_models" => v,
+                        "custom" | "prices" | "profiles" | "roles" => normalize_named_entries(v),
+                        _ => normalize_keys(v),
+                    };
+                    (key, value)
+                })
                 .collect();
             serde_json::Value::Object(normalized)
         }
@@ -506,6 +1132,16 @@ fn normalize_keys(value: serde_json::Value) -> serde_json::Value {
         other => other,
     }
 }
+fn normalize_named_entries(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .map(|(name, v)| (name, normalize_keys(v)))
+                .collect(),
+        ),
+        other => normalize_keys(other),
+    }
+}
 fn camel_to_snake(input: &str) -> String {
     let mut out = String::with_capacity(input.len());
     let chars: Vec<char> = input.chars().collect();
@@ -558,6 +1194,38 @@ mod tests {
         );
     }
     #[test]
+    fn tool_role_names_are_kept_verbatim() {
+        let raw = r#"{
+            "tools": {
+                "permissions": {
+                    "defaultRole": "familyGuest",
+                    "roles": { "familyGuest": { "execAllowPrefixes": ["uptime"] } },
+                    "rules": [{ "role": "owner", "sender": "42" }]
+                }
+            }
+        }"#;
+        let parsed = parse_compat_json(raw).expect("parse");
+        let permissions = &parsed.tools.permissions;
+        let role = permissions.role(&permissions.default_role).expect("role");
+        assert_eq!(role.exec_allow_prefixes, Some(vec!["uptime".to_string()]));
+        assert!(permissions.role("read_only").is_some());
+        assert!(permissions.rules[0].matches("telegram", "1", "42"));
+    }
+    #[test]
+    fn tool_permission_rules_match_telegram_compound_senders() {
+        let rule = |sender: &str| ToolPermissionRule {
+            role: "owner".to_string(),
+            channel: Some("telegram".to_string()),
+            chat_id: None,
+            sender: Some(sender.to_string()),
+        };
+        assert!(rule("42").matches("telegram", "42", "42|alice"));
+        assert!(rule("@alice").matches("telegram", "42", "42|alice"));
+        assert!(rule("42|alice").matches("telegram", "42", "42"));
+        assert!(!rule("42").matches("telegram", "42", "7|bob"));
+        assert!(!rule("42").matches("discord", "42", "42|alice"));
+    }
+    #[test]
     fn loads_exec_tool_policy_from_config() {
         let raw = r#"{
             "tools": {
@@ -612,4 +1280,63 @@ mod tests {
         assert_eq!(parsed.runtime.worker_threads, 3);
         assert_eq!(parsed.runtime.max_blocking_threads, 12);
     }
+    #[test]
+    fn loads_provider_retry_settings() {
+        let raw = r#"{
+            "providers": {
+                "openai": { "retry": { "maxRetries": 5, "initialBackoffMs": 100 } }
+            }
+        }"#;
+        let parsed = parse_compat_json(raw).expect("parse");
+        let retry = &parsed.providers.openai.retry;
+        assert_eq!(retry.max_retries, 5);
+        assert_eq!(retry.initial_backoff_ms, 100);
+        assert_eq!(retry.max_backoff_ms, 30_000);
+        assert_eq!(parsed.providers.groq.retry.max_retries, 3);
+    }
+    #[test]
+    fn agent_configs_list_each_workspace_once() {
+        let raw = r#"{
+            "agents": {
+                "defaults": { "workspace": "/tmp/ws" },
+                "profiles": {
+                    "coder": {},
+                    "shared": { "workspace": "/tmp/ws" },
+                    "twin": { "workspace": "/tmp/ws/agents/coder" }
+                }
+            }
+        }"#;
+        let parsed = parse_compat_json(raw).expect("parse");
+        let agents: Vec<(String, PathBuf)> = parsed
+            .agent_configs()
+            .into_iter()
+            .map(|(name, config)| (name, config.workspace_path()))
+            .collect();
+        assert_eq!(
+            agents,
+            vec![
+                ("default".to_string(), PathBuf::from("/tmp/ws")),
+                ("coder".to_string(), PathBuf::from("/tmp/ws/agents/coder")),
+            ]
+        );
+    }
+    #[test]
+    fn custom_endpoint_names_and_headers_keep_their_spelling() {
+        let raw = r#"{
+            "providers": {
+                "custom": {
+                    "lmStudio": {
+                        "apiBase": "http://localhost:1234/v1",
+                        "headers": { "X-Api-Token": "t" },
+                        "model": "qwen2.5-7b"
+                    }
+                }
+            }
+        }"#;
+        let parsed = parse_compat_json(raw).expect("parse");
+        let lm = parsed.providers.custom.get("lmStudio").expect("entry");
+        assert_eq!(lm.api_base.as_deref(), Some("http://localhost:1234/v1"));
+        assert_eq!(lm.headers.get("X-Api-Token").map(String::as_str), Some("t"));
+        assert_eq!(lm.model.as_deref(), Some("qwen2.5-7b"));
+    }
 }
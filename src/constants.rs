@@ -1,3 +1,7 @@
 pub fn is_internal_channel(channel: &str) -> bool {
     matches!(channel, "cli" | "cron" | "heartbeat" | "system" | "device")
 }
+/// Senders used by the heartbeat and cron schedulers for their own turns.
+pub fn is_background_sender(sender_id: &str) -> bool {
+    matches!(sender_id, "heartbeat" | "system:cron")
+}
@@ -9,7 +9,9 @@ pub struct ContextBuilder {
     memory: MemoryStore,
     cached_bootstrap: OnceCell<String>,
     cached_skills_summary: OnceCell<String>,
+    bootstrap_files: Vec<String>,
 }
+const DEFAULT_BOOTSTRAP_FILES: [&str; 4] = ["AGENTS.md", "SOUL.md", "USER.md", "IDENTITY.md"];
 impl ContextBuilder {
     pub fn new(workspace: PathBuf) -> Self {
         let skills_loader = SkillsLoader::new(&workspace);
@@ -20,8 +22,14 @@ impl ContextBuilder {
             memory,
             cached_bootstrap: OnceCell::new(),
             cached_skills_summary: OnceCell::new(),
+            bootstrap_files: DEFAULT_BOOTSTRAP_FILES.map(str::to_string).to_vec(),
         }
     }
+    /// Workspace files appended to the system prompt instead of the defaults.
+    pub fn with_bootstrap_files(mut self, files: &[String]) -> Self {
+        self.bootstrap_files = files.to_vec();
+        self
+    }
     pub fn get_skills_info(&self) -> serde_json::Value {
         let skills = self.skills_loader.list_skills();
         serde_json::json!({
@@ -173,9 +181,8 @@ impl ContextBuilder {
         out
     }
     fn load_bootstrap_files(&self) -> String {
-        let files = ["AGENTS.md", "SOUL.md", "USER.md", "IDENTITY.md"];
         let mut out = String::new();
-        for name in files {
+        for name in &self.bootstrap_files {
             let path = self.workspace.join(name);
             let content = std::fs::read_to_string(path).unwrap_or_default();
             if content.is_empty() {
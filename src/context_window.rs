@@ -0,0 +1,307 @@
+use crate::providers::{Message, ToolDefinition};
+use std::collections::HashMap;
+/// Used for models that are neither configured nor in [`KNOWN_CONTEXT_SIZES`].
+pub const DEFAULT_CONTEXT_SIZE: usize = 32_768;
+/// Share of the window we plan to fill; token estimates are approximate.
+const SAFETY_PERCENT: usize = 90;
+/// Older tool results over this many chars are cut down before whole turns
+/// are dropped.
+const COMPRESSED_TOOL_CHARS: usize = 500;
+/// Fixed per-message cost of role markers and separators.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+const COMPRESSED_MARKER: &str = "[older tool output compressed";
+/// Rough cost of one inline image; providers bill roughly 500-1600 tokens.
+const IMAGE_TOKENS: usize = 1000;
+/// Room kept for the note about dropped turns added to the system prompt.
+const DROP_NOTE_TOKENS: usize = 20;
+/// Context sizes by model-name prefix (without the `vendor/` part); the first
+/// match wins, so longer prefixes come first.
+const KNOWN_CONTEXT_SIZES: &[(&str, usize)] = &[
+    ("claude", 200_000),
+    ("gemini", 1_048_576),
+    ("gpt-5", 400_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4", 8_192),
+    ("gpt-3.5", 16_385),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("deepseek", 128_000),
+    ("glm-4", 128_000),
+    ("llama-3", 128_000),
+    ("llama3", 8_192),
+    ("mixtral", 32_768),
+    ("qwen", 32_768),
+];
+/// Context size of `model`: `overrides` (keyed by the full or bare model
+/// name), then the built-in table, then [`DEFAULT_CONTEXT_SIZE`].
+pub fn context_size(model: &str, overrides: &HashMap<String, usize>) -> usize {
+    let bare = model.rsplit_once('/').map_or(model, |(_, bare)| bare);
+    if let Some(size) = overrides.get(model).or_else(|| overrides.get(bare)) {
+        return *size;
+    }
+    let bare = bare.to_lowercase();
+    KNOWN_CONTEXT_SIZES
+        .iter()
+        .find(|(prefix, _)| bare.starts_with(prefix))
+        .map_or(DEFAULT_CONTEXT_SIZE, |(_, size)| *size)
+}
+/// Tokens left for the prompt once the reply and the tool schemas are
+/// accounted for.
+pub fn prompt_budget(context_size: usize, max_output: usize, tool_tokens: usize) -> usize {
+    // A reply limit larger than half the window is a misconfiguration; don't
+    // let it starve the prompt entirely.
+    let reserved = max_output.min(context_size / 2) + tool_tokens;
+    (context_size * SAFETY_PERCENT / 100).saturating_sub(reserved)
+}
+/// Rough token count of `text`: ~4 ASCII chars per token, ~2 for other
+/// alphabets (Cyrillic, Greek, ...) and one per CJK character.
+pub fn estimate_text(text: &str) -> usize {
+    let quarters: usize = text.chars().map(char_quarters).sum();
+    quarters.div_ceil(4)
+}
+/// Cost of one char in quarter tokens.
+fn char_quarters(c: char) -> usize {
+    match c as u32 {
+        0..0x80 => 1,
+        0x80..0x2E80 => 2,
+        _ => 4,
+    }
+}
+pub fn estimate_message(message: &Message) -> usize {
+    let calls: usize = message
+        .tool_calls
+        .iter()
+        .map(|tc| {
+            MESSAGE_OVERHEAD_TOKENS
+                + estimate_text(tc.name.as_deref().unwrap_or_default())
+                + tc.arguments.as_ref().map_or(0, |args| {
+                    estimate_text(&serde_json::to_string(args).unwrap_or_default())
+                })
+        })
+        .sum();
+    MESSAGE_OVERHEAD_TOKENS
+        + estimate_text(&message.content)
+        + calls
+        + message.tool_call_id.as_deref().map_or(0, estimate_text)
+        + message.images.len() * IMAGE_TOKENS
+}
+pub fn estimate_tokens(messages: &[Message]) -> usize {
+    messages.iter().map(estimate_message).sum()
+}
+pub fn estimate_tool_defs(defs: &[ToolDefinition]) -> usize {
+    estimate_text(&serde_json::to_string(defs).unwrap_or_default())
+}
+/// What [`fit`] had to do to make the request fit.
+#[derive(Debug, Default, PartialEq)]
+pub struct Fitted {
+    pub compressed: usize,
+    pub dropped: usize,
+    pub truncated: usize,
+}
+impl Fitted {
+    pub fn changed(&self) -> bool {
+        *self != Self::default()
+    }
+}
+/// Shrinks `messages` (system prompt, history, current turn) to `budget`
+/// tokens. In order: older tool outputs are compressed, then the oldest
+/// turns are dropped whole, and as a last resort the largest remaining
+/// messages are truncated. A turn runs from one user message to the next,
+/// so tool calls and their results are always kept or dropped together.
+pub fn fit(messages: &mut Vec<Message>, budget: usize) -> Fitted {
+    let mut fitted = Fitted::default();
+    let mut total = estimate_tokens(messages);
+    if total <= budget {
+        return fitted;
+    }
+    let head = usize::from(messages.first().is_some_and(|m| m.role == "system"));
+    let current = messages
+        .iter()
+        .rposition(|m| m.role == "user")
+        .unwrap_or(messages.len())
+        .max(head);
+    // The results the model is about to read are left alone.
+    let latest_batch = messages
+        .iter()
+        .rposition(|m| m.role == "assistant" && !m.tool_calls.is_empty())
+        .filter(|&idx| idx >= current)
+        .unwrap_or(messages.len());
+    for message in &mut messages[head..latest_batch] {
+        if total <= budget {
+            break;
+        }
+        if message.role != "tool" {
+            continue;
+        }
+        let Some(short) = compress_tool_output(&message.content) else {
+            continue;
+        };
+        let before = estimate_message(message);
+        message.content = short;
+        total = total - before + estimate_message(message);
+        fitted.compressed += 1;
+    }
+    let note = if head == 1 { DROP_NOTE_TOKENS } else { 0 };
+    let mut drop_end = head;
+    while total + note > budget && drop_end < current {
+        let next = messages[drop_end + 1..current]
+            .iter()
+            .position(|m| m.role == "user")
+            .map_or(current, |pos| drop_end + 1 + pos);
+        total -= estimate_tokens(&messages[drop_end..next]);
+        drop_end = next;
+    }
+    if drop_end > head {
+        fitted.dropped = drop_end - head;
+        messages.drain(head..drop_end);
+        if head == 1 {
+            let before = estimate_message(&messages[0]);
+            messages[0].content.push_str(&format!(
+                "\n\n[{} earlier messages were left out to fit the context window.]",
+                fitted.dropped
+            ));
+            total = total - before + estimate_message(&messages[0]);
+        }
+    }
+    let mut truncated = vec![false; messages.len()];
+    while total > budget {
+        let Some(idx) = (0..messages.len())
+            .filter(|&i| !truncated[i])
+            .max_by_key(|&i| estimate_text(&messages[i].content))
+        else {
+            break;
+        };
+        truncated[idx] = true;
+        let before = estimate_message(&messages[idx]);
+        let keep = estimate_text(&messages[idx].content).saturating_sub(total - budget);
+        messages[idx].content = truncate_to_tokens(&messages[idx].content, keep);
+        total = total - before + estimate_message(&messages[idx]);
+        fitted.truncated += 1;
+    }
+    fitted
+}
+fn compress_tool_output(content: &str) -> Option<String> {
+    let total = content.chars().count();
+    if total <= COMPRESSED_TOOL_CHARS || content.contains(COMPRESSED_MARKER) {
+        return None;
+    }
+    let kept: String = content.chars().take(COMPRESSED_TOOL_CHARS).collect();
+    let omitted = total - COMPRESSED_TOOL_CHARS;
+    Some(format!(
+        "{kept}\n\n{COMPRESSED_MARKER}: omitted {omitted} chars]"
+    ))
+}
+fn truncate_to_tokens(text: &str, tokens: usize) -> String {
+    const MARKER: &str = "\n\n[truncated to fit the context window]";
+    let budget = tokens.saturating_sub(estimate_text(MARKER)) * 4;
+    let mut used = 0;
+    let mut end = 0;
+    for (idx, c) in text.char_indices() {
+        used += char_quarters(c);
+        if used > budget {
+            break;
+        }
+        end = idx + c.len_utf8();
+    }
+    format!("{}{}", &text[..end], MARKER)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ToolCall;
+    fn exec_pair(id: &str, output: &str) -> [Message; 2] {
+        [
+            Message {
+                role: "assistant".to_string(),
+                content: String::new(),
+                tool_calls: vec![ToolCall {
+                    id: id.to_string(),
+                    tool_type: "function".to_string(),
+                    function: None,
+                    name: Some("exec".to_string()),
+                    arguments: None,
+                }],
+                tool_call_id: None,
+                images: vec![],
+            },
+            Message::tool(output, id),
+        ]
+    }
+    #[test]
+    fn context_size_prefers_overrides_then_known_models() {
+        let overrides = HashMap::from([("my-local".to_string(), 4096)]);
+        assert_eq!(context_size("ollama/my-local", &overrides), 4096);
+        assert_eq!(
+            context_size("anthropic/claude-sonnet-4", &overrides),
+            200_000
+        );
+        assert_eq!(context_size("gpt-4o-mini", &overrides), 128_000);
+        assert_eq!(context_size("unknown", &overrides), DEFAULT_CONTEXT_SIZE);
+        assert_eq!(estimate_text("abcdefgh"), 2);
+        assert_eq!(estimate_text("привет"), 3);
+    }
+    #[test]
+    fn fit_compresses_then_drops_whole_turns() {
+        let big = "x".repeat(4000);
+        let mut messages = vec![Message::system("sys"), Message::user("old question")];
+        messages.extend(exec_pair("a", &big));
+        messages.push(Message::user("recent question"));
+        messages.extend(exec_pair("b", &big));
+        messages.push(Message::user("now"));
+        messages.extend(exec_pair("c", &big));
+        let untouched = estimate_tokens(&messages);
+        let fitted = fit(&mut messages, untouched - 500);
+        assert_eq!(
+            fitted,
+            Fitted {
+                compressed: 1,
+                dropped: 0,
+                truncated: 0
+            }
+        );
+        assert!(messages[3].content.contains("older tool output compressed"));
+        assert_eq!(messages.last().unwrap().content, big);
+        let fitted = fit(&mut messages, 1250);
+        assert_eq!(
+            fitted,
+            Fitted {
+                compressed: 1,
+                dropped: 3,
+                truncated: 0
+            }
+        );
+        assert_eq!(messages[1].content, "recent question");
+        assert!(
+            messages[0]
+                .content
+                .contains("3 earlier messages were left out")
+        );
+        for (idx, m) in messages.iter().enumerate() {
+            if let Some(id) = m.tool_call_id.as_ref() {
+                assert!(
+                    messages[..idx]
+                        .iter()
+                        .any(|a| a.tool_calls.iter().any(|tc| &tc.id == id))
+                );
+            }
+        }
+        assert!(estimate_tokens(&messages) <= 1250);
+    }
+    #[test]
+    fn fit_truncates_when_the_current_turn_alone_is_too_big() {
+        let mut messages = vec![Message::system("sys"), Message::user(&"y".repeat(10_000))];
+        let fitted = fit(&mut messages, 500);
+        assert_eq!(fitted.truncated, 1);
+        assert!(
+            messages[1]
+                .content
+                .ends_with("[truncated to fit the context window]")
+        );
+        let fitted_tokens = estimate_tokens(&messages);
+        assert!(fitted_tokens <= 500);
+        assert!(fitted_tokens >= 490, "over-truncated to {fitted_tokens}");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::bus::{MessageBus, OutboundMessage};
+use crate::bus::{MessageBus, OutboundKind, OutboundMessage};
 use anyhow::{Result, anyhow};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
@@ -221,6 +221,7 @@ impl CronRunner {
                                 channel: channel.clone(),
                                 chat_id: chat_id.clone(),
                                 content,
+                                kind: OutboundKind::Final,
                             })
                             .await
                         {
@@ -1,4 +1,4 @@
-use crate::bus::{MessageBus, OutboundMessage};
+use crate::bus::{MessageBus, OutboundKind, OutboundMessage};
 use crate::constants;
 use crate::state;
 use async_trait::async_trait;
@@ -164,6 +164,7 @@ async fn send_notification(
             channel: platform.to_string(),
             chat_id: user_id.to_string(),
             content: ev.format_message(),
+            kind: OutboundKind::Final,
         })
         .await;
 }
@@ -0,0 +1,66 @@
+use crate::config::HttpClientConfig;
+use anyhow::{Result, anyhow};
+use std::time::Duration;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Client builder with the configured proxy, timeouts and user agent applied.
+/// `proxy` accepts `http://`, `https://`, `socks5://` and `socks5h://` URLs;
+/// an empty value means a direct connection.
+///
+/// `timeout_secs` limits how long a response may go without sending data,
+/// not the whole request, so streamed replies can run as long as they keep
+/// arriving. Clients that need a deadline add one with [`request_timeout`].
+pub fn client_builder(
+    proxy: Option<&str>,
+    http: &HttpClientConfig,
+) -> Result<reqwest::ClientBuilder> {
+    let user_agent = http
+        .user_agent
+        .clone()
+        .filter(|ua| !ua.trim().is_empty())
+        .unwrap_or_else(|| format!("asterclaw/{}", env!("CARGO_PKG_VERSION")));
+    let mut builder = reqwest::Client::builder()
+        .read_timeout(request_timeout(http))
+        .connect_timeout(Duration::from_secs(
+            http.connect_timeout_secs
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        ))
+        .pool_max_idle_per_host(4)
+        .user_agent(user_agent);
+    if let Some(url) = proxy.map(str::trim).filter(|p| !p.is_empty()) {
+        builder = builder.proxy(parse_proxy(url)?);
+    }
+    Ok(builder)
+}
+/// The configured `timeout_secs`, or the default.
+pub fn request_timeout(http: &HttpClientConfig) -> Duration {
+    Duration::from_secs(http.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS))
+}
+pub fn build_client(proxy: Option<&str>, http: &HttpClientConfig) -> Result<reqwest::Client> {
+    Ok(client_builder(proxy, http)?.build()?)
+}
+fn parse_proxy(url: &str) -> Result<reqwest::Proxy> {
+    let scheme = url.split_once("://").map(|(s, _)| s.to_ascii_lowercase());
+    match scheme.as_deref() {
+        Some("http" | "https" | "socks5" | "socks5h") => {
+            reqwest::Proxy::all(url).map_err(|err| anyhow!("invalid proxy url '{}': {}", url, err))
+        }
+        _ => Err(anyhow!(
+            "unsupported proxy url '{}': expected http://, https://, socks5:// or socks5h://",
+            url
+        )),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn accepts_http_and_socks_proxies() {
+        let http = HttpClientConfig::default();
+        assert!(build_client(Some("http://127.0.0.1:3128"), &http).is_ok());
+        assert!(build_client(Some("socks5h://user:pw@127.0.0.1:1080"), &http).is_ok());
+        assert!(build_client(Some("  "), &http).is_ok());
+        let err = build_client(Some("ftp://proxy"), &http).expect_err("bad scheme");
+        assert!(err.to_string().contains("unsupported proxy url"));
+    }
+}
@@ -1,22 +1,30 @@
 mod agent;
+mod approval;
 mod auth;
 mod bus;
 mod channels;
 mod config;
 mod constants;
 mod context_builder;
+mod context_window;
 mod cron;
 mod devices;
 mod health;
 mod heartbeat;
+mod http;
 mod logger;
+mod media;
 mod memory;
 mod migrate;
 mod providers;
+mod retention;
 mod session;
 mod skills;
 mod state;
 mod tools;
+mod trace;
+mod transcript;
+mod usage;
 mod voice;
 use anyhow::Result;
 use clap::{Parser, Subcommand};
@@ -77,9 +85,73 @@ enum Commands {
         #[command(subcommand)]
         command: Option<SkillsCommands>,
     },
+    /// Token and cost totals per day, session and model
+    Usage {
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+        #[arg(long)]
+        session: Option<String>,
+    },
+    /// Per-turn traces of provider calls and tool runs
+    Trace {
+        #[command(subcommand)]
+        command: TraceCommands,
+    },
+    /// Stored conversations: list, view, export and delete them
+    Sessions {
+        /// Only this agent profile (`default` for the default agent);
+        /// otherwise every agent's workspace
+        #[arg(long, global = true)]
+        agent: Option<String>,
+        #[command(subcommand)]
+        command: SessionsCommands,
+    },
     Version,
 }
 #[derive(Subcommand, Debug)]
+enum SessionsCommands {
+    /// All sessions, most recently active first
+    List,
+    /// Print a session's transcript
+    Show { key: String },
+    /// Write a session as Markdown, JSON or HTML
+    Export {
+        key: String,
+        #[arg(long, default_value = "md", value_parser = ["md", "json", "html"])]
+        format: String,
+        /// File to write; stdout when omitted
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Delete one session
+    Delete { key: String },
+    /// Delete sessions with no activity for longer than an age like 30d
+    Prune {
+        #[arg(long)]
+        older_than: String,
+        /// Only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Move JSON session files into the SQLite store
+    Migrate,
+}
+#[derive(Subcommand, Debug)]
+enum TraceCommands {
+    /// Most recent turns, newest first
+    List {
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Pretty-print one turn
+    Show {
+        turn_id: String,
+        /// Don't clip long messages and tool outputs
+        #[arg(long)]
+        full: bool,
+    },
+}
+#[derive(Subcommand, Debug)]
 enum CronCommands {
     List {
         #[arg(long, default_value_t = false)]
@@ -162,6 +234,9 @@ fn main() -> Result<()> {
         } => migrate_cmd(dry_run, config_only, workspace_only, force),
         Commands::Auth { command } => auth_cmd(command),
         Commands::Skills { command } => skills_cmd(command),
+        Commands::Usage { days, session } => usage_cmd(days, session),
+        Commands::Trace { command } => trace_cmd(command),
+        Commands::Sessions { agent, command } => sessions_cmd(agent, command),
     }
 }
 fn build_runtime(runtime_cfg: &config::RuntimeConfig) -> Result<tokio::runtime::Runtime> {
@@ -225,13 +300,14 @@ fn agent_cmd(message: Option<String>, session: Option<String>) -> Result<()> {
     );
     let session_key = session.unwrap_or_else(|| "cli:default".to_string());
     let runtime = build_runtime(&config.runtime)?;
+    let input = CliInput::spawn();
+    agent_loop.set_prompter("cli", Arc::new(CliPrompter(input.clone())));
     if let Some(msg) = message {
-        let response =
-            runtime.block_on(async { agent_loop.process_direct(&msg, &session_key).await })?;
-        println!("\n{}", response);
+        let response = run_cli_turn(&agent_loop, &msg, &session_key, &runtime)?;
+        println!("{}", response);
     } else {
         println!("Interactive mode (Ctrl+C to exit)\n");
-        interactive_mode(&agent_loop, &session_key, &runtime)?;
+        interactive_mode(&agent_loop, &session_key, &runtime, &input)?;
     }
     Ok(())
 }
@@ -239,13 +315,15 @@ fn interactive_mode(
     agent_loop: &Arc<agent::AgentLoop>,
     session_key: &str,
     runtime: &tokio::runtime::Runtime,
+    stdin: &CliInput,
 ) -> Result<()> {
     use std::io::{self, Write};
     loop {
         print!("You: ");
         io::stdout().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
+        let Some(input) = runtime.block_on(stdin.next_line()) else {
+            break;
+        };
         let input = input.trim();
         if input.is_empty() {
             continue;
@@ -254,15 +332,119 @@ fn interactive_mode(
             println!("Goodbye!");
             break;
         }
-        let response =
-            runtime.block_on(async { agent_loop.process_direct(input, session_key).await });
-        match response {
-            Ok(resp) => println!("\n{}\n", resp),
+        match run_cli_turn(agent_loop, input, session_key, runtime) {
+            Ok(resp) => println!("{}\n", resp),
             Err(e) => println!("Error: {}", e),
         }
     }
     Ok(())
 }
+/// Runs one CLI turn, echoing streamed text to stdout as it arrives. Returns
+/// whatever still has to be printed once the turn completes.
+fn run_cli_turn(
+    agent_loop: &agent::AgentLoop,
+    input: &str,
+    session_key: &str,
+    runtime: &tokio::runtime::Runtime,
+) -> Result<String> {
+    let printer = StdoutStream::default();
+    println!();
+    let on_update = |text: &str| printer.update(text);
+    let response = runtime.block_on(async {
+        agent_loop
+            .process_direct_streaming(input, session_key, &on_update)
+            .await
+    })?;
+    Ok(printer.finish(response))
+}
+/// Lines read from stdin by a dedicated thread, so the chat loop and
+/// approval prompts raised mid-turn share one reader.
+struct CliInput {
+    lines: tokio::sync::Mutex<tokio::sync::mpsc::UnboundedReceiver<String>>,
+}
+impl CliInput {
+    fn spawn() -> Arc<Self> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Arc::new(Self {
+            lines: tokio::sync::Mutex::new(rx),
+        })
+    }
+    /// `None` once stdin is closed.
+    async fn next_line(&self) -> Option<String> {
+        self.lines.lock().await.recv().await
+    }
+}
+/// Asks for exec approvals on the terminal.
+struct CliPrompter(Arc<CliInput>);
+#[async_trait::async_trait]
+impl approval::Prompter for CliPrompter {
+    async fn ask(&self, request: &approval::ApprovalRequest) -> Option<approval::Decision> {
+        use approval::Decision;
+        use std::io::Write;
+        println!("\n⚠️  Approval needed: {}", request.reason);
+        println!("    {}", request.command);
+        loop {
+            match &request.remember {
+                Some(prefix) => print!("Run it? [y]es / [n]o / [a]lways for `{}`: ", prefix),
+                None => print!("Run it? [y]es / [n]o: "),
+            }
+            let _ = std::io::stdout().flush();
+            let answer = self.0.next_line().await?;
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => return Some(Decision::Approve),
+                "n" | "no" => return Some(Decision::Deny),
+                "a" | "always" if request.remember.is_some() => {
+                    return Some(Decision::ApproveAlways);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+/// Prints the growing text of the current LLM iteration; an empty update
+/// starts a new iteration.
+#[derive(Default)]
+struct StdoutStream {
+    state: parking_lot::Mutex<(usize, String)>,
+}
+impl StdoutStream {
+    fn update(&self, text: &str) {
+        use std::io::Write;
+        let mut state = self.state.lock();
+        if text.is_empty() {
+            if state.0 > 0 {
+                println!();
+            }
+            *state = (0, String::new());
+            return;
+        }
+        let printed = state.0;
+        if let Some(rest) = text.get(printed..) {
+            print!("{}", rest);
+            let _ = std::io::stdout().flush();
+            state.0 = text.len();
+        }
+        state.1 = text.to_string();
+    }
+    fn finish(&self, response: String) -> String {
+        let state = self.state.lock();
+        if state.0 == 0 {
+            return response;
+        }
+        if state.1.trim() == response.trim() {
+            return String::new();
+        }
+        format!("\n{}", response)
+    }
+}
 fn gateway_cmd(_debug: bool) -> Result<()> {
     let config_path = config::get_config_path()?;
     let config = config::load_config(&config_path)?;
@@ -274,6 +456,12 @@ fn gateway_cmd(_debug: bool) -> Result<()> {
     println!("📦 Agent Status:");
     let startup_info = agent_loop.get_startup_info();
     println!("  • Tools: {} loaded", startup_info["tools"]["count"]);
+    if let Some(agents) = startup_info["agents"].as_array()
+        && !agents.is_empty()
+    {
+        let names: Vec<&str> = agents.iter().filter_map(|a| a.as_str()).collect();
+        println!("  • Agents: default, {}", names.join(", "));
+    }
     let channel_manager = Arc::new(channels::ChannelManager::new(&config, &msg_bus)?);
     let enabled_channels = channel_manager.get_enabled_channels();
     agent_loop.set_channel_manager(channel_manager.clone());
@@ -300,6 +488,11 @@ fn gateway_cmd(_debug: bool) -> Result<()> {
         );
         heartbeat_service.set_bus(&msg_bus);
         heartbeat_service.start()?;
+        let retention_service = retention::RetentionService::new(
+            agent_loop.retention_targets(),
+            config.retention.clone(),
+        );
+        retention_service.start();
         let mut devices_service = devices::Service::new(
             devices::Config {
                 enabled: config.devices.enabled,
@@ -329,6 +522,7 @@ fn gateway_cmd(_debug: bool) -> Result<()> {
         agent_loop.stop();
         channel_manager.stop_all().await?;
         heartbeat_service.stop().await;
+        retention_service.stop().await;
         devices_service.stop();
         cron_runner.stop();
         health_server.stop().await?;
@@ -359,7 +553,7 @@ fn status_cmd() -> Result<()> {
             workspace.display(),
             if workspace.exists() { "✓" } else { "✗" }
         );
-        println!("Model: {}", config.agents.defaults.model);
+        println!("Model: {}", providers::default_model(&config));
         let has_openrouter = config.providers.openrouter.api_key.is_some();
         let has_anthropic = config.providers.anthropic.api_key.is_some();
         let has_openai = config.providers.openai.api_key.is_some();
@@ -376,6 +570,15 @@ fn status_cmd() -> Result<()> {
         println!("OpenAI API: {}", if has_openai { "✓" } else { "not set" });
         println!("Zhipu API: {}", if has_zhipu { "✓" } else { "not set" });
         println!("Groq API: {}", if has_groq { "✓" } else { "not set" });
+        let mut custom: Vec<_> = config.providers.custom.iter().collect();
+        custom.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, endpoint) in custom {
+            println!(
+                "Custom endpoint {}: {}",
+                name,
+                endpoint.api_base.as_deref().unwrap_or("api_base not set")
+            );
+        }
     } else {
         println!("Config: {} ✗", config_path.display());
         println!("\nRun 'asterclaw onboard' to initialize.");
@@ -548,6 +751,232 @@ fn auth_cmd(command: Option<AuthCommands>) -> Result<()> {
     }
     Ok(())
 }
+fn usage_cmd(days: i64, session: Option<String>) -> Result<()> {
+    let cfg_path = config::get_config_path()?;
+    let cfg = config::load_config(&cfg_path)?;
+    // Every agent profile records into this one ledger in the default
+    // workspace, so it already covers them all.
+    let ledger = usage::UsageLedger::new(&cfg.workspace_path(), &cfg.usage);
+    let since = chrono::Utc::now() - chrono::Duration::days(days.max(1) - 1);
+    let since_day = since.format("%Y-%m-%d").to_string();
+    let records: Vec<usage::UsageRecord> = ledger
+        .load()
+        .into_iter()
+        .filter(|r| usage::day_key(r) >= since_day)
+        .filter(|r| session.as_ref().is_none_or(|s| &r.session_key == s))
+        .collect();
+    if records.is_empty() {
+        println!("No usage recorded in the last {} day(s).", days.max(1));
+        return Ok(());
+    }
+    let total = usage::totals_by(&records, |_| "total".to_string());
+    println!("{}\n", usage::format_section("Total", &total));
+    println!(
+        "{}\n",
+        usage::format_section("By day", &usage::totals_by(&records, usage::day_key))
+    );
+    println!(
+        "{}\n",
+        usage::format_section(
+            "By session",
+            &usage::totals_by(&records, |r| r.session_key.clone())
+        )
+    );
+    println!(
+        "{}",
+        usage::format_section(
+            "By model",
+            &usage::totals_by(&records, |r| format!("{}/{}", r.provider, r.model))
+        )
+    );
+    Ok(())
+}
+fn trace_cmd(command: TraceCommands) -> Result<()> {
+    let cfg_path = config::get_config_path()?;
+    let cfg = config::load_config(&cfg_path)?;
+    let workspace = cfg.workspace_path();
+    match command {
+        TraceCommands::List { limit } => {
+            let ids = trace::recent_turns(&workspace, limit.max(1));
+            if ids.is_empty() {
+                println!("No traces recorded yet.");
+            }
+            for id in ids {
+                let lines = trace::load_turn(&workspace, &id).unwrap_or_default();
+                println!("{}  {}", id, trace::summarize_turn(&lines));
+            }
+        }
+        TraceCommands::Show { turn_id, full } => {
+            let lines = trace::load_turn(&workspace, &turn_id)?;
+            print!("{}", trace::render_turn(&lines, if full { 0 } else { 400 }));
+        }
+    }
+    Ok(())
+}
+fn sessions_cmd(agent: Option<String>, command: SessionsCommands) -> Result<()> {
+    let cfg_path = config::get_config_path()?;
+    let cfg = config::load_config(&cfg_path)?;
+    // Profiles with their own workspace keep their own sessions, as the
+    // gateway's retention pass does.
+    let agents = match agent.as_deref() {
+        None => cfg.agent_configs(),
+        Some("default") => vec![("default".to_string(), cfg.clone())],
+        Some(name) => vec![(
+            name.to_string(),
+            cfg.for_profile(name)
+                .ok_or_else(|| anyhow::anyhow!("unknown agent '{}'", name))?,
+        )],
+    };
+    let stores = agents
+        .iter()
+        .map(|(name, agent_cfg)| {
+            let sessions =
+                session::SessionManager::open(&agent_cfg.workspace_path(), &agent_cfg.sessions)?;
+            Ok((name.as_str(), agent_cfg, sessions))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Names the agent in messages only when there is more than one.
+    let tag = |name: &str| {
+        if stores.len() > 1 {
+            format!(" [{}]", name)
+        } else {
+            String::new()
+        }
+    };
+    let transcript = |key: &str| {
+        let mut found = Vec::new();
+        for (name, _, sessions) in &stores {
+            if let Some(transcript) = sessions.transcript(key)? {
+                found.push((*name, transcript));
+            }
+        }
+        if found.len() > 1 {
+            let names: Vec<&str> = found.iter().map(|(name, _)| *name).collect();
+            anyhow::bail!(
+                "session {} exists for agents {}; pick one with --agent",
+                key,
+                names.join(", ")
+            );
+        }
+        found
+            .pop()
+            .map(|(_, transcript)| transcript)
+            .ok_or_else(|| anyhow::anyhow!("no saved session {}", key))
+    };
+    match command {
+        SessionsCommands::List => {
+            let mut any = false;
+            for (name, agent_cfg, sessions) in &stores {
+                let list = sessions.list()?;
+                if list.is_empty() {
+                    continue;
+                }
+                if stores.len() > 1 {
+                    if any {
+                        println!();
+                    }
+                    println!("{} ({}):", name, agent_cfg.workspace_path().display());
+                }
+                any = true;
+                for info in list {
+                    println!(
+                        "{:<40} {:>5} msgs  {}  {:>6.1} KB",
+                        info.key,
+                        info.messages,
+                        info.last_active.format("%Y-%m-%d %H:%M"),
+                        info.size as f64 / 1024.0
+                    );
+                }
+            }
+            if !any {
+                println!("No saved sessions.");
+            }
+        }
+        SessionsCommands::Show { key } => {
+            print!("{}", transcript::render_markdown(&transcript(&key)?));
+        }
+        SessionsCommands::Export {
+            key,
+            format,
+            output,
+        } => {
+            let transcript = transcript(&key)?;
+            let rendered = match format.as_str() {
+                "json" => serde_json::to_string_pretty(&transcript)? + "\n",
+                "html" => transcript::render_html(&transcript),
+                _ => transcript::render_markdown(&transcript),
+            };
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, rendered)?;
+                    println!("Exported {} to {}", key, path.display());
+                }
+                None => print!("{}", rendered),
+            }
+        }
+        SessionsCommands::Delete { key } => {
+            let mut deleted = false;
+            for (name, _, sessions) in &stores {
+                if sessions.delete(&key)? {
+                    println!("Deleted session {}{}", key, tag(name));
+                    deleted = true;
+                }
+            }
+            if !deleted {
+                println!("No saved session {}", key);
+            }
+        }
+        SessionsCommands::Prune {
+            older_than,
+            dry_run,
+        } => {
+            let cutoff = chrono::Local::now() - session::parse_age(&older_than)?;
+            let mut any = false;
+            for (name, _, sessions) in &stores {
+                let stale: Vec<_> = sessions
+                    .list()?
+                    .into_iter()
+                    .filter(|info| info.last_active < cutoff)
+                    .collect();
+                for info in &stale {
+                    if !dry_run {
+                        sessions.delete(&info.key)?;
+                    }
+                    println!(
+                        "{} {}{} (last active {})",
+                        if dry_run { "Would delete" } else { "Deleted" },
+                        info.key,
+                        tag(name),
+                        info.last_active.format("%Y-%m-%d")
+                    );
+                }
+                any |= !stale.is_empty();
+            }
+            if !any {
+                println!("No sessions inactive for longer than {}.", older_than);
+            }
+        }
+        SessionsCommands::Migrate => {
+            for (name, agent_cfg, _) in &stores {
+                let sessions_dir = agent_cfg.workspace_path().join("sessions");
+                let target = session::SqliteStore::open(&sessions_dir.join("sessions.db"))?;
+                let imported = session::migrate_json(&sessions_dir, &target)?;
+                for key in &imported {
+                    println!("Imported {}{}", key, tag(name));
+                }
+                println!(
+                    "Moved {} session(s){} into sessions.db; the JSON files are in sessions/migrated/.",
+                    imported.len(),
+                    tag(name)
+                );
+            }
+            if cfg.sessions.store != "sqlite" {
+                println!("Set sessions.store to \"sqlite\" in the config to use it.");
+            }
+        }
+    }
+    Ok(())
+}
 fn skills_cmd(command: Option<SkillsCommands>) -> Result<()> {
     let cfg_path = config::get_config_path()?;
     let cfg = config::load_config(&cfg_path)?;
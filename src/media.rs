@@ -0,0 +1,144 @@
+use crate::providers::{ImagePart, Message};
+use base64::Engine;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+/// Larger images are not inlined; most APIs reject them anyway.
+const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+/// Vision-capable models by model-name prefix (without the `vendor/` part).
+const KNOWN_VISION_MODELS: &[&str] = &[
+    "claude-3",
+    "claude-sonnet-4",
+    "claude-opus-4",
+    "claude-haiku-4",
+    "gpt-4o",
+    "gpt-4.1",
+    "gpt-4-turbo",
+    "gpt-5",
+    "o1",
+    "o3",
+    "o4",
+    "gemini",
+    "llama-4",
+    "llava",
+    "pixtral",
+    "glm-4v",
+    "glm-4.5v",
+    "grok-4",
+];
+/// Whether `model` accepts images: `overrides` (keyed by the full or bare
+/// model name), then the built-in list; names containing `vision` or `-vl`
+/// count too.
+pub fn supports_vision(model: &str, overrides: &HashMap<String, bool>) -> bool {
+    let bare = model.rsplit_once('/').map_or(model, |(_, bare)| bare);
+    if let Some(vision) = overrides.get(model).or_else(|| overrides.get(bare)) {
+        return *vision;
+    }
+    let bare = bare.to_lowercase();
+    KNOWN_VISION_MODELS
+        .iter()
+        .any(|prefix| bare.starts_with(prefix))
+        || bare.contains("vision")
+        || bare.contains("-vl")
+}
+/// Where channels keep downloaded attachments, one directory per chat.
+pub fn media_root() -> PathBuf {
+    std::env::temp_dir().join("asterclaw-media")
+}
+/// Download directory for attachments from `chat_id` on `channel`.
+pub fn media_dir(channel: &str, chat_id: &str) -> PathBuf {
+    media_root().join(format!("{channel}_{chat_id}").replace([':', '/', '\\'], "_"))
+}
+/// MIME type of an image file we can inline, by extension.
+pub fn image_mime(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path).extension()?.to_str()?.to_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+fn load_image(path: &str, mime_type: &str) -> Result<ImagePart, String> {
+    let size = std::fs::metadata(path)
+        .map_err(|err| err.to_string())?
+        .len();
+    if size > MAX_IMAGE_BYTES {
+        return Err(format!(
+            "{} KB is over the {} KB limit",
+            size / 1024,
+            MAX_IMAGE_BYTES / 1024
+        ));
+    }
+    let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+    Ok(ImagePart {
+        mime_type: mime_type.to_string(),
+        data: base64::engine::general_purpose::STANDARD.encode(bytes),
+    })
+}
+/// Attaches the images among `media` to `message`. Without `vision`, or
+/// when an image can't be read, a note in the text tells the model why it
+/// can't see it so it can say so instead of guessing.
+pub fn attach_images(message: &mut Message, media: &[String], vision: bool) {
+    let mut notes = Vec::new();
+    for path in media {
+        let Some(mime_type) = image_mime(path) else {
+            continue;
+        };
+        if !vision {
+            notes.push(
+                "[The user sent an image, but the current model can't view images. \
+Tell them so; they can describe it or switch to a vision-capable model with /switch model.]"
+                    .to_string(),
+            );
+            continue;
+        }
+        match load_image(path, mime_type) {
+            Ok(image) => message.images.push(image),
+            Err(err) => {
+                tracing::warn!("can't attach image {}: {}", path, err);
+                notes.push(format!("[An attached image could not be loaded: {}]", err));
+            }
+        }
+    }
+    notes.dedup();
+    for note in notes {
+        if !message.content.is_empty() {
+            message.content.push('\n');
+        }
+        message.content.push_str(&note);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn images_go_to_vision_models_and_others_get_a_note() {
+        let overrides = HashMap::from([("my-local".to_string(), true)]);
+        assert!(supports_vision("openai/gpt-4o-mini", &overrides));
+        assert!(supports_vision("ollama/my-local", &overrides));
+        assert!(supports_vision("qwen2.5-vl-72b", &overrides));
+        assert!(!supports_vision("deepseek-chat", &overrides));
+        let tmp = tempfile::TempDir::new().expect("tmp");
+        let photo = tmp.path().join("photo.JPG");
+        std::fs::write(&photo, [0xff, 0xd8, 0xff]).expect("write");
+        let media = vec![
+            photo.to_string_lossy().to_string(),
+            tmp.path().join("voice.ogg").to_string_lossy().to_string(),
+        ];
+        let mut message = Message::user("[image]");
+        attach_images(&mut message, &media, true);
+        assert_eq!(
+            message.images,
+            vec![ImagePart {
+                mime_type: "image/jpeg".to_string(),
+                data: "/9j/".to_string(),
+            }]
+        );
+        assert_eq!(message.content, "[image]");
+        let mut message = Message::user("what is this?");
+        attach_images(&mut message, &media, false);
+        assert!(message.images.is_empty());
+        assert!(message.content.contains("can't view images"));
+    }
+}
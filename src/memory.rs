@@ -63,6 +63,40 @@ impl MemoryStore {
         }
         notes.join("\n\n---\n\n")
     }
+    /// Deletes daily notes dated before `before`, and month directories
+    /// left empty. `MEMORY.md` is never touched. Returns how many notes were
+    /// deleted.
+    pub fn prune_daily_notes(&self, before: chrono::NaiveDate) -> anyhow::Result<usize> {
+        let mut deleted = 0;
+        for month in std::fs::read_dir(&self.memory_dir)?.filter_map(Result::ok) {
+            let month_dir = month.path();
+            let is_month = month
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.len() == 6 && name.bytes().all(|b| b.is_ascii_digit()));
+            if !is_month || !month_dir.is_dir() {
+                continue;
+            }
+            for note in std::fs::read_dir(&month_dir)?.filter_map(Result::ok) {
+                let path = note.path();
+                let Some(date) = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_suffix(".md"))
+                    .and_then(|ymd| chrono::NaiveDate::parse_from_str(ymd, "%Y%m%d").ok())
+                else {
+                    continue;
+                };
+                if date < before {
+                    std::fs::remove_file(&path)?;
+                    deleted += 1;
+                }
+            }
+            // Fails, as intended, while the directory still has files.
+            let _ = std::fs::remove_dir(&month_dir);
+        }
+        Ok(deleted)
+    }
     pub fn get_memory_context(&self) -> String {
         let mut sections = Vec::new();
         let long_term = self.read_long_term();
@@ -165,6 +165,18 @@ fn merge_provider(dst: ProviderConfig, src: ProviderConfig) -> ProviderConfig {
         } else {
             dst.connect_mode
         },
+        retry: dst.retry,
+        headers: if dst.headers.is_empty() {
+            src.headers
+        } else {
+            dst.headers
+        },
+        model: if is_empty_opt(&dst.model) {
+            src.model
+        } else {
+            dst.model
+        },
+        http: dst.http,
     }
 }
 fn is_empty_opt(v: &Option<String>) -> bool {
@@ -190,6 +202,9 @@ fn merge_config(mut existing: Config, incoming: Config) -> Config {
     existing.providers.zhipu = merge_provider(existing.providers.zhipu, incoming.providers.zhipu);
     existing.providers.deepseek =
         merge_provider(existing.providers.deepseek, incoming.providers.deepseek);
+    for (name, cfg) in incoming.providers.custom {
+        existing.providers.custom.entry(name).or_insert(cfg);
+    }
     if !existing.channels.telegram.enabled && incoming.channels.telegram.enabled {
         existing.channels.telegram = incoming.channels.telegram;
     }
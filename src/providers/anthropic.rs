@@ -0,0 +1,350 @@
+use super::retry::{RetryPolicy, send_with_retry};
+use super::{FunctionCall, LlmResponse, Message, Provider, ToolCall, ToolDefinition, UsageInfo};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::collections::HashMap;
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const DEFAULT_MAX_TOKENS: i64 = 4096;
+pub(super) struct AnthropicProvider {
+    api_key: String,
+    base_url: String,
+    extra_headers: HashMap<String, String>,
+    retry: RetryPolicy,
+    client: reqwest::Client,
+}
+impl AnthropicProvider {
+    pub(super) fn new(
+        api_key: String,
+        base_url: String,
+        extra_headers: HashMap<String, String>,
+        retry: RetryPolicy,
+        client: reqwest::Client,
+    ) -> Self {
+        Self {
+            api_key,
+            base_url,
+            extra_headers,
+            retry,
+            client,
+        }
+    }
+    async fn make_request(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        options: &HashMap<String, serde_json::Value>,
+    ) -> Result<LlmResponse> {
+        let model = model.strip_prefix("anthropic/").unwrap_or(model);
+        let body = build_anthropic_request(model, messages, tools, options);
+        let url = format!("{}/messages", self.base_url);
+        let resp = send_with_retry(&self.retry, "anthropic", || {
+            let mut req = self
+                .client
+                .post(&url)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION)
+                .header("Content-Type", "application/json");
+            for (k, v) in &self.extra_headers {
+                req = req.header(k, v);
+            }
+            req.json(&body)
+        })
+        .await?;
+        let result: serde_json::Value = resp.json().await?;
+        parse_anthropic_response(&result)
+    }
+}
+#[async_trait]
+impl Provider for AnthropicProvider {
+    async fn chat_with_options(
+        &self,
+        messages: &mut Vec<Message>,
+        tools: Option<&[ToolDefinition]>,
+        model: &str,
+        options: HashMap<String, serde_json::Value>,
+    ) -> Result<LlmResponse> {
+        self.make_request(model, messages, tools, &options).await
+    }
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let url = format!("{}/models?limit=1000", self.base_url);
+        let resp = send_with_retry(&self.retry, "anthropic", || {
+            let mut req = self
+                .client
+                .get(&url)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION);
+            for (k, v) in &self.extra_headers {
+                req = req.header(k, v);
+            }
+            req
+        })
+        .await?;
+        let result: serde_json::Value = resp.json().await?;
+        Ok(super::model_ids(&result["data"], "id"))
+    }
+}
+fn build_anthropic_request(
+    model: &str,
+    messages: &[Message],
+    tools: Option<&[ToolDefinition]>,
+    options: &HashMap<String, serde_json::Value>,
+) -> serde_json::Value {
+    let (system, turns) = convert_messages(messages);
+    let max_tokens = options
+        .get("max_tokens")
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!(DEFAULT_MAX_TOKENS));
+    let mut body = serde_json::json!({
+        "model": model,
+        "max_tokens": max_tokens,
+        "messages": turns,
+    });
+    if !system.is_empty() {
+        body["system"] = serde_json::json!(system);
+    }
+    if let Some(tool_defs) = tools
+        && !tool_defs.is_empty()
+    {
+        let tools_json: Vec<serde_json::Value> = tool_defs
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "name": t.function.name(),
+                    "description": t.function.description(),
+                    "input_schema": t.function.parameters(),
+                })
+            })
+            .collect();
+        body["tools"] = serde_json::json!(tools_json);
+    }
+    if let Some(temp) = options.get("temperature") {
+        body["temperature"] = temp.clone();
+    }
+    if let Some(top_p) = options.get("top_p") {
+        body["top_p"] = top_p.clone();
+    }
+    if let Some(stop) = options.get("stop") {
+        body["stop_sequences"] = stop.clone();
+    }
+    body
+}
+fn convert_messages(messages: &[Message]) -> (String, Vec<serde_json::Value>) {
+    let mut system_parts: Vec<&str> = Vec::new();
+    let mut turns: Vec<(String, Vec<serde_json::Value>)> = Vec::new();
+    for msg in messages {
+        let (role, blocks) = match msg.role.as_str() {
+            "system" => {
+                if !msg.content.trim().is_empty() {
+                    system_parts.push(&msg.content);
+                }
+                continue;
+            }
+            "assistant" => ("assistant", assistant_blocks(msg)),
+            "tool" => (
+                "user",
+                vec![serde_json::json!({
+                    "type": "tool_result",
+                    "tool_use_id": msg.tool_call_id.clone().unwrap_or_default(),
+                    "content": msg.content,
+                })],
+            ),
+            _ => ("user", user_blocks(msg)),
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        match turns.last_mut() {
+            Some((last_role, last_blocks)) if last_role == role => last_blocks.extend(blocks),
+            _ => turns.push((role.to_string(), blocks)),
+        }
+    }
+    let turns = turns
+        .into_iter()
+        .map(|(role, content)| serde_json::json!({ "role": role, "content": content }))
+        .collect();
+    (system_parts.join("\n\n"), turns)
+}
+fn text_blocks(content: &str) -> Vec<serde_json::Value> {
+    if content.is_empty() {
+        return vec![];
+    }
+    vec![serde_json::json!({ "type": "text", "text": content })]
+}
+fn user_blocks(msg: &Message) -> Vec<serde_json::Value> {
+    let mut blocks = text_blocks(&msg.content);
+    blocks.extend(msg.images.iter().map(|image| {
+        serde_json::json!({
+            "type": "image",
+            "source": { "type": "base64", "media_type": image.mime_type, "data": image.data },
+        })
+    }));
+    blocks
+}
+fn assistant_blocks(msg: &Message) -> Vec<serde_json::Value> {
+    let mut blocks = text_blocks(&msg.content);
+    for (idx, tc) in msg.tool_calls.iter().enumerate() {
+        let id = if tc.id.trim().is_empty() {
+            format!("call_{}", idx + 1)
+        } else {
+            tc.id.clone()
+        };
+        let name = tc
+            .function
+            .as_ref()
+            .map(|f| f.name.clone())
+            .or_else(|| tc.name.clone())
+            .unwrap_or_default();
+        let input = tc
+            .arguments
+            .as_ref()
+            .map(|m| serde_json::json!(m))
+            .or_else(|| {
+                tc.function
+                    .as_ref()
+                    .and_then(|f| serde_json::from_str::<serde_json::Value>(&f.arguments).ok())
+            })
+            .filter(|v| v.is_object())
+            .unwrap_or_else(|| serde_json::json!({}));
+        blocks.push(serde_json::json!({
+            "type": "tool_use",
+            "id": id,
+            "name": name,
+            "input": input,
+        }));
+    }
+    blocks
+}
+fn parse_anthropic_response(result: &serde_json::Value) -> Result<LlmResponse> {
+    let blocks = result
+        .get("content")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| anyhow!("anthropic response missing 'content'"))?;
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    for (idx, block) in blocks.iter().enumerate() {
+        match block["type"].as_str().unwrap_or_default() {
+            "text" => content.push_str(block["text"].as_str().unwrap_or_default()),
+            "tool_use" => {
+                let id = block["id"]
+                    .as_str()
+                    .map(str::to_string)
+                    .filter(|s| !s.trim().is_empty())
+                    .unwrap_or_else(|| format!("call_{}", idx + 1));
+                let name = block["name"].as_str().unwrap_or_default().to_string();
+                let args: HashMap<String, serde_json::Value> =
+                    serde_json::from_value(block["input"].clone()).unwrap_or_default();
+                let args_str = serde_json::to_string(&args).unwrap_or_else(|_| "{}".to_string());
+                tool_calls.push(ToolCall {
+                    id,
+                    tool_type: "function".to_string(),
+                    function: Some(FunctionCall {
+                        name: name.clone(),
+                        arguments: args_str,
+                    }),
+                    name: Some(name),
+                    arguments: Some(args),
+                });
+            }
+            _ => {}
+        }
+    }
+    let finish_reason = result["stop_reason"].as_str().map(|s| match s {
+        "end_turn" | "stop_sequence" => "stop".to_string(),
+        "tool_use" => "tool_calls".to_string(),
+        "max_tokens" => "length".to_string(),
+        other => other.to_string(),
+    });
+    let usage = result.get("usage").and_then(|u| {
+        u.as_object().map(|_| {
+            let prompt_tokens = u["input_tokens"].as_i64().unwrap_or(0) as i32;
+            let completion_tokens = u["output_tokens"].as_i64().unwrap_or(0) as i32;
+            UsageInfo {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+                ..Default::default()
+            }
+        })
+    });
+    Ok(LlmResponse {
+        content,
+        tool_calls,
+        finish_reason,
+        usage,
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn system_prompt_is_hoisted_and_tool_results_merged() {
+        let messages = vec![
+            Message::system("be brief"),
+            Message::user("read it"),
+            Message {
+                role: "assistant".to_string(),
+                content: String::new(),
+                tool_calls: vec![
+                    ToolCall {
+                        id: "toolu_1".to_string(),
+                        tool_type: "function".to_string(),
+                        function: Some(FunctionCall {
+                            name: "read_file".to_string(),
+                            arguments: "{\"path\":\"a.md\"}".to_string(),
+                        }),
+                        name: Some("read_file".to_string()),
+                        arguments: None,
+                    },
+                    ToolCall {
+                        id: "toolu_2".to_string(),
+                        tool_type: "function".to_string(),
+                        function: None,
+                        name: Some("list_dir".to_string()),
+                        arguments: Some(HashMap::from([(
+                            "path".to_string(),
+                            serde_json::json!("."),
+                        )])),
+                    },
+                ],
+                tool_call_id: None,
+                images: vec![],
+            },
+            Message::tool("a", "toolu_1"),
+            Message::tool("b", "toolu_2"),
+        ];
+        let body = build_anthropic_request("claude-test", &messages, None, &HashMap::new());
+        assert_eq!(body["system"], "be brief");
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        let turns = body["messages"].as_array().expect("messages");
+        assert_eq!(turns.len(), 3);
+        assert_eq!(turns[1]["role"], "assistant");
+        assert_eq!(turns[1]["content"][0]["type"], "tool_use");
+        assert_eq!(turns[1]["content"][0]["input"]["path"], "a.md");
+        assert_eq!(turns[1]["content"][1]["input"]["path"], ".");
+        assert_eq!(turns[2]["role"], "user");
+        assert_eq!(turns[2]["content"].as_array().map(Vec::len), Some(2));
+        assert_eq!(turns[2]["content"][1]["tool_use_id"], "toolu_2");
+    }
+    #[test]
+    fn parse_tool_use_and_usage() {
+        let payload = serde_json::json!({
+            "content": [
+                { "type": "text", "text": "checking" },
+                { "type": "tool_use", "id": "toolu_9", "name": "read_file", "input": { "path": "README.md" } }
+            ],
+            "stop_reason": "tool_use",
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        });
+        let parsed = parse_anthropic_response(&payload).expect("parse");
+        assert_eq!(parsed.content, "checking");
+        assert_eq!(parsed.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(parsed.tool_calls.len(), 1);
+        assert_eq!(parsed.tool_calls[0].id, "toolu_9");
+        assert_eq!(parsed.tool_calls[0].name.as_deref(), Some("read_file"));
+        let args = parsed.tool_calls[0].arguments.as_ref().expect("args");
+        assert_eq!(args["path"], "README.md");
+        let usage = parsed.usage.expect("usage");
+        assert_eq!(usage.total_tokens, 15);
+    }
+}
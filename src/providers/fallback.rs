@@ -0,0 +1,251 @@
+use super::{LlmResponse, Message, Provider, StreamSink, ToolDefinition};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+pub(super) struct FallbackEntry {
+    pub(super) name: String,
+    /// `None` for the primary entry, which uses whatever model the caller asks for.
+    pub(super) model: Option<String>,
+    pub(super) provider: Arc<dyn Provider>,
+}
+/// Tries each configured backend in order until one produces a usable answer.
+pub(super) struct FallbackProvider {
+    entries: Vec<FallbackEntry>,
+    last_backend: Mutex<Option<String>>,
+}
+impl FallbackProvider {
+    pub(super) fn new(entries: Vec<FallbackEntry>) -> Self {
+        Self {
+            entries,
+            last_backend: Mutex::new(None),
+        }
+    }
+    fn label(entry: &FallbackEntry, model: &str) -> String {
+        format!("{}/{}", entry.name, entry.model.as_deref().unwrap_or(model))
+    }
+    fn attribute(entry: &FallbackEntry, model: &str, response: &mut LlmResponse) {
+        if let Some(usage) = response.usage.as_mut() {
+            usage.provider = Some(entry.name.clone());
+            usage.model = Some(entry.model.as_deref().unwrap_or(model).to_string());
+        }
+    }
+    fn record(&self, label: String, idx: usize) {
+        if idx > 0 {
+            tracing::warn!("fallback backend {} answered", label);
+        } else {
+            tracing::debug!("primary backend {} answered", label);
+        }
+        *self.last_backend.lock() = Some(label);
+    }
+}
+fn is_empty_response(response: &LlmResponse) -> bool {
+    response.content.trim().is_empty() && response.tool_calls.is_empty()
+}
+#[async_trait]
+impl Provider for FallbackProvider {
+    async fn chat_with_options(
+        &self,
+        messages: &mut Vec<Message>,
+        tools: Option<&[ToolDefinition]>,
+        model: &str,
+        options: HashMap<String, serde_json::Value>,
+    ) -> Result<LlmResponse> {
+        let mut last_err = None;
+        let mut empty = None;
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let entry_model = entry.model.as_deref().unwrap_or(model);
+            let label = Self::label(entry, model);
+            match entry
+                .provider
+                .chat_with_options(messages, tools, entry_model, options.clone())
+                .await
+            {
+                Ok(mut response) if is_empty_response(&response) => {
+                    tracing::warn!("backend {} returned an empty response", label);
+                    Self::attribute(entry, model, &mut response);
+                    empty = Some((label, idx, response));
+                }
+                Ok(mut response) => {
+                    Self::attribute(entry, model, &mut response);
+                    self.record(label, idx);
+                    return Ok(response);
+                }
+                Err(err) => {
+                    tracing::warn!("backend {} failed: {}", label, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        if let Some((label, idx, response)) = empty {
+            self.record(label, idx);
+            return Ok(response);
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("no provider backends configured")))
+    }
+    async fn chat_stream(
+        &self,
+        messages: &mut Vec<Message>,
+        tools: Option<&[ToolDefinition]>,
+        model: &str,
+        options: HashMap<String, serde_json::Value>,
+        on_delta: &StreamSink<'_>,
+    ) -> Result<LlmResponse> {
+        let mut last_err = None;
+        let mut empty = None;
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let entry_model = entry.model.as_deref().unwrap_or(model);
+            let label = Self::label(entry, model);
+            let emitted = AtomicBool::new(false);
+            let forward = |delta: &str| {
+                emitted.store(true, Ordering::SeqCst);
+                on_delta(delta);
+            };
+            let result = entry
+                .provider
+                .chat_stream(messages, tools, entry_model, options.clone(), &forward)
+                .await;
+            // Once text has reached the user, switching backends would splice two
+            // different answers together, so a mid-stream failure is final.
+            let emitted = emitted.load(Ordering::SeqCst);
+            match result {
+                Ok(mut response) if !emitted && is_empty_response(&response) => {
+                    tracing::warn!("backend {} returned an empty response", label);
+                    Self::attribute(entry, model, &mut response);
+                    empty = Some((label, idx, response));
+                }
+                Ok(mut response) => {
+                    Self::attribute(entry, model, &mut response);
+                    self.record(label, idx);
+                    return Ok(response);
+                }
+                Err(err) if emitted => return Err(err),
+                Err(err) => {
+                    tracing::warn!("backend {} failed: {}", label, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        if let Some((label, idx, response)) = empty {
+            self.record(label, idx);
+            return Ok(response);
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("no provider backends configured")))
+    }
+    /// Lists the primary backend's models; the fallbacks use fixed models.
+    async fn list_models(&self) -> Result<Vec<String>> {
+        match self.entries.first() {
+            Some(primary) => primary.provider.list_models().await,
+            None => Err(anyhow!("no provider backends configured")),
+        }
+    }
+    fn status(&self) -> Option<String> {
+        let chain: Vec<String> = self
+            .entries
+            .iter()
+            .map(|e| match e.model.as_deref() {
+                Some(model) => format!("{}/{}", e.name, model),
+                None => e.name.clone(),
+            })
+            .collect();
+        let last = self
+            .last_backend
+            .lock()
+            .clone()
+            .unwrap_or_else(|| "none yet".to_string());
+        Some(format!(
+            "Provider chain: {}\nLast answered by: {}",
+            chain.join(" → "),
+            last
+        ))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    struct Scripted {
+        reply: Option<&'static str>,
+        calls: Mutex<Vec<String>>,
+    }
+    #[async_trait]
+    impl Provider for Scripted {
+        async fn chat_with_options(
+            &self,
+            _messages: &mut Vec<Message>,
+            _tools: Option<&[ToolDefinition]>,
+            model: &str,
+            _options: HashMap<String, serde_json::Value>,
+        ) -> Result<LlmResponse> {
+            self.calls.lock().push(model.to_string());
+            let content = self.reply.ok_or_else(|| anyhow!("backend down"))?;
+            Ok(LlmResponse {
+                content: content.to_string(),
+                tool_calls: vec![],
+                finish_reason: Some("stop".to_string()),
+                usage: None,
+            })
+        }
+    }
+    fn entry(name: &str, model: Option<&str>, reply: Option<&'static str>) -> FallbackEntry {
+        FallbackEntry {
+            name: name.to_string(),
+            model: model.map(str::to_string),
+            provider: Arc::new(Scripted {
+                reply,
+                calls: Mutex::new(vec![]),
+            }),
+        }
+    }
+    #[tokio::test]
+    async fn falls_through_errors_and_empty_answers() {
+        let chain = FallbackProvider::new(vec![
+            entry("openrouter", None, None),
+            entry("groq", Some("llama-3.1-8b"), Some("  ")),
+            entry("deepseek", Some("deepseek-chat"), Some("hi")),
+        ]);
+        let mut msgs = vec![Message::user("ping")];
+        let response = chain
+            .chat_with_options(&mut msgs, None, "openai/gpt-4o", HashMap::new())
+            .await
+            .expect("deepseek should answer");
+        assert_eq!(response.content, "hi");
+        let status = chain.status().expect("status");
+        assert!(status.contains("openrouter → groq/llama-3.1-8b → deepseek/deepseek-chat"));
+        assert!(status.contains("Last answered by: deepseek/deepseek-chat"));
+    }
+    #[tokio::test]
+    async fn returns_last_error_when_every_backend_fails() {
+        let chain = FallbackProvider::new(vec![
+            entry("openrouter", None, None),
+            entry("groq", Some("llama"), None),
+        ]);
+        let mut msgs = vec![Message::user("ping")];
+        let err = chain
+            .chat_with_options(&mut msgs, None, "m", HashMap::new())
+            .await
+            .expect_err("all down");
+        assert!(err.to_string().contains("backend down"));
+    }
+    #[tokio::test]
+    async fn empty_answer_beats_later_failures_with_and_without_streaming() {
+        let chain = FallbackProvider::new(vec![
+            entry("openrouter", None, Some("")),
+            entry("groq", Some("llama"), None),
+        ]);
+        let mut msgs = vec![Message::user("ping")];
+        let response = chain
+            .chat_with_options(&mut msgs, None, "m", HashMap::new())
+            .await
+            .expect("empty answer");
+        assert_eq!(response.content, "");
+        let response = chain
+            .chat_stream(&mut msgs, None, "m", HashMap::new(), &|_| {})
+            .await
+            .expect("empty streamed answer");
+        assert_eq!(response.content, "");
+        let status = chain.status().expect("status");
+        assert!(status.contains("Last answered by: openrouter/m"));
+    }
+}
@@ -0,0 +1,540 @@
+use super::retry::{RetryPolicy, send_with_retry};
+use super::{FunctionCall, LlmResponse, Message, Provider, ToolCall, ToolDefinition, UsageInfo};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::collections::HashMap;
+const UNSUPPORTED_SCHEMA_KEYS: &[&str] = &["$schema", "additionalProperties", "default"];
+pub(super) struct GeminiProvider {
+    api_key: String,
+    base_url: String,
+    extra_headers: HashMap<String, String>,
+    retry: RetryPolicy,
+    client: reqwest::Client,
+}
+impl GeminiProvider {
+    pub(super) fn new(
+        api_key: String,
+        base_url: String,
+        extra_headers: HashMap<String, String>,
+        retry: RetryPolicy,
+        client: reqwest::Client,
+    ) -> Self {
+        Self {
+            api_key,
+            base_url,
+            extra_headers,
+            retry,
+            client,
+        }
+    }
+    async fn make_request(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        options: &HashMap<String, serde_json::Value>,
+    ) -> Result<LlmResponse> {
+        let model = model.strip_prefix("gemini/").unwrap_or(model);
+        let model = model.strip_prefix("models/").unwrap_or(model);
+        let body = build_gemini_request(messages, tools, options);
+        let url = format!("{}/models/{}:generateContent", self.base_url, model);
+        let resp = send_with_retry(&self.retry, "gemini", || {
+            let mut req = self
+                .client
+                .post(&url)
+                .header("x-goog-api-key", &self.api_key)
+                .header("Content-Type", "application/json");
+            for (k, v) in &self.extra_headers {
+                req = req.header(k, v);
+            }
+            req.json(&body)
+        })
+        .await?;
+        let result: serde_json::Value = resp.json().await?;
+        parse_gemini_response(&result)
+    }
+}
+#[async_trait]
+impl Provider for GeminiProvider {
+    async fn chat_with_options(
+        &self,
+        messages: &mut Vec<Message>,
+        tools: Option<&[ToolDefinition]>,
+        model: &str,
+        options: HashMap<String, serde_json::Value>,
+    ) -> Result<LlmResponse> {
+        self.make_request(model, messages, tools, &options).await
+    }
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let url = format!("{}/models?pageSize=1000", self.base_url);
+        let resp = send_with_retry(&self.retry, "gemini", || {
+            let mut req = self
+                .client
+                .get(&url)
+                .header("x-goog-api-key", &self.api_key);
+            for (k, v) in &self.extra_headers {
+                req = req.header(k, v);
+            }
+            req
+        })
+        .await?;
+        let result: serde_json::Value = resp.json().await?;
+        Ok(super::model_ids(&result["models"], "name")
+            .into_iter()
+            .map(|name| name.trim_start_matches("models/").to_string())
+            .collect())
+    }
+}
+fn build_gemini_request(
+    messages: &[Message],
+    tools: Option<&[ToolDefinition]>,
+    options: &HashMap<String, serde_json::Value>,
+) -> serde_json::Value {
+    let (system, contents) = convert_messages(messages);
+    let mut body = serde_json::json!({ "contents": contents });
+    if !system.is_empty() {
+        body["systemInstruction"] = serde_json::json!({ "parts": [{ "text": system }] });
+    }
+    if let Some(tool_defs) = tools
+        && !tool_defs.is_empty()
+    {
+        let declarations: Vec<serde_json::Value> = tool_defs
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "name": t.function.name(),
+                    "description": t.function.description(),
+                    "parameters": sanitize_schema(t.function.parameters()),
+                })
+            })
+            .collect();
+        body["tools"] = serde_json::json!([{ "functionDeclarations": declarations }]);
+    }
+    let mut generation = serde_json::Map::new();
+    if let Some(temp) = options.get("temperature") {
+        generation.insert("temperature".to_string(), temp.clone());
+    }
+    if let Some(max_tokens) = options.get("max_tokens") {
+        generation.insert("maxOutputTokens".to_string(), max_tokens.clone());
+    }
+    if let Some(top_p) = options.get("top_p") {
+        generation.insert("topP".to_string(), top_p.clone());
+    }
+    if let Some(stop) = options.get("stop") {
+        generation.insert("stopSequences".to_string(), stop.clone());
+    }
+    if !generation.is_empty() {
+        body["generationConfig"] = serde_json::Value::Object(generation);
+    }
+    body
+}
+fn convert_messages(messages: &[Message]) -> (String, Vec<serde_json::Value>) {
+    let mut system_parts: Vec<&str> = Vec::new();
+    let mut call_names: HashMap<String, String> = HashMap::new();
+    let mut turns: Vec<(&'static str, Vec<serde_json::Value>)> = Vec::new();
+    for msg in messages {
+        let (role, parts) = match msg.role.as_str() {
+            "system" => {
+                if !msg.content.trim().is_empty() {
+                    system_parts.push(&msg.content);
+                }
+                continue;
+            }
+            "assistant" => {
+                let mut parts = text_parts(&msg.content);
+                for tc in &msg.tool_calls {
+                    let name = tool_call_name(tc);
+                    call_names.insert(tc.id.clone(), name.clone());
+                    parts.push(serde_json::json!({
+                        "functionCall": { "name": name, "args": tool_call_args(tc) }
+                    }));
+                }
+                ("model", parts)
+            }
+            "tool" => {
+                let id = msg.tool_call_id.clone().unwrap_or_default();
+                let name = call_names.get(&id).cloned().unwrap_or(id);
+                (
+                    "user",
+                    vec![serde_json::json!({
+                        "functionResponse": {
+                            "name": name,
+                            "response": { "content": msg.content },
+                        }
+                    })],
+                )
+            }
+            _ => {
+                let mut parts = text_parts(&msg.content);
+                parts.extend(msg.images.iter().map(|image| {
+                    serde_json::json!({
+                        "inlineData": { "mimeType": image.mime_type, "data": image.data }
+                    })
+                }));
+                ("user", parts)
+            }
+        };
+        if parts.is_empty() {
+            continue;
+        }
+        match turns.last_mut() {
+            Some((last_role, last_parts)) if *last_role == role => last_parts.extend(parts),
+            _ => turns.push((role, parts)),
+        }
+    }
+    let contents = turns
+        .into_iter()
+        .map(|(role, parts)| serde_json::json!({ "role": role, "parts": parts }))
+        .collect();
+    (system_parts.join("\n\n"), contents)
+}
+fn text_parts(content: &str) -> Vec<serde_json::Value> {
+    if content.is_empty() {
+        return vec![];
+    }
+    vec![serde_json::json!({ "text": content })]
+}
+fn tool_call_name(tc: &ToolCall) -> String {
+    tc.function
+        .as_ref()
+        .map(|f| f.name.clone())
+        .or_else(|| tc.name.clone())
+        .unwrap_or_default()
+}
+fn tool_call_args(tc: &ToolCall) -> serde_json::Value {
+    tc.arguments
+        .as_ref()
+        .map(|m| serde_json::json!(m))
+        .or_else(|| {
+            tc.function
+                .as_ref()
+                .and_then(|f| serde_json::from_str::<serde_json::Value>(&f.arguments).ok())
+        })
+        .filter(|v| v.is_object())
+        .unwrap_or_else(|| serde_json::json!({}))
+}
+fn sanitize_schema(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .filter(|(k, _)| !UNSUPPORTED_SCHEMA_KEYS.contains(&k.as_str()))
+                .map(|(k, v)| match (k.as_str(), v) {
+                    ("properties", serde_json::Value::Object(props)) => {
+                        let props = props
+                            .into_iter()
+                            .map(|(name, schema)| (name, sanitize_schema(schema)))
+                            .collect();
+                        (k, serde_json::Value::Object(props))
+                    }
+                    (_, v) => (k, sanitize_schema(v)),
+                })
+                .collect(),
+        ),
+        serde_json::Value::Array(arr) => {
+            serde_json::Value::Array(arr.into_iter().map(sanitize_schema).collect())
+        }
+        other => other,
+    }
+}
+fn parse_gemini_response(result: &serde_json::Value) -> Result<LlmResponse> {
+    let candidates = result
+        .get("candidates")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| anyhow!("gemini response missing 'candidates'"))?;
+    let usage = result.get("usageMetadata").and_then(|u| {
+        u.as_object().map(|_| UsageInfo {
+            prompt_tokens: u["promptTokenCount"].as_i64().unwrap_or(0) as i32,
+            completion_tokens: u["candidatesTokenCount"].as_i64().unwrap_or(0) as i32,
+            total_tokens: u["totalTokenCount"].as_i64().unwrap_or(0) as i32,
+            ..Default::default()
+        })
+    });
+    let Some(candidate) = candidates.first() else {
+        return Ok(LlmResponse {
+            content: String::new(),
+            tool_calls: vec![],
+            finish_reason: Some("stop".to_string()),
+            usage,
+        });
+    };
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    if let Some(parts) = candidate["content"]["parts"].as_array() {
+        for part in parts {
+            if let Some(text) = part["text"].as_str() {
+                content.push_str(text);
+            }
+            if let Some(call) = part.get("functionCall") {
+                let id = call["id"]
+                    .as_str()
+                    .map(str::to_string)
+                    .filter(|s| !s.trim().is_empty())
+                    .unwrap_or_else(|| format!("call_{}", tool_calls.len() + 1));
+                let name = call["name"].as_str().unwrap_or_default().to_string();
+                let args: HashMap<String, serde_json::Value> =
+                    serde_json::from_value(call["args"].clone()).unwrap_or_default();
+                let args_str = serde_json::to_string(&args).unwrap_or_else(|_| "{}".to_string());
+                tool_calls.push(ToolCall {
+                    id,
+                    tool_type: "function".to_string(),
+                    function: Some(FunctionCall {
+                        name: name.clone(),
+                        arguments: args_str,
+                    }),
+                    name: Some(name),
+                    arguments: Some(args),
+                });
+            }
+        }
+    }
+    let finish_reason = if !tool_calls.is_empty() {
+        Some("tool_calls".to_string())
+    } else {
+        candidate["finishReason"].as_str().map(|s| match s {
+            "STOP" => "stop".to_string(),
+            "MAX_TOKENS" => "length".to_string(),
+            other => other.to_ascii_lowercase(),
+        })
+    };
+    Ok(LlmResponse {
+        content,
+        tool_calls,
+        finish_reason,
+        usage,
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::providers::create_provider;
+    use axum::extract::{Path, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::Arc;
+    use tokio::sync::{Mutex, oneshot};
+    #[derive(Clone, Default)]
+    struct MockState {
+        requests: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+    async fn mock_generate(
+        State(state): State<MockState>,
+        Path(model_action): Path<String>,
+        headers: HeaderMap,
+        Json(body): Json<serde_json::Value>,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let key = headers
+            .get("x-goog-api-key")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if key != "gemini-key" {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({ "error": { "message": "API key not valid" } })),
+            );
+        }
+        state
+            .requests
+            .lock()
+            .await
+            .push((model_action.clone(), body.clone()));
+        let last_part = body["contents"]
+            .as_array()
+            .and_then(|c| c.last())
+            .and_then(|c| c["parts"].as_array())
+            .and_then(|p| p.last())
+            .cloned()
+            .unwrap_or_default();
+        if let Some(resp) = last_part.get("functionResponse") {
+            return (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "candidates": [{
+                        "content": {
+                            "role": "model",
+                            "parts": [{ "text": format!("result: {}", resp["response"]["content"].as_str().unwrap_or_default()) }]
+                        },
+                        "finishReason": "STOP"
+                    }]
+                })),
+            );
+        }
+        if model_action.contains("tool") {
+            return (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "candidates": [{
+                        "content": {
+                            "role": "model",
+                            "parts": [{ "functionCall": { "name": "read_file", "args": { "path": "README.md" } } }]
+                        },
+                        "finishReason": "STOP"
+                    }]
+                })),
+            );
+        }
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "candidates": [{
+                    "content": { "role": "model", "parts": [{ "text": "hello from gemini" }] },
+                    "finishReason": "STOP"
+                }],
+                "usageMetadata": {
+                    "promptTokenCount": 4,
+                    "candidatesTokenCount": 3,
+                    "totalTokenCount": 7
+                }
+            })),
+        )
+    }
+    async fn start_mock_server() -> (String, MockState, oneshot::Sender<()>) {
+        let state = MockState::default();
+        let app = Router::new()
+            .route("/models/{model_action}", post(mock_generate))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+                let _ = rx.await;
+            });
+            let _ = server.await;
+        });
+        (format!("http://{}", addr), state, tx)
+    }
+    fn gemini_config(base: String, key: &str) -> Config {
+        let mut cfg = Config::default();
+        cfg.agents.defaults.provider = "gemini".to_string();
+        cfg.providers.gemini.api_key = Some(key.to_string());
+        cfg.providers.gemini.api_base = Some(base);
+        cfg
+    }
+    #[test]
+    fn tool_definitions_become_function_declarations() {
+        let tools = vec![ToolDefinition {
+            tool_type: "function".to_string(),
+            function: crate::providers::ToolFunctionDefinition {
+                name: "read_file".to_string(),
+                description: "Read a file".to_string(),
+                parameters: serde_json::json!({
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": { "path": { "type": "string", "default": "." } },
+                    "required": ["path"]
+                }),
+            },
+        }];
+        let body = build_gemini_request(&[Message::user("hi")], Some(&tools), &HashMap::new());
+        let decl = &body["tools"][0]["functionDeclarations"][0];
+        assert_eq!(decl["name"], "read_file");
+        assert!(decl["parameters"].get("additionalProperties").is_none());
+        assert!(
+            decl["parameters"]["properties"]["path"]
+                .get("default")
+                .is_none()
+        );
+        assert_eq!(decl["parameters"]["required"][0], "path");
+    }
+    #[test]
+    fn tool_results_map_back_to_function_names() {
+        let messages = vec![
+            Message::system("sys"),
+            Message::user("read"),
+            Message {
+                role: "assistant".to_string(),
+                content: String::new(),
+                tool_calls: vec![ToolCall {
+                    id: "call_1".to_string(),
+                    tool_type: "function".to_string(),
+                    function: Some(FunctionCall {
+                        name: "read_file".to_string(),
+                        arguments: "{\"path\":\"a.md\"}".to_string(),
+                    }),
+                    name: Some("read_file".to_string()),
+                    arguments: None,
+                }],
+                tool_call_id: None,
+                images: vec![],
+            },
+            Message::tool("contents", "call_1"),
+        ];
+        let mut options = HashMap::new();
+        options.insert("max_tokens".to_string(), serde_json::json!(512));
+        let body = build_gemini_request(&messages, None, &options);
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "sys");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 512);
+        let contents = body["contents"].as_array().expect("contents");
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(
+            contents[1]["parts"][0]["functionCall"]["args"]["path"],
+            "a.md"
+        );
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"]["name"],
+            "read_file"
+        );
+    }
+    #[tokio::test]
+    async fn gemini_text_response_and_usage() {
+        let (base, state, shutdown) = start_mock_server().await;
+        let provider = create_provider(&gemini_config(base, "gemini-key")).expect("provider");
+        let mut msgs = vec![Message::system("sys"), Message::user("ping")];
+        let response = provider
+            .chat_with_options(&mut msgs, None, "gemini/gemini-2.0-flash", HashMap::new())
+            .await
+            .expect("chat should succeed");
+        assert_eq!(response.content, "hello from gemini");
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(response.usage.map(|u| u.total_tokens), Some(7));
+        let requests = state.requests.lock().await;
+        assert_eq!(requests[0].0, "gemini-2.0-flash:generateContent");
+        let _ = shutdown.send(());
+    }
+    #[tokio::test]
+    async fn gemini_tool_call_roundtrip() {
+        let (base, _state, shutdown) = start_mock_server().await;
+        let provider = create_provider(&gemini_config(base, "gemini-key")).expect("provider");
+        let mut msgs = vec![Message::user("read README")];
+        let response = provider
+            .chat_with_options(&mut msgs, None, "gemini-tool-model", HashMap::new())
+            .await
+            .expect("chat should succeed");
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!(response.tool_calls[0].id, "call_1");
+        assert_eq!(response.tool_calls[0].name.as_deref(), Some("read_file"));
+        msgs.push(Message {
+            role: "assistant".to_string(),
+            content: response.content.clone(),
+            tool_calls: response.tool_calls.clone(),
+            tool_call_id: None,
+            images: vec![],
+        });
+        msgs.push(Message::tool("hi", &response.tool_calls[0].id));
+        let response = provider
+            .chat_with_options(&mut msgs, None, "gemini-tool-model", HashMap::new())
+            .await
+            .expect("chat should succeed");
+        assert_eq!(response.content, "result: hi");
+        assert!(response.tool_calls.is_empty());
+        let _ = shutdown.send(());
+    }
+    #[tokio::test]
+    async fn gemini_invalid_key_surfaces_status() {
+        let (base, _state, shutdown) = start_mock_server().await;
+        let provider = create_provider(&gemini_config(base, "wrong")).expect("provider");
+        let mut msgs = vec![Message::user("ping")];
+        let err = provider
+            .chat_with_options(&mut msgs, None, "gemini-2.0-flash", HashMap::new())
+            .await
+            .expect_err("expected auth failure");
+        assert!(err.to_string().contains("403"));
+        let _ = shutdown.send(());
+    }
+}
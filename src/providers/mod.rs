@@ -1,9 +1,15 @@
+mod anthropic;
+mod fallback;
+mod gemini;
+mod retry;
+mod stream;
 pub mod types;
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
 pub use types::*;
+pub type StreamSink<'a> = dyn Fn(&str) + Send + Sync + 'a;
 #[async_trait]
 pub trait Provider: Send + Sync {
     async fn chat_with_options(
@@ -13,6 +19,30 @@ pub trait Provider: Send + Sync {
         model: &str,
         options: HashMap<String, serde_json::Value>,
     ) -> Result<LlmResponse>;
+    async fn chat_stream(
+        &self,
+        messages: &mut Vec<Message>,
+        tools: Option<&[ToolDefinition]>,
+        model: &str,
+        options: HashMap<String, serde_json::Value>,
+        on_delta: &StreamSink<'_>,
+    ) -> Result<LlmResponse> {
+        let response = self
+            .chat_with_options(messages, tools, model, options)
+            .await?;
+        if !response.content.is_empty() {
+            on_delta(&response.content);
+        }
+        Ok(response)
+    }
+    /// Human-readable backend details for `/status`, if the provider has any.
+    fn status(&self) -> Option<String> {
+        None
+    }
+    /// Model ids the backend offers, for `/list models`.
+    async fn list_models(&self) -> Result<Vec<String>> {
+        Err(anyhow!("this provider can't list its models"))
+    }
 }
 #[derive(Debug, Clone, Copy)]
 enum ProviderKind {
@@ -21,12 +51,17 @@ enum ProviderKind {
     Groq,
     Zhipu,
     DeepSeek,
+    Anthropic,
+    Gemini,
+    Custom,
 }
 struct HttpProvider {
     api_key: String,
     base_url: String,
     extra_headers: HashMap<String, String>,
-    _kind: ProviderKind,
+    kind: ProviderKind,
+    retry: retry::RetryPolicy,
+    model_prefix: Option<String>,
     client: reqwest::Client,
 }
 impl HttpProvider {
@@ -35,28 +70,38 @@ impl HttpProvider {
         base_url: String,
         extra_headers: HashMap<String, String>,
         kind: ProviderKind,
+        retry: retry::RetryPolicy,
+        client: reqwest::Client,
     ) -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .connect_timeout(std::time::Duration::from_secs(10))
-            .pool_max_idle_per_host(4)
-            .build()
-            .unwrap_or_default();
         Self {
             api_key,
             base_url,
             extra_headers,
-            _kind: kind,
+            kind,
+            retry,
+            model_prefix: None,
             client,
         }
     }
-    async fn make_request(
+    /// Strips `<prefix>/` from model names, so `ollama/llama3.1` reaches a
+    /// custom endpoint as `llama3.1`.
+    fn with_model_prefix(mut self, prefix: &str) -> Self {
+        self.model_prefix = Some(prefix.to_string());
+        self
+    }
+    fn wire_model<'m>(&self, model: &'m str) -> &'m str {
+        match (self.model_prefix.as_deref(), model.split_once('/')) {
+            (Some(prefix), Some((head, rest))) if head.eq_ignore_ascii_case(prefix) => rest,
+            _ => model,
+        }
+    }
+    fn build_body(
         &self,
         model: &str,
         messages: &[Message],
         tools: Option<&[ToolDefinition]>,
         options: &HashMap<String, serde_json::Value>,
-    ) -> Result<LlmResponse> {
+    ) -> serde_json::Value {
         let messages_json: Vec<serde_json::Value> = messages
             .iter()
             .map(normalize_message_for_provider)
@@ -90,32 +135,135 @@ impl HttpProvider {
         if let Some(max_tokens) = options.get("max_tokens") {
             body["max_tokens"] = max_tokens.clone();
         }
-        let mut req = self
-            .client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json");
-        for (k, v) in &self.extra_headers {
-            req = req.header(k, v);
+        for key in ["top_p", "stop"] {
+            if let Some(value) = options.get(key) {
+                body[key] = value.clone();
+            }
         }
-        let resp = req.json(&body).send().await?;
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            return Err(anyhow!(
-                "provider API request failed (status={}): {}",
-                status,
-                body
-            ));
+        if let Some(effort) = options.get("reasoning_effort") {
+            match self.kind {
+                ProviderKind::OpenRouter => {
+                    body["reasoning"] = serde_json::json!({ "effort": effort });
+                }
+                _ => body["reasoning_effort"] = effort.clone(),
+            }
         }
+        body
+    }
+    async fn send(&self, body: &serde_json::Value) -> Result<reqwest::Response> {
+        let url = format!("{}/chat/completions", self.base_url);
+        let resp = retry::send_with_retry(&self.retry, "provider", || {
+            let mut req = self
+                .client
+                .post(&url)
+                .header("Content-Type", "application/json");
+            if !self.api_key.is_empty() {
+                req = req.bearer_auth(&self.api_key);
+            }
+            for (k, v) in &self.extra_headers {
+                req = req.header(k, v);
+            }
+            req.json(body)
+        })
+        .await?;
+        Ok(resp)
+    }
+    async fn make_request(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        options: &HashMap<String, serde_json::Value>,
+    ) -> Result<LlmResponse> {
+        let body = self.build_body(self.wire_model(model), messages, tools, options);
+        let resp = self.send(&body).await?;
         let result: serde_json::Value = resp.json().await?;
         parse_openai_compatible_response(&result)
     }
+    async fn make_stream_request(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        options: &HashMap<String, serde_json::Value>,
+        on_delta: &StreamSink<'_>,
+    ) -> Result<LlmResponse> {
+        let mut body = self.build_body(self.wire_model(model), messages, tools, options);
+        body["stream"] = serde_json::json!(true);
+        body["stream_options"] = serde_json::json!({ "include_usage": true });
+        let mut resp = self.send(&body).await?;
+        let is_sse = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        if !is_sse {
+            let result: serde_json::Value = resp.json().await?;
+            let response = parse_openai_compatible_response(&result)?;
+            if !response.content.is_empty() {
+                on_delta(&response.content);
+            }
+            return Ok(response);
+        }
+        let mut decoder = stream::SseDecoder::default();
+        let mut acc = stream::StreamAccumulator::default();
+        let mut done = false;
+        while !done {
+            // The client's read timeout ends a stream that goes quiet; the
+            // whole reply may take as long as it keeps arriving.
+            let chunk = resp.chunk().await.map_err(|err| {
+                if err.is_timeout() {
+                    anyhow!("provider stream stalled: no data for too long")
+                } else {
+                    err.into()
+                }
+            })?;
+            let events = match chunk {
+                Some(bytes) => decoder.feed(&bytes),
+                None => {
+                    done = true;
+                    decoder.finish()
+                }
+            };
+            for data in events {
+                if data.trim() == "[DONE]" {
+                    done = true;
+                    break;
+                }
+                let chunk: serde_json::Value = match serde_json::from_str(&data) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        tracing::debug!("skipping malformed stream chunk: {}", err);
+                        continue;
+                    }
+                };
+                if let Some(err) = chunk.get("error") {
+                    return Err(anyhow!("provider stream error: {}", err));
+                }
+                if let Some(text) = acc.push_chunk(&chunk) {
+                    on_delta(&text);
+                }
+            }
+        }
+        Ok(acc.finish())
+    }
 }
 fn normalize_message_for_provider(message: &Message) -> serde_json::Value {
+    let content = if message.images.is_empty() {
+        serde_json::json!(message.content)
+    } else {
+        let mut parts = Vec::with_capacity(message.images.len() + 1);
+        if !message.content.is_empty() {
+            parts.push(serde_json::json!({ "type": "text", "text": message.content }));
+        }
+        parts.extend(message.images.iter().map(|image| {
+            serde_json::json!({ "type": "image_url", "image_url": { "url": image.data_url() } })
+        }));
+        serde_json::Value::Array(parts)
+    };
     let mut out = serde_json::json!({
         "role": message.role,
-        "content": message.content,
+        "content": content,
     });
     if let Some(tool_call_id) = message.tool_call_id.as_ref()
         && !tool_call_id.trim().is_empty()
@@ -175,6 +323,75 @@ impl Provider for HttpProvider {
     ) -> Result<LlmResponse> {
         self.make_request(model, messages, tools, &options).await
     }
+    async fn chat_stream(
+        &self,
+        messages: &mut Vec<Message>,
+        tools: Option<&[ToolDefinition]>,
+        model: &str,
+        options: HashMap<String, serde_json::Value>,
+        on_delta: &StreamSink<'_>,
+    ) -> Result<LlmResponse> {
+        self.make_stream_request(model, messages, tools, &options, on_delta)
+            .await
+    }
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let url = format!("{}/models", self.base_url);
+        let resp = retry::send_with_retry(&self.retry, "provider", || {
+            let mut req = self.client.get(&url);
+            if !self.api_key.is_empty() {
+                req = req.bearer_auth(&self.api_key);
+            }
+            for (k, v) in &self.extra_headers {
+                req = req.header(k, v);
+            }
+            req
+        })
+        .await?;
+        let result: serde_json::Value = resp.json().await?;
+        Ok(model_ids(&result["data"], "id"))
+    }
+}
+/// Collects `field` from every entry of a model-list array.
+fn model_ids(list: &serde_json::Value, field: &str) -> Vec<String> {
+    list.as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|m| m[field].as_str())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+fn openai_tool_call(idx: usize, id: &str, tool_type: &str, name: &str, args_str: &str) -> ToolCall {
+    let id = Some(id)
+        .filter(|s| !s.trim().is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("call_{}", idx + 1));
+    let tool_type = Some(tool_type)
+        .filter(|s| !s.trim().is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| "function".to_string());
+    let args: HashMap<String, serde_json::Value> =
+        serde_json::from_str(args_str).unwrap_or_default();
+    ToolCall {
+        id,
+        tool_type,
+        function: Some(FunctionCall {
+            name: name.to_string(),
+            arguments: args_str.to_string(),
+        }),
+        name: Some(name.to_string()),
+        arguments: Some(args),
+    }
+}
+fn parse_openai_usage(u: &serde_json::Value) -> Option<UsageInfo> {
+    u.as_object().map(|_| UsageInfo {
+        prompt_tokens: u["prompt_tokens"].as_i64().unwrap_or(0) as i32,
+        completion_tokens: u["completion_tokens"].as_i64().unwrap_or(0) as i32,
+        total_tokens: u["total_tokens"].as_i64().unwrap_or(0) as i32,
+        ..Default::default()
+    })
 }
 fn parse_openai_compatible_response(result: &serde_json::Value) -> Result<LlmResponse> {
     let choices = result
@@ -194,39 +411,16 @@ fn parse_openai_compatible_response(result: &serde_json::Value) -> Result<LlmRes
     let mut tool_calls = Vec::new();
     if let Some(tc) = message["tool_calls"].as_array() {
         for (idx, t) in tc.iter().enumerate() {
-            let id = t["id"]
-                .as_str()
-                .map(str::to_string)
-                .filter(|s| !s.trim().is_empty())
-                .unwrap_or_else(|| format!("call_{}", idx + 1));
-            let name = t["function"]["name"].as_str().unwrap_or("").to_string();
-            let args_str = t["function"]["arguments"].as_str().unwrap_or("{}");
-            let args: HashMap<String, serde_json::Value> =
-                serde_json::from_str(args_str).unwrap_or_default();
-            let tool_type = t["type"]
-                .as_str()
-                .map(str::to_string)
-                .filter(|s| !s.trim().is_empty())
-                .unwrap_or_else(|| "function".to_string());
-            tool_calls.push(ToolCall {
-                id,
-                tool_type,
-                function: Some(FunctionCall {
-                    name: name.clone(),
-                    arguments: args_str.to_string(),
-                }),
-                name: Some(name),
-                arguments: Some(args),
-            });
+            tool_calls.push(openai_tool_call(
+                idx,
+                t["id"].as_str().unwrap_or(""),
+                t["type"].as_str().unwrap_or(""),
+                t["function"]["name"].as_str().unwrap_or(""),
+                t["function"]["arguments"].as_str().unwrap_or("{}"),
+            ));
         }
     }
-    let usage = result.get("usage").and_then(|u| {
-        u.as_object().map(|_| UsageInfo {
-            prompt_tokens: u["prompt_tokens"].as_i64().unwrap_or(0) as i32,
-            completion_tokens: u["completion_tokens"].as_i64().unwrap_or(0) as i32,
-            total_tokens: u["total_tokens"].as_i64().unwrap_or(0) as i32,
-        })
-    });
+    let usage = result.get("usage").and_then(parse_openai_usage);
     Ok(LlmResponse {
         content,
         tool_calls,
@@ -234,27 +428,118 @@ fn parse_openai_compatible_response(result: &serde_json::Value) -> Result<LlmRes
         usage,
     })
 }
-use crate::config::{Config, ProviderConfig};
+use crate::config::{AgentDefaults, Config, ProviderConfig};
 pub fn create_provider(config: &Config) -> Result<Arc<dyn Provider>> {
     let provider_name = select_provider(config);
-    if provider_name == "anthropic" || provider_name == "claude" {
-        return Err(anyhow!(
-            "provider '{}' is not in current MVP (use OpenAI-compatible providers)",
-            provider_name
-        ));
+    let fallbacks = &config.agents.defaults.fallbacks;
+    if fallbacks.is_empty() {
+        return build_provider(config, &provider_name).map(|(provider, _)| provider);
+    }
+    let mut entries = Vec::with_capacity(fallbacks.len() + 1);
+    let mut last_err = None;
+    let candidates = std::iter::once((provider_name, None)).chain(
+        fallbacks
+            .iter()
+            .map(|f| (f.provider.trim().to_lowercase(), Some(f.model.trim()))),
+    );
+    for (name, model) in candidates {
+        match build_provider(config, &name) {
+            Ok((provider, model_default)) => {
+                let model = model.map(|m| {
+                    if m.is_empty() {
+                        model_default.to_string()
+                    } else {
+                        m.to_string()
+                    }
+                });
+                entries.push(fallback::FallbackEntry {
+                    name,
+                    model,
+                    provider,
+                });
+            }
+            Err(err) => {
+                tracing::warn!("skipping provider '{}' in fallback chain: {}", name, err);
+                last_err = Some(err);
+            }
+        }
+    }
+    if entries.is_empty() {
+        return Err(last_err.unwrap_or_else(|| anyhow!("no usable providers configured")));
     }
-    let (provider_cfg, base_default, _model_default, kind, extra_headers, env_names) =
-        provider_meta(config, &provider_name)?;
-    let api_key = read_api_key(provider_cfg, &env_names)?;
+    Ok(Arc::new(fallback::FallbackProvider::new(entries)))
+}
+/// Model the agent should use by default. A custom endpoint's own `model`
+/// applies when `agents.defaults.model` was left at its built-in value.
+pub fn default_model(config: &Config) -> String {
+    let configured = config.agents.defaults.model.trim();
+    let untouched = configured.is_empty() || configured == AgentDefaults::default().model;
+    if untouched
+        && let Some((_, custom)) = find_custom(config, &select_provider(config))
+        && let Some(model) = custom.model.as_deref().filter(|m| !m.trim().is_empty())
+    {
+        return model.to_string();
+    }
+    configured.to_string()
+}
+/// Builds a single provider (no fallback chain) and returns it with its
+/// default model.
+pub fn build_provider<'a>(
+    config: &'a Config,
+    provider_name: &str,
+) -> Result<(Arc<dyn Provider>, &'a str)> {
+    let (provider_cfg, base_default, model_default, kind, mut extra_headers, env_names) =
+        provider_meta(config, provider_name)?;
+    let api_key = match kind {
+        ProviderKind::Custom => read_api_key(provider_cfg, &env_names).unwrap_or_default(),
+        _ => read_api_key(provider_cfg, &env_names)?,
+    };
     let base_url = provider_cfg
         .api_base
         .clone()
         .filter(|s| !s.trim().is_empty())
         .unwrap_or_else(|| base_default.to_string());
-    let provider = HttpProvider::new(api_key, base_url, extra_headers, kind);
-    Ok(Arc::new(provider))
+    if base_url.is_empty() {
+        return Err(anyhow!(
+            "custom provider '{}' requires api_base",
+            provider_name
+        ));
+    }
+    extra_headers.extend(provider_cfg.headers.clone());
+    let retry = retry::RetryPolicy::from(&provider_cfg.retry);
+    let client = crate::http::build_client(provider_cfg.proxy.as_deref(), &provider_cfg.http)
+        .map_err(|err| anyhow!("provider '{}': {}", provider_name, err))?;
+    let provider: Arc<dyn Provider> = match kind {
+        ProviderKind::Anthropic => Arc::new(anthropic::AnthropicProvider::new(
+            api_key,
+            base_url,
+            extra_headers,
+            retry,
+            client,
+        )),
+        ProviderKind::Gemini => Arc::new(gemini::GeminiProvider::new(
+            api_key,
+            base_url,
+            extra_headers,
+            retry,
+            client,
+        )),
+        ProviderKind::Custom => Arc::new(
+            HttpProvider::new(api_key, base_url, extra_headers, kind, retry, client)
+                .with_model_prefix(provider_name),
+        ),
+        _ => Arc::new(HttpProvider::new(
+            api_key,
+            base_url,
+            extra_headers,
+            kind,
+            retry,
+            client,
+        )),
+    };
+    Ok((provider, model_default))
 }
-fn select_provider(config: &Config) -> String {
+pub fn select_provider(config: &Config) -> String {
     let explicit = config.agents.defaults.provider.trim().to_lowercase();
     if !explicit.is_empty() {
         return explicit;
@@ -273,6 +558,12 @@ fn select_provider(config: &Config) -> String {
         "zhipu".to_string()
     } else if config.providers.deepseek.api_key.is_some() {
         "deepseek".to_string()
+    } else if config.providers.anthropic.api_key.is_some() {
+        "anthropic".to_string()
+    } else if config.providers.gemini.api_key.is_some() {
+        "gemini".to_string()
+    } else if let Some(name) = config.providers.custom.keys().min() {
+        name.to_lowercase()
     } else {
         "openrouter".to_string()
     }
@@ -280,7 +571,7 @@ fn select_provider(config: &Config) -> String {
 type ProviderMeta<'a> = (
     &'a ProviderConfig,
     &'static str,
-    &'static str,
+    &'a str,
     ProviderKind,
     HashMap<String, String>,
     Vec<&'static str>,
@@ -335,9 +626,43 @@ fn provider_meta<'a>(config: &'a Config, provider_name: &str) -> Result<Provider
             HashMap::new(),
             vec!["DEEPSEEK_API_KEY"],
         )),
-        other => Err(anyhow!("unsupported provider '{}'", other)),
+        "anthropic" | "claude" => Ok((
+            &config.providers.anthropic,
+            "https://api.anthropic.com/v1",
+            "claude-sonnet-4-5",
+            ProviderKind::Anthropic,
+            HashMap::new(),
+            vec!["ANTHROPIC_API_KEY"],
+        )),
+        "gemini" | "google" => Ok((
+            &config.providers.gemini,
+            "https://generativelanguage.googleapis.com/v1beta",
+            "gemini-2.0-flash",
+            ProviderKind::Gemini,
+            HashMap::new(),
+            vec!["GEMINI_API_KEY", "GOOGLE_API_KEY"],
+        )),
+        other => {
+            let (_, cfg) = find_custom(config, other)
+                .ok_or_else(|| anyhow!("unsupported provider '{}'", other))?;
+            Ok((
+                cfg,
+                "",
+                cfg.model.as_deref().unwrap_or(""),
+                ProviderKind::Custom,
+                HashMap::new(),
+                vec![],
+            ))
+        }
     }
 }
+fn find_custom<'a>(config: &'a Config, name: &str) -> Option<(&'a String, &'a ProviderConfig)> {
+    config
+        .providers
+        .custom
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+}
 fn read_api_key(cfg: &ProviderConfig, env_names: &[&str]) -> Result<String> {
     if let Some(key) = cfg.api_key.as_ref()
         && !key.trim().is_empty()
@@ -422,6 +747,192 @@ mod tests {
             })),
         )
     }
+    async fn mock_anthropic_messages(
+        headers: HeaderMap,
+        Json(body): Json<serde_json::Value>,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let key = headers
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let version = headers
+            .get("anthropic-version")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if key != "anthropic-key" || version.is_empty() {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({ "type": "error" })),
+            );
+        }
+        let has_system = body["system"].as_str().is_some_and(|s| !s.is_empty());
+        let last_is_tool_result = body["messages"]
+            .as_array()
+            .and_then(|m| m.last())
+            .and_then(|m| m["content"].as_array())
+            .and_then(|c| c.last())
+            .is_some_and(|b| b["type"] == "tool_result");
+        if !has_system {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "type": "error", "message": "system missing" })),
+            );
+        }
+        if last_is_tool_result {
+            return (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "content": [{ "type": "text", "text": "file says hi" }],
+                    "stop_reason": "end_turn",
+                    "usage": { "input_tokens": 7, "output_tokens": 3 }
+                })),
+            );
+        }
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "content": [{
+                    "type": "tool_use",
+                    "id": "toolu_1",
+                    "name": "read_file",
+                    "input": { "path": "README.md" }
+                }],
+                "stop_reason": "tool_use",
+                "usage": { "input_tokens": 5, "output_tokens": 2 }
+            })),
+        )
+    }
+    async fn start_mock_anthropic_server() -> (String, oneshot::Sender<()>) {
+        let app = Router::new().route("/messages", post(mock_anthropic_messages));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+                let _ = rx.await;
+            });
+            let _ = server.await;
+        });
+        (format!("http://{}", addr), tx)
+    }
+    async fn mock_chat_stream(
+        Json(body): Json<serde_json::Value>,
+    ) -> ([(axum::http::header::HeaderName, &'static str); 1], String) {
+        assert_eq!(body["stream"], true);
+        let chunks = [
+            serde_json::json!({"choices":[{"delta":{"content":"Hel"}}]}),
+            serde_json::json!({"choices":[{"delta":{"content":"lo"}}]}),
+            serde_json::json!({"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_s","type":"function","function":{"name":"read_file","arguments":"{\"path\":"}}]}}]}),
+            serde_json::json!({"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"README.md\"}"}}]},"finish_reason":"tool_calls"}]}),
+        ];
+        let mut sse: String = chunks.iter().map(|c| format!("data: {c}\n\n")).collect();
+        sse.push_str("data: [DONE]\n\n");
+        (
+            [(axum::http::header::CONTENT_TYPE, "text/event-stream")],
+            sse,
+        )
+    }
+    async fn mock_flaky_chat(
+        State(hits): State<Arc<std::sync::atomic::AtomicUsize>>,
+        Json(body): Json<serde_json::Value>,
+    ) -> axum::response::Response {
+        use axum::response::IntoResponse;
+        let hit = hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        if body["model"] == "bad-request" {
+            return (StatusCode::BAD_REQUEST, "invalid schema").into_response();
+        }
+        if hit == 0 {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(axum::http::header::RETRY_AFTER, "0")],
+                "slow down",
+            )
+                .into_response();
+        }
+        if hit == 1 {
+            return (StatusCode::BAD_GATEWAY, "upstream hiccup").into_response();
+        }
+        Json(serde_json::json!({
+            "choices": [{ "message": { "content": "recovered" }, "finish_reason": "stop" }]
+        }))
+        .into_response()
+    }
+    async fn start_mock_flaky_server(
+        hits: Arc<std::sync::atomic::AtomicUsize>,
+    ) -> (String, oneshot::Sender<()>) {
+        let app = Router::new()
+            .route("/chat/completions", post(mock_flaky_chat))
+            .with_state(hits);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+                let _ = rx.await;
+            });
+            let _ = server.await;
+        });
+        (format!("http://{}", addr), tx)
+    }
+    async fn mock_local_chat(
+        headers: HeaderMap,
+        Json(body): Json<serde_json::Value>,
+    ) -> Json<serde_json::Value> {
+        let seen = format!(
+            "model={} auth={} tag={}",
+            body["model"].as_str().unwrap_or_default(),
+            headers.contains_key("authorization"),
+            headers
+                .get("x-client-tag")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("-"),
+        );
+        Json(serde_json::json!({
+            "choices": [{ "message": { "content": seen }, "finish_reason": "stop" }]
+        }))
+    }
+    async fn mock_local_models() -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "object": "list",
+            "data": [{ "id": "llama3.1:8b" }, { "id": "qwen2.5:7b" }, { "object": "model" }]
+        }))
+    }
+    async fn start_mock_local_server() -> (String, oneshot::Sender<()>) {
+        let app = Router::new()
+            .route("/chat/completions", post(mock_local_chat))
+            .route("/models", axum::routing::get(mock_local_models));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+                let _ = rx.await;
+            });
+            let _ = server.await;
+        });
+        (format!("http://{}", addr), tx)
+    }
+    async fn start_mock_stream_server() -> (String, oneshot::Sender<()>) {
+        let app = Router::new().route("/chat/completions", post(mock_chat_stream));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+                let _ = rx.await;
+            });
+            let _ = server.await;
+        });
+        (format!("http://{}", addr), tx)
+    }
     async fn start_mock_server(expected_auth: &str) -> (String, oneshot::Sender<()>) {
         let app = Router::new()
             .route("/chat/completions", post(mock_chat))
@@ -479,6 +990,7 @@ mod tests {
                 arguments: None,
             }],
             tool_call_id: None,
+            images: vec![],
         };
         let v = normalize_message_for_provider(&msg);
         assert_eq!(v["role"], "assistant");
@@ -491,6 +1003,25 @@ mod tests {
         );
     }
     #[test]
+    fn normalize_message_sends_images_as_content_parts() {
+        let mut msg = Message::user("what is this?");
+        msg.images.push(ImagePart {
+            mime_type: "image/png".to_string(),
+            data: "iVBORw0K".to_string(),
+        });
+        let v = normalize_message_for_provider(&msg);
+        assert_eq!(v["content"][0]["text"], "what is this?");
+        assert_eq!(v["content"][1]["type"], "image_url");
+        assert_eq!(
+            v["content"][1]["image_url"]["url"],
+            "data:image/png;base64,iVBORw0K"
+        );
+        assert_eq!(
+            normalize_message_for_provider(&Message::user("hi"))["content"],
+            "hi"
+        );
+    }
+    #[test]
     fn normalize_message_serializes_tool_result_shape() {
         let msg = Message::tool("done", "call_abc");
         let v = normalize_message_for_provider(&msg);
@@ -588,4 +1119,181 @@ mod tests {
         assert_eq!(response.tool_calls[0].name.as_deref(), Some("read_file"));
         let _ = shutdown.send(());
     }
+    #[tokio::test]
+    async fn anthropic_provider_path_works() {
+        let (base, shutdown) = start_mock_anthropic_server().await;
+        let mut cfg = Config::default();
+        cfg.agents.defaults.provider = "anthropic".to_string();
+        cfg.providers.anthropic.api_key = Some("anthropic-key".to_string());
+        cfg.providers.anthropic.api_base = Some(base);
+        let provider = create_provider(&cfg).expect("provider");
+        let mut msgs = vec![Message::system("sys"), Message::user("read README")];
+        let response = provider
+            .chat_with_options(&mut msgs, None, "claude-sonnet-4-5", HashMap::new())
+            .await
+            .expect("chat should succeed");
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!(response.tool_calls[0].name.as_deref(), Some("read_file"));
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+        msgs.push(Message {
+            role: "assistant".to_string(),
+            content: response.content.clone(),
+            tool_calls: response.tool_calls.clone(),
+            tool_call_id: None,
+            images: vec![],
+        });
+        msgs.push(Message::tool("hi", &response.tool_calls[0].id));
+        let response = provider
+            .chat_with_options(&mut msgs, None, "claude-sonnet-4-5", HashMap::new())
+            .await
+            .expect("chat should succeed");
+        assert_eq!(response.content, "file says hi");
+        assert_eq!(response.usage.map(|u| u.total_tokens), Some(10));
+        let _ = shutdown.send(());
+    }
+    #[tokio::test]
+    async fn openai_stream_emits_deltas_and_assembles_tool_calls() {
+        let (base, shutdown) = start_mock_stream_server().await;
+        let mut cfg = Config::default();
+        cfg.agents.defaults.provider = "deepseek".to_string();
+        cfg.providers.deepseek.api_key = Some("deepseek-key".to_string());
+        cfg.providers.deepseek.api_base = Some(base);
+        let provider = create_provider(&cfg).expect("provider");
+        let mut msgs = vec![Message::user("stream please")];
+        let deltas = parking_lot::Mutex::new(Vec::new());
+        let on_delta = |d: &str| deltas.lock().push(d.to_string());
+        let response = provider
+            .chat_stream(&mut msgs, None, "deepseek-chat", HashMap::new(), &on_delta)
+            .await
+            .expect("stream should succeed");
+        assert_eq!(deltas.into_inner(), vec!["Hel", "lo"]);
+        assert_eq!(response.content, "Hello");
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!(response.tool_calls[0].id, "call_s");
+        let args = response.tool_calls[0].arguments.as_ref().expect("args");
+        assert_eq!(args["path"], "README.md");
+        let _ = shutdown.send(());
+    }
+    #[tokio::test]
+    async fn retries_rate_limits_and_server_errors_but_not_bad_requests() {
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let (base, shutdown) = start_mock_flaky_server(hits.clone()).await;
+        let mut cfg = Config::default();
+        cfg.agents.defaults.provider = "deepseek".to_string();
+        cfg.providers.deepseek.api_key = Some("deepseek-key".to_string());
+        cfg.providers.deepseek.api_base = Some(base);
+        cfg.providers.deepseek.retry.initial_backoff_ms = 1;
+        let provider = create_provider(&cfg).expect("provider");
+        let mut msgs = vec![Message::user("ping")];
+        let response = provider
+            .chat_with_options(&mut msgs, None, "deepseek-chat", HashMap::new())
+            .await
+            .expect("should recover after retries");
+        assert_eq!(response.content, "recovered");
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 3);
+        hits.store(0, std::sync::atomic::Ordering::SeqCst);
+        let err = provider
+            .chat_with_options(&mut msgs, None, "bad-request", HashMap::new())
+            .await
+            .expect_err("400 is fatal");
+        assert!(err.to_string().contains("status=400"));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let _ = shutdown.send(());
+    }
+    #[tokio::test]
+    async fn fallback_chain_moves_past_unreachable_primary() {
+        let (base, shutdown) = start_mock_server("Bearer deepseek-key").await;
+        let mut cfg = Config::default();
+        cfg.agents.defaults.provider = "openai".to_string();
+        cfg.providers.openai.api_key = Some("openai-key".to_string());
+        cfg.providers.openai.api_base = Some("http://127.0.0.1:9".to_string());
+        cfg.providers.openai.retry.max_retries = 0;
+        cfg.providers.deepseek.api_key = Some("deepseek-key".to_string());
+        cfg.providers.deepseek.api_base = Some(base);
+        cfg.agents.defaults.fallbacks = vec![
+            crate::config::FallbackTarget {
+                provider: "zhipu".to_string(),
+                model: String::new(),
+            },
+            crate::config::FallbackTarget {
+                provider: "deepseek".to_string(),
+                model: String::new(),
+            },
+        ];
+        let provider = create_provider(&cfg).expect("provider");
+        let mut msgs = vec![Message::user("ping")];
+        let response = provider
+            .chat_with_options(&mut msgs, None, "gpt-4o", HashMap::new())
+            .await
+            .expect("fallback should answer");
+        assert_eq!(response.content, "hello from mock");
+        let status = provider.status().expect("chain status");
+        assert!(status.contains("Provider chain: openai → deepseek/deepseek-chat"));
+        assert!(status.contains("Last answered by: deepseek/deepseek-chat"));
+        let _ = shutdown.send(());
+    }
+    #[tokio::test]
+    async fn keyless_custom_endpoint_is_selected_by_model_prefix() {
+        let (base, shutdown) = start_mock_local_server().await;
+        let mut cfg = Config::default();
+        cfg.agents.defaults.model = "Ollama/llama3.1:8b".to_string();
+        cfg.providers.custom.insert(
+            "ollama".to_string(),
+            ProviderConfig {
+                api_base: Some(base),
+                headers: HashMap::from([("X-Client-Tag".to_string(), "local".to_string())]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(default_model(&cfg), "Ollama/llama3.1:8b");
+        let provider = create_provider(&cfg).expect("keyless custom provider");
+        let mut msgs = vec![Message::user("ping")];
+        let response = provider
+            .chat_with_options(&mut msgs, None, &default_model(&cfg), HashMap::new())
+            .await
+            .expect("chat should succeed");
+        assert_eq!(response.content, "model=llama3.1:8b auth=false tag=local");
+        let models = provider.list_models().await.expect("model list");
+        assert_eq!(models, vec!["llama3.1:8b", "qwen2.5:7b"]);
+        let _ = shutdown.send(());
+    }
+    #[test]
+    fn custom_endpoint_default_model_applies_when_agent_model_untouched() {
+        let mut cfg = Config::default();
+        cfg.agents.defaults.provider = "vllm".to_string();
+        cfg.providers.custom.insert(
+            "vllm".to_string(),
+            ProviderConfig {
+                model: Some("Qwen/Qwen2.5-7B-Instruct".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(default_model(&cfg), "Qwen/Qwen2.5-7B-Instruct");
+        let err = create_provider(&cfg).err().expect("api_base is required");
+        assert!(err.to_string().contains("requires api_base"));
+        cfg.agents.defaults.model = "other-model".to_string();
+        assert_eq!(default_model(&cfg), "other-model");
+    }
+    #[tokio::test]
+    async fn provider_requests_go_through_configured_proxy() {
+        let (proxy, shutdown) = start_mock_local_server().await;
+        let mut cfg = Config::default();
+        cfg.agents.defaults.provider = "local".to_string();
+        cfg.providers.custom.insert(
+            "local".to_string(),
+            ProviderConfig {
+                api_base: Some("http://llm.internal.invalid".to_string()),
+                proxy: Some(proxy),
+                ..Default::default()
+            },
+        );
+        let provider = create_provider(&cfg).expect("provider");
+        let mut msgs = vec![Message::user("ping")];
+        let response = provider
+            .chat_with_options(&mut msgs, None, "local/tiny", HashMap::new())
+            .await
+            .expect("proxy should forward the request");
+        assert_eq!(response.content, "model=tiny auth=false tag=-");
+        let _ = shutdown.send(());
+    }
 }
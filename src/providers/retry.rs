@@ -0,0 +1,212 @@
+use crate::config::RetryConfig;
+use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
+use std::time::Duration;
+#[derive(Debug, thiserror::Error)]
+pub(super) enum ProviderError {
+    #[error("{provider} API request failed (status={status}): {body}")]
+    Status {
+        provider: &'static str,
+        status: StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("{provider} API request failed: {source}")]
+    Transport {
+        provider: &'static str,
+        #[source]
+        source: reqwest::Error,
+    },
+}
+impl ProviderError {
+    /// Rate limits, server-side failures and network hiccups are worth another
+    /// attempt; auth and request-shape errors are not.
+    pub(super) fn is_retryable(&self) -> bool {
+        match self {
+            Self::Status { status, .. } => {
+                matches!(status.as_u16(), 408 | 425 | 429 | 529)
+                    || (status.is_server_error() && *status != StatusCode::NOT_IMPLEMENTED)
+            }
+            Self::Transport { source, .. } => source.is_connect() || source.is_timeout(),
+        }
+    }
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Status { retry_after, .. } => *retry_after,
+            Self::Transport { .. } => None,
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub(super) struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+impl From<&RetryConfig> for RetryPolicy {
+    fn from(cfg: &RetryConfig) -> Self {
+        Self {
+            max_retries: cfg.max_retries,
+            initial_backoff: Duration::from_millis(cfg.initial_backoff_ms),
+            max_backoff: Duration::from_millis(cfg.max_backoff_ms.max(cfg.initial_backoff_ms)),
+        }
+    }
+}
+impl RetryPolicy {
+    /// Delay before retry number `attempt + 1`. A server-provided
+    /// `Retry-After` wins over the computed backoff; if it asks for longer
+    /// than `max_backoff` we give up instead of stalling the turn.
+    fn delay_for(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(wait) = retry_after {
+            return (wait <= self.max_backoff).then_some(wait);
+        }
+        let exp = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let half = exp / 2;
+        let jitter_ms = (uuid::Uuid::new_v4().as_u128() % (half.as_millis() + 1)) as u64;
+        Some(half + Duration::from_millis(jitter_ms))
+    }
+}
+/// Sends the request built by `build`, retrying retryable failures according
+/// to `policy`. Non-success responses are turned into [`ProviderError`].
+pub(super) async fn send_with_retry<F>(
+    policy: &RetryPolicy,
+    provider: &'static str,
+    build: F,
+) -> Result<reqwest::Response, ProviderError>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let mut attempt = 0;
+    loop {
+        let err = match build().send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(resp),
+            Ok(resp) => {
+                let status = resp.status();
+                let retry_after = parse_retry_after(resp.headers());
+                let body = resp.text().await.unwrap_or_default();
+                ProviderError::Status {
+                    provider,
+                    status,
+                    body,
+                    retry_after,
+                }
+            }
+            Err(source) => ProviderError::Transport { provider, source },
+        };
+        if !err.is_retryable() || attempt >= policy.max_retries {
+            return Err(err);
+        }
+        let Some(delay) = policy.delay_for(attempt, err.retry_after()) else {
+            return Err(err);
+        };
+        attempt += 1;
+        tracing::warn!(
+            "{} request failed, retry {}/{} in {:?}: {}",
+            provider,
+            attempt,
+            policy.max_retries,
+            delay,
+            err
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(ms) = headers
+        .get("retry-after-ms")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        && let Some(wait) = secs_to_delay(ms / 1000.0)
+    {
+        return Some(wait);
+    }
+    let raw = headers.get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(secs) = raw.parse::<f64>() {
+        return secs_to_delay(secs);
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(raw).ok()?;
+    let wait = at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or_default())
+}
+/// Headers are untrusted: negative and NaN values are ignored, and values
+/// too large for a `Duration` (`inf`, `1e30`) saturate, so the retry gives
+/// up like for any other wait past `max_backoff`.
+fn secs_to_delay(secs: f64) -> Option<Duration> {
+    if secs.is_nan() || secs < 0.0 {
+        return None;
+    }
+    Some(Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", "2".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after-ms", "150".parse().unwrap());
+        assert_eq!(
+            parse_retry_after(&headers),
+            Some(Duration::from_millis(150))
+        );
+        let mut dated = HeaderMap::new();
+        let past = "Wed, 21 Oct 2015 07:28:00 GMT";
+        dated.insert("retry-after", past.parse().unwrap());
+        assert_eq!(parse_retry_after(&dated), Some(Duration::ZERO));
+    }
+    #[test]
+    fn retry_after_survives_absurd_values() {
+        let parse = |name: &'static str, value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, value.parse().unwrap());
+            parse_retry_after(&headers)
+        };
+        let policy = RetryPolicy::from(&RetryConfig::default());
+        for name in ["retry-after", "retry-after-ms"] {
+            assert_eq!(parse(name, "inf"), Some(Duration::MAX), "{name}");
+            assert_eq!(parse(name, "1e30"), Some(Duration::MAX), "{name}");
+            assert_eq!(policy.delay_for(0, parse(name, "1e30")), None, "{name}");
+            assert_eq!(parse(name, "nan"), None, "{name}");
+            assert_eq!(parse(name, "-5"), None, "{name}");
+        }
+    }
+    #[test]
+    fn backoff_grows_and_respects_limits() {
+        let policy = RetryPolicy::from(&RetryConfig {
+            max_retries: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        });
+        for attempt in 0..6 {
+            let delay = policy.delay_for(attempt, None).expect("delay");
+            let cap = Duration::from_millis((100u64 << attempt).min(1000));
+            assert!(
+                delay >= cap / 2 && delay <= cap,
+                "attempt {attempt}: {delay:?}"
+            );
+        }
+        assert_eq!(
+            policy.delay_for(0, Some(Duration::from_millis(300))),
+            Some(Duration::from_millis(300))
+        );
+        assert_eq!(policy.delay_for(0, Some(Duration::from_secs(5))), None);
+    }
+    #[test]
+    fn auth_and_schema_errors_are_fatal() {
+        let status_err = |code: u16| ProviderError::Status {
+            provider: "provider",
+            status: StatusCode::from_u16(code).unwrap(),
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(status_err(429).is_retryable());
+        assert!(status_err(503).is_retryable());
+        assert!(!status_err(400).is_retryable());
+        assert!(!status_err(401).is_retryable());
+        assert!(!status_err(403).is_retryable());
+    }
+}
@@ -0,0 +1,175 @@
+use super::{LlmResponse, ToolCall, openai_tool_call, parse_openai_usage};
+/// How far past the calls seen so far a tool call `index` may point; calls
+/// arrive in order, so anything further is a broken or hostile stream.
+const MAX_TOOL_CALL_INDEX_GAP: usize = 4;
+#[derive(Default)]
+pub(super) struct SseDecoder {
+    buffer: Vec<u8>,
+    data_lines: Vec<String>,
+}
+impl SseDecoder {
+    pub(super) fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data_lines.is_empty() {
+                    events.push(self.data_lines.join("\n"));
+                    self.data_lines.clear();
+                }
+                continue;
+            }
+            if let Some(data) = line.strip_prefix("data:") {
+                self.data_lines
+                    .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+        }
+        events
+    }
+    pub(super) fn finish(&mut self) -> Vec<String> {
+        let mut events = self.feed(b"\n\n");
+        if !self.buffer.is_empty() {
+            let rest = String::from_utf8_lossy(&self.buffer).to_string();
+            self.buffer.clear();
+            if let Some(data) = rest.trim().strip_prefix("data:") {
+                events.push(data.trim().to_string());
+            }
+        }
+        events
+    }
+}
+#[derive(Default)]
+struct PartialToolCall {
+    id: String,
+    tool_type: String,
+    name: String,
+    arguments: String,
+}
+#[derive(Default)]
+pub(super) struct StreamAccumulator {
+    content: String,
+    tool_calls: Vec<PartialToolCall>,
+    finish_reason: Option<String>,
+    usage: Option<super::UsageInfo>,
+}
+impl StreamAccumulator {
+    pub(super) fn push_chunk(&mut self, chunk: &serde_json::Value) -> Option<String> {
+        if let Some(usage) = chunk.get("usage").and_then(parse_openai_usage) {
+            self.usage = Some(usage);
+        }
+        let choice = chunk.get("choices")?.as_array()?.first()?;
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+        let delta = &choice["delta"];
+        if let Some(calls) = delta["tool_calls"].as_array() {
+            for (pos, call) in calls.iter().enumerate() {
+                let idx = call["index"].as_u64().unwrap_or(pos as u64);
+                if idx > (self.tool_calls.len() + MAX_TOOL_CALL_INDEX_GAP) as u64 {
+                    tracing::debug!("skipping stream tool call with index {}", idx);
+                    continue;
+                }
+                let idx = idx as usize;
+                if self.tool_calls.len() <= idx {
+                    self.tool_calls
+                        .resize_with(idx + 1, PartialToolCall::default);
+                }
+                let slot = &mut self.tool_calls[idx];
+                if let Some(id) = call["id"].as_str() {
+                    slot.id = id.to_string();
+                }
+                if let Some(tool_type) = call["type"].as_str() {
+                    slot.tool_type = tool_type.to_string();
+                }
+                if let Some(name) = call["function"]["name"].as_str() {
+                    slot.name.push_str(name);
+                }
+                if let Some(args) = call["function"]["arguments"].as_str() {
+                    slot.arguments.push_str(args);
+                }
+            }
+        }
+        let text = delta["content"].as_str().filter(|s| !s.is_empty())?;
+        self.content.push_str(text);
+        Some(text.to_string())
+    }
+    pub(super) fn finish(self) -> LlmResponse {
+        let tool_calls: Vec<ToolCall> = self
+            .tool_calls
+            .into_iter()
+            .filter(|tc| !tc.name.is_empty())
+            .enumerate()
+            .map(|(idx, tc)| {
+                let args = if tc.arguments.trim().is_empty() {
+                    "{}"
+                } else {
+                    tc.arguments.as_str()
+                };
+                openai_tool_call(idx, &tc.id, &tc.tool_type, &tc.name, args)
+            })
+            .collect();
+        LlmResponse {
+            content: self.content,
+            tool_calls,
+            finish_reason: self.finish_reason.or_else(|| Some("stop".to_string())),
+            usage: self.usage,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn decoder_handles_split_lines_and_multibyte_chars() {
+        let mut decoder = SseDecoder::default();
+        let raw = "data: {\"a\":\"привет\"}\n\ndata: [DONE]\n\n".as_bytes();
+        let mut events = Vec::new();
+        for chunk in raw.chunks(5) {
+            events.extend(decoder.feed(chunk));
+        }
+        events.extend(decoder.finish());
+        assert_eq!(events, vec!["{\"a\":\"привет\"}", "[DONE]"]);
+    }
+    #[test]
+    fn accumulator_assembles_incremental_tool_call_arguments() {
+        let chunks = [
+            serde_json::json!({"choices":[{"delta":{"content":"Let me "}}]}),
+            serde_json::json!({"choices":[{"delta":{"content":"check."}}]}),
+            serde_json::json!({"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_a","type":"function","function":{"name":"read_file","arguments":""}}]}}]}),
+            serde_json::json!({"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"path\":"}}]}}]}),
+            serde_json::json!({"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"README.md\"}"}}]}}]}),
+            serde_json::json!({"choices":[{"delta":{},"finish_reason":"tool_calls"}]}),
+            serde_json::json!({"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":4,"total_tokens":7}}),
+        ];
+        let mut acc = StreamAccumulator::default();
+        let deltas: Vec<String> = chunks.iter().filter_map(|c| acc.push_chunk(c)).collect();
+        assert_eq!(deltas, vec!["Let me ", "check."]);
+        let response = acc.finish();
+        assert_eq!(response.content, "Let me check.");
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!(response.tool_calls[0].id, "call_a");
+        let args = response.tool_calls[0].arguments.as_ref().expect("args");
+        assert_eq!(args["path"], "README.md");
+        assert_eq!(response.usage.map(|u| u.total_tokens), Some(7));
+    }
+    #[test]
+    fn accumulator_skips_tool_call_indices_far_past_the_end() {
+        let call = |index: u64, name: &str| serde_json::json!({"choices":[{"delta":{"tool_calls":[{"index":index,"id":name,"function":{"name":name,"arguments":"{}"}}]}}]});
+        let mut acc = StreamAccumulator::default();
+        acc.push_chunk(&call(u64::MAX, "huge"));
+        acc.push_chunk(&call(1_000_000_000, "far"));
+        acc.push_chunk(&call(1, "gap"));
+        assert_eq!(acc.tool_calls.len(), 2);
+        let names: Vec<String> = acc
+            .finish()
+            .tool_calls
+            .into_iter()
+            .filter_map(|tc| tc.function.map(|f| f.name))
+            .collect();
+        assert_eq!(names, ["gap"]);
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ToolCall {
     pub id: String,
     #[serde(rename = "type", default)]
@@ -12,7 +12,7 @@ pub struct ToolCall {
     #[serde(default)]
     pub arguments: Option<HashMap<String, serde_json::Value>>,
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionCall {
     pub name: String,
     pub arguments: String,
@@ -27,20 +27,41 @@ pub struct LlmResponse {
     #[serde(default)]
     pub usage: Option<UsageInfo>,
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct UsageInfo {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
     pub total_tokens: i32,
+    /// Backend that actually served the call, when it differs from the
+    /// configured one (set by fallback chains).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
+    /// Text part of the message.
     pub content: String,
     #[serde(default)]
     pub tool_calls: Vec<ToolCall>,
     #[serde(default)]
     pub tool_call_id: Option<String>,
+    /// Image parts sent along with `content` (user messages to vision models).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImagePart>,
+}
+/// Inline image, base64-encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImagePart {
+    pub mime_type: String,
+    pub data: String,
+}
+impl ImagePart {
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.data)
+    }
 }
 impl Message {
     pub fn user(content: &str) -> Self {
@@ -49,6 +70,7 @@ impl Message {
             content: content.to_string(),
             tool_calls: vec![],
             tool_call_id: None,
+            images: vec![],
         }
     }
     pub fn system(content: &str) -> Self {
@@ -57,6 +79,7 @@ impl Message {
             content: content.to_string(),
             tool_calls: vec![],
             tool_call_id: None,
+            images: vec![],
         }
     }
     pub fn tool(content: &str, tool_call_id: &str) -> Self {
@@ -65,6 +88,7 @@ impl Message {
             content: content.to_string(),
             tool_calls: vec![],
             tool_call_id: Some(tool_call_id.to_string()),
+            images: vec![],
         }
     }
 }
@@ -101,6 +125,12 @@ pub struct ProcessOptions {
     pub default_response: String,
     pub enable_summary: bool,
     pub no_history: bool,
+    /// Sender charged for the turn; empty when there is no real sender.
+    pub sender_id: String,
+    /// Heartbeat/cron turn, billed against the background budget.
+    pub background: bool,
+    /// Local files that came with the user message (images, voice, ...).
+    pub media: Vec<String>,
 }
 impl Default for ProcessOptions {
     fn default() -> Self {
@@ -112,6 +142,9 @@ impl Default for ProcessOptions {
             default_response: "I've completed processing but have no response to give.".to_string(),
             enable_summary: true,
             no_history: false,
+            sender_id: String::new(),
+            background: false,
+            media: Vec::new(),
         }
     }
 }
@@ -0,0 +1,291 @@
+use crate::config::RetentionConfig;
+use crate::media;
+use crate::memory::MemoryStore;
+use crate::session::SessionManager;
+use crate::trace;
+use chrono::{DateTime, Local};
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+/// A workspace and the sessions stored in it. Agent profiles with their own
+/// workspace are targets of their own.
+pub struct Target {
+    pub workspace: PathBuf,
+    pub sessions: Arc<SessionManager>,
+}
+/// What one pruning pass deleted.
+#[derive(Debug, Default, PartialEq)]
+pub struct Pruned {
+    pub sessions: usize,
+    /// Sessions cut down to `session_max_messages`.
+    pub trimmed: usize,
+    pub archives: usize,
+    pub notes: usize,
+    pub media: usize,
+    pub traces: usize,
+}
+/// `seconds` before `now`; `None` for zero, which means "keep forever".
+fn cutoff(now: DateTime<Local>, seconds: u64) -> Option<DateTime<Local>> {
+    if seconds == 0 {
+        return None;
+    }
+    now.checked_sub_signed(chrono::TimeDelta::try_seconds(
+        i64::try_from(seconds).ok()?,
+    )?)
+}
+/// Applies `config` to every target and to the media under `media_root`.
+/// Failures are logged and the pass goes on with the next item.
+pub fn prune(targets: &[Target], media_root: &Path, config: &RetentionConfig) -> Pruned {
+    let now = Local::now();
+    let sessions_before = cutoff(now, config.session_max_age_days.saturating_mul(86_400));
+    let notes_before = cutoff(now, config.notes_max_age_days.saturating_mul(86_400));
+    let traces_before = cutoff(now, config.trace_max_age_days.saturating_mul(86_400));
+    let mut pruned = Pruned::default();
+    let mut workspaces = HashSet::new();
+    for target in targets {
+        if let Some(before) = sessions_before {
+            match target.sessions.delete_idle(before) {
+                Ok(keys) => pruned.sessions += keys.len(),
+                Err(err) => tracing::warn!("retention: failed to delete old sessions: {}", err),
+            }
+            match target.sessions.delete_archives(None, Some(before)) {
+                Ok(count) => pruned.archives += count,
+                Err(err) => tracing::warn!("retention: failed to delete old archives: {}", err),
+            }
+        }
+        match target.sessions.trim_stored() {
+            Ok(count) => pruned.trimmed += count,
+            Err(err) => tracing::warn!("retention: failed to trim sessions: {}", err),
+        }
+        // Profiles may share a workspace, and with it the notes and traces.
+        if !workspaces.insert(target.workspace.clone()) {
+            continue;
+        }
+        if let Some(before) = notes_before {
+            match MemoryStore::new(target.workspace.clone()).prune_daily_notes(before.date_naive())
+            {
+                Ok(count) => pruned.notes += count,
+                Err(err) => tracing::warn!("retention: failed to delete old notes: {}", err),
+            }
+        }
+        if let Some(before) = traces_before {
+            match trace::prune_traces(&target.workspace, before.date_naive()) {
+                Ok(count) => pruned.traces += count,
+                Err(err) => tracing::warn!("retention: failed to delete old traces: {}", err),
+            }
+        }
+    }
+    if let Some(before) = cutoff(now, config.media_max_age_hours.saturating_mul(3_600)) {
+        let chats = match std::fs::read_dir(media_root) {
+            Ok(chats) => chats.filter_map(Result::ok).collect(),
+            Err(_) => Vec::new(),
+        };
+        for chat in chats {
+            match delete_files(&chat.path(), Some(before.into())) {
+                Ok(count) => pruned.media += count,
+                Err(err) => tracing::warn!("retention: failed to delete old media: {}", err),
+            }
+        }
+    }
+    pruned
+}
+/// Deletes the files in `dir` modified before `before` (all of them when
+/// `None`), then `dir` itself if that left it empty.
+fn delete_files(dir: &Path, before: Option<SystemTime>) -> std::io::Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut deleted = 0;
+    for entry in entries.filter_map(Result::ok) {
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        if let Some(before) = before
+            && meta.modified()? >= before
+        {
+            continue;
+        }
+        std::fs::remove_file(entry.path())?;
+        deleted += 1;
+    }
+    // Fails, as intended, while the directory still has files.
+    let _ = std::fs::remove_dir(dir);
+    Ok(deleted)
+}
+/// Deletes everything downloaded from `chat_id` on `channel`.
+pub fn forget_media(channel: &str, chat_id: &str) -> std::io::Result<usize> {
+    delete_files(&media::media_dir(channel, chat_id), None)
+}
+/// Runs [`prune`] in the gateway every `interval_minutes`, starting right
+/// away.
+pub struct RetentionService {
+    targets: Arc<Vec<Target>>,
+    config: RetentionConfig,
+    shutdown_tx: Mutex<Option<oneshot::Sender<()>>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+impl RetentionService {
+    pub fn new(targets: Vec<Target>, config: RetentionConfig) -> Self {
+        Self {
+            targets: Arc::new(targets),
+            config,
+            shutdown_tx: Mutex::new(None),
+            handle: Mutex::new(None),
+        }
+    }
+    pub fn start(&self) {
+        if self.handle.lock().is_some() {
+            return;
+        }
+        if self.config.interval_minutes == 0 {
+            tracing::info!("retention pruning disabled");
+            return;
+        }
+        let targets = self.targets.clone();
+        let config = self.config.clone();
+        let (tx, mut rx) = oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(
+                config.interval_minutes.saturating_mul(60),
+            ));
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        let targets = targets.clone();
+                        let config = config.clone();
+                        let pass = tokio::task::spawn_blocking(move || {
+                            prune(&targets, &media::media_root(), &config)
+                        });
+                        match pass.await {
+                            Ok(pruned) if pruned != Pruned::default() => tracing::info!(
+                                "retention: deleted {} sessions, {} archives, {} notes, {} media files and {} traces; trimmed {} sessions",
+                                pruned.sessions,
+                                pruned.archives,
+                                pruned.notes,
+                                pruned.media,
+                                pruned.traces,
+                                pruned.trimmed
+                            ),
+                            Ok(_) => {}
+                            Err(err) => tracing::error!("retention pass failed: {}", err),
+                        }
+                    }
+                    _ = &mut rx => break,
+                }
+            }
+        });
+        *self.shutdown_tx.lock() = Some(tx);
+        *self.handle.lock() = Some(handle);
+    }
+    pub async fn stop(&self) {
+        if let Some(tx) = self.shutdown_tx.lock().take() {
+            let _ = tx.send(());
+        }
+        let handle = self.handle.lock().take();
+        if let Some(handle) = handle {
+            let _ = handle.await;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    fn backdate(path: &Path, age: Duration) {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .expect("open")
+            .set_modified(SystemTime::now() - age)
+            .expect("set mtime");
+    }
+    #[test]
+    fn prune_deletes_what_is_past_its_age() {
+        let tmp = tempfile::tempdir().expect("tempdir");
+        let workspace = tmp.path().join("workspace");
+        let sessions_dir = workspace.join("sessions");
+        let unlimited = SessionManager::new(sessions_dir.clone());
+        for key in ["telegram:old", "telegram:new", "telegram:archived"] {
+            for turn in ["one", "two"] {
+                unlimited.add_message(key, "user", turn);
+                unlimited.add_message(key, "assistant", turn);
+            }
+            unlimited.save(key).expect("save");
+        }
+        assert!(unlimited.archive("telegram:archived").expect("archive"));
+        let month_ago = Duration::from_secs(40 * 86_400);
+        backdate(&sessions_dir.join("telegram_old.json"), month_ago);
+        for archived in std::fs::read_dir(sessions_dir.join("archive")).expect("archive dir") {
+            backdate(&archived.expect("entry").path(), month_ago);
+        }
+        let notes = workspace.join("memory").join("202001");
+        std::fs::create_dir_all(&notes).expect("notes dir");
+        std::fs::write(notes.join("20200101.md"), "# 2020-01-01").expect("note");
+        let memory = MemoryStore::new(workspace.clone());
+        memory.append_today("today").expect("today");
+        let traces = trace::traces_dir(&workspace);
+        std::fs::create_dir_all(traces.join("2020-01-01")).expect("old traces dir");
+        std::fs::create_dir_all(traces.join("prompts")).expect("prompts dir");
+        std::fs::write(traces.join("2020-01-01/20200101-000000-abcdef.jsonl"), "")
+            .expect("old trace");
+        std::fs::write(traces.join("prompts/0123456789abcdef.txt"), "old").expect("old prompt");
+        let turn = trace::TraceLog::new(&workspace, &crate::config::Config::default()).start_turn();
+        let prompt = [crate::providers::Message::system("today")];
+        turn.request(1, "m", &prompt, Vec::new(), &Default::default(), false);
+        let hash = turn.system_prompt(&prompt);
+        let media_root = tmp.path().join("media");
+        let chat = media_root.join("telegram_1");
+        std::fs::create_dir_all(&chat).expect("media dir");
+        std::fs::write(chat.join("old.jpg"), "x").expect("old media");
+        std::fs::write(chat.join("new.jpg"), "x").expect("new media");
+        backdate(&chat.join("old.jpg"), Duration::from_secs(2 * 86_400));
+        let sessions = Arc::new(SessionManager::new(sessions_dir).with_max_messages(3));
+        let target = Target {
+            workspace: workspace.clone(),
+            sessions: sessions.clone(),
+        };
+        let config = RetentionConfig {
+            session_max_age_days: 30,
+            notes_max_age_days: 7,
+            ..RetentionConfig::default()
+        };
+        let pruned = prune(&[target], &media_root, &config);
+        assert_eq!(
+            pruned,
+            Pruned {
+                sessions: 1,
+                trimmed: 1,
+                archives: 1,
+                notes: 1,
+                media: 1,
+                traces: 1,
+            }
+        );
+        let keys: Vec<String> = sessions
+            .list()
+            .expect("list")
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        assert_eq!(keys, ["telegram:new"]);
+        // Cut at a turn boundary, so only the last turn is left.
+        let reloaded = SessionManager::new(workspace.join("sessions"));
+        assert_eq!(reloaded.get_history("telegram:new").len(), 2);
+        assert!(!notes.exists());
+        assert!(memory.read_today().contains("today"));
+        assert_eq!(trace::recent_turns(&workspace, 10), [turn.id]);
+        assert!(!traces.join("prompts/0123456789abcdef.txt").exists());
+        assert!(traces.join(format!("prompts/{}.txt", hash)).exists());
+        assert!(chat.join("new.jpg").exists());
+        assert_eq!(delete_files(&chat, None).expect("forget"), 1);
+        assert!(!chat.exists());
+    }
+}
@@ -0,0 +1,188 @@
+use super::{MessageDelta, SessionInfo, SessionState, SessionStore};
+use crate::providers::Message as ProviderMessage;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+/// On-disk layout of `<sessions>/<key>.json`.
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    /// The session key as given; the file name can't be mapped back to it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    key: String,
+    #[serde(flatten)]
+    state: SessionState,
+}
+/// Session files written before summaries were persisted are a bare array.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredSession {
+    Current(SessionFile),
+    Legacy(Vec<ProviderMessage>),
+}
+/// A readable session file in the store's directory.
+pub(super) struct Entry {
+    pub path: PathBuf,
+    pub stem: String,
+    pub meta: std::fs::Metadata,
+    file: SessionFile,
+}
+impl Entry {
+    /// The stored key; `None` for files from before it was stored.
+    pub fn key(&self) -> Option<&str> {
+        Some(self.file.key.as_str()).filter(|key| !key.is_empty())
+    }
+    pub fn into_state(self) -> SessionState {
+        self.file.state
+    }
+}
+/// One JSON file per session, rewritten whole on every save.
+pub struct JsonStore {
+    dir: PathBuf,
+}
+impl JsonStore {
+    pub fn new(dir: PathBuf) -> Self {
+        std::fs::create_dir_all(&dir).ok();
+        Self { dir }
+    }
+    fn read_file(path: &Path) -> anyhow::Result<Option<SessionFile>> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Some(match serde_json::from_str(&data)? {
+            StoredSession::Current(file) => file,
+            StoredSession::Legacy(messages) => SessionFile {
+                key: String::new(),
+                state: SessionState {
+                    messages,
+                    ..SessionState::default()
+                },
+            },
+        }))
+    }
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", sanitize_session_key(key)))
+    }
+    fn write_atomic(&self, path: &Path, data: &[u8]) -> anyhow::Result<()> {
+        let temp = tempfile::NamedTempFile::new_in(&self.dir)?;
+        std::fs::write(temp.path(), data)?;
+        temp.persist(path)?;
+        Ok(())
+    }
+    pub(super) fn entries(&self) -> anyhow::Result<Vec<Entry>> {
+        Ok(std::fs::read_dir(&self.dir)?
+            .filter_map(Result::ok)
+            .filter_map(|dir_entry| {
+                let path = dir_entry.path();
+                let stem = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_suffix(".json")?
+                    .to_string();
+                let meta = dir_entry.metadata().ok().filter(|m| m.is_file())?;
+                let file = Self::read_file(&path).ok()??;
+                Some(Entry {
+                    path,
+                    stem,
+                    meta,
+                    file,
+                })
+            })
+            .collect())
+    }
+}
+impl SessionStore for JsonStore {
+    fn load(&self, key: &str) -> anyhow::Result<Option<SessionState>> {
+        Ok(Self::read_file(&self.path(key))?.map(|file| file.state))
+    }
+    fn save(&self, key: &str, state: &SessionState, _delta: MessageDelta) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(&SessionFile {
+            key: key.to_string(),
+            state: state.clone(),
+        })?;
+        self.write_atomic(&self.path(key), &data)
+    }
+    fn delete(&self, key: &str) -> anyhow::Result<bool> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(false);
+        }
+        std::fs::remove_file(path)?;
+        Ok(true)
+    }
+    /// Copies the session to `<sessions>/archive/<key>-<time>.json`.
+    fn archive(&self, key: &str, state: &SessionState) -> anyhow::Result<()> {
+        let archive_dir = self.dir.join("archive");
+        std::fs::create_dir_all(&archive_dir)?;
+        let path = archive_dir.join(format!(
+            "{}-{}.json",
+            sanitize_session_key(key),
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        ));
+        let data = serde_json::to_vec(&SessionFile {
+            key: key.to_string(),
+            state: state.clone(),
+        })?;
+        self.write_atomic(&path, &data)
+    }
+    /// Files written before the key was stored are listed under their file
+    /// name.
+    fn list(&self) -> anyhow::Result<Vec<SessionInfo>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter_map(|entry| {
+                Some(SessionInfo {
+                    messages: entry.file.state.messages.len(),
+                    last_active: entry.meta.modified().ok()?.into(),
+                    size: entry.meta.len(),
+                    key: if entry.file.key.is_empty() {
+                        entry.stem
+                    } else {
+                        entry.file.key
+                    },
+                })
+            })
+            .collect())
+    }
+    /// Deletes files in `archive/` written before `before`, and only those
+    /// of `key` when given.
+    fn delete_archives(
+        &self,
+        key: Option<&str>,
+        before: Option<chrono::DateTime<chrono::Local>>,
+    ) -> anyhow::Result<usize> {
+        let entries = match std::fs::read_dir(self.dir.join("archive")) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        let mut deleted = 0;
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            if let Some(before) = before {
+                let modified = entry.metadata()?.modified()?;
+                if chrono::DateTime::<chrono::Local>::from(modified) >= before {
+                    continue;
+                }
+            }
+            if let Some(key) = key
+                && Self::read_file(&path)
+                    .ok()
+                    .flatten()
+                    .is_none_or(|file| file.key != key)
+            {
+                continue;
+            }
+            std::fs::remove_file(&path)?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+}
+fn sanitize_session_key(key: &str) -> String {
+    key.replace([':', '/', '\\'], "_")
+}
@@ -0,0 +1,657 @@
+mod json;
+mod sqlite;
+use crate::config::{GenerationSettings, RetentionConfig, SessionsConfig};
+use crate::providers::Message as ProviderMessage;
+pub use json::JsonStore;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+pub use sqlite::SqliteStore;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+/// Model chosen for one session with `/switch model`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSelection {
+    /// `None` keeps the configured provider (and its fallback chain).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    pub model: String,
+}
+/// What a [`SessionStore`] keeps for one session.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<ModelSelection>,
+    #[serde(default, skip_serializing_if = "GenerationSettings::is_empty")]
+    pub settings: GenerationSettings,
+    /// The last turn hit the tool iteration limit; `/continue` resumes it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    #[serde(default)]
+    pub messages: Vec<ProviderMessage>,
+}
+impl SessionState {
+    fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+            && self.summary.is_empty()
+            && self.model.is_none()
+            && self.settings.is_empty()
+            && !self.interrupted
+    }
+}
+/// How the messages being saved differ from the stored ones: the first
+/// `dropped` stored messages are gone, the next `kept` are unchanged and
+/// anything stored after them was removed. `messages[kept..]` are new.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MessageDelta {
+    pub dropped: usize,
+    pub kept: usize,
+}
+/// Where sessions are persisted.
+pub trait SessionStore: Send + Sync {
+    /// `None` when nothing is stored under `key`.
+    fn load(&self, key: &str) -> anyhow::Result<Option<SessionState>>;
+    /// Stores `state`; stores that write incrementally use `delta` to touch
+    /// only what changed.
+    fn save(&self, key: &str, state: &SessionState, delta: MessageDelta) -> anyhow::Result<()>;
+    /// Returns false when nothing was stored under `key`.
+    fn delete(&self, key: &str) -> anyhow::Result<bool>;
+    /// Keeps a copy of `state` aside before the session starts over.
+    fn archive(&self, key: &str, state: &SessionState) -> anyhow::Result<()>;
+    /// Stored sessions, in no particular order.
+    fn list(&self) -> anyhow::Result<Vec<SessionInfo>>;
+    /// Deletes archived copies of `key` (of every session when `None`)
+    /// made before `before` (at any time when `None`). Returns how many
+    /// were deleted.
+    fn delete_archives(
+        &self,
+        key: Option<&str>,
+        before: Option<chrono::DateTime<chrono::Local>>,
+    ) -> anyhow::Result<usize>;
+}
+/// Session histories keyed by session key. Each session has its own lock, so
+/// turns in different chats never wait on each other's history. Only the
+/// `cache_size` most recently used sessions stay in memory.
+pub struct SessionManager {
+    store: Arc<dyn SessionStore>,
+    sessions: RwLock<HashMap<String, CachedSession>>,
+    cache_size: usize,
+    /// Messages kept per session; older turns are dropped.
+    max_messages: usize,
+    /// Logical clock for least-recently-used eviction.
+    clock: AtomicU64,
+}
+struct CachedSession {
+    session: Arc<Mutex<Session>>,
+    last_used: AtomicU64,
+}
+struct Session {
+    state: SessionState,
+    /// Leading messages that match the store, and stored messages dropped
+    /// from the front since the last save.
+    saved: usize,
+    dropped: usize,
+    dirty: bool,
+}
+impl Session {
+    fn drain_front(&mut self, count: usize) {
+        self.state.messages.drain(..count);
+        self.dropped += count.min(self.saved);
+        self.saved = self.saved.saturating_sub(count);
+        self.dirty = true;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.state.messages.truncate(len);
+        self.saved = self.saved.min(len);
+        self.dirty = true;
+    }
+}
+/// A stored session, as shown by `asterclaw sessions list`.
+pub struct SessionInfo {
+    pub key: String,
+    pub messages: usize,
+    pub last_active: chrono::DateTime<chrono::Local>,
+    pub size: u64,
+}
+/// Everything a session holds, for viewing and export.
+#[derive(Serialize)]
+pub struct Transcript {
+    pub key: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<ModelSelection>,
+    pub messages: Vec<ProviderMessage>,
+}
+impl SessionManager {
+    /// JSON files in `sessions_dir` with the default cache size.
+    pub fn new(sessions_dir: PathBuf) -> Self {
+        Self::with_store(
+            Arc::new(JsonStore::new(sessions_dir)),
+            SessionsConfig::default().cache_size,
+        )
+    }
+    pub fn with_store(store: Arc<dyn SessionStore>, cache_size: usize) -> Self {
+        Self {
+            store,
+            sessions: RwLock::new(HashMap::new()),
+            cache_size: cache_size.max(1),
+            max_messages: RetentionConfig::default().session_max_messages,
+            clock: AtomicU64::new(0),
+        }
+    }
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages.max(1);
+        self
+    }
+    /// The store `config` selects in `<workspace>/sessions`.
+    pub fn open(workspace: &Path, config: &SessionsConfig) -> anyhow::Result<Self> {
+        let dir = workspace.join("sessions");
+        let store: Arc<dyn SessionStore> = match config.store.as_str() {
+            "json" => Arc::new(JsonStore::new(dir)),
+            "sqlite" => Arc::new(SqliteStore::open(&dir.join("sessions.db"))?),
+            other => anyhow::bail!(
+                "unknown sessions.store {:?}: expected json or sqlite",
+                other
+            ),
+        };
+        Ok(Self::with_store(store, config.cache_size))
+    }
+    fn get_or_create(&self, key: &str) -> Arc<Mutex<Session>> {
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some(cached) = self.sessions.read().get(key) {
+            cached.last_used.store(tick, Ordering::Relaxed);
+            return cached.session.clone();
+        }
+        let state = self
+            .store
+            .load(key)
+            .unwrap_or_else(|err| {
+                tracing::warn!("failed to load session {}: {}", key, err);
+                None
+            })
+            .unwrap_or_default();
+        let mut sessions = self.sessions.write();
+        let session = sessions
+            .entry(key.to_string())
+            .or_insert_with(|| CachedSession {
+                session: Arc::new(Mutex::new(Session {
+                    saved: state.messages.len(),
+                    state,
+                    dropped: 0,
+                    dirty: false,
+                })),
+                last_used: AtomicU64::new(tick),
+            })
+            .session
+            .clone();
+        if sessions.len() > self.cache_size {
+            Self::evict(&mut sessions, self.cache_size);
+        }
+        session
+    }
+    /// Unloads the least recently used sessions that nobody holds and that
+    /// have nothing unsaved, until at most `cache_size` remain.
+    fn evict(sessions: &mut HashMap<String, CachedSession>, cache_size: usize) {
+        let mut idle: Vec<(u64, String)> = sessions
+            .iter()
+            .filter(|(_, cached)| {
+                Arc::strong_count(&cached.session) == 1
+                    && cached.session.try_lock().is_some_and(|s| !s.dirty)
+            })
+            .map(|(key, cached)| (cached.last_used.load(Ordering::Relaxed), key.clone()))
+            .collect();
+        idle.sort_unstable();
+        let excess = sessions.len().saturating_sub(cache_size);
+        for (_, key) in idle.into_iter().take(excess) {
+            sessions.remove(&key);
+        }
+    }
+    /// Messages to drop from the front so history stays within
+    /// `max_messages`. The cut is at the next turn so history always starts
+    /// with a user message; inside one huge turn, at least no tool result
+    /// loses its call.
+    fn history_excess(&self, messages: &[ProviderMessage]) -> usize {
+        let Some(excess) = messages.len().checked_sub(self.max_messages) else {
+            return 0;
+        };
+        messages[excess..]
+            .iter()
+            .position(|m| m.role == "user")
+            .map_or_else(
+                || {
+                    excess
+                        + messages[excess..]
+                            .iter()
+                            .take_while(|m| m.role == "tool")
+                            .count()
+                },
+                |pos| excess + pos,
+            )
+    }
+    pub fn add_message(&self, key: &str, role: &str, content: &str) {
+        self.add_full_message(
+            key,
+            ProviderMessage {
+                role: role.to_string(),
+                content: content.to_string(),
+                tool_calls: vec![],
+                tool_call_id: None,
+                images: vec![],
+            },
+        );
+    }
+    pub fn add_full_message(&self, key: &str, msg: ProviderMessage) {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        session.state.messages.push(msg);
+        let excess = self.history_excess(&session.state.messages);
+        session.drain_front(excess);
+        session.dirty = true;
+    }
+    pub fn get_history(&self, key: &str) -> Vec<ProviderMessage> {
+        self.get_or_create(key).lock().state.messages.clone()
+    }
+    pub fn get_summary(&self, key: &str) -> String {
+        self.get_or_create(key).lock().state.summary.clone()
+    }
+    pub fn get_model(&self, key: &str) -> Option<ModelSelection> {
+        self.get_or_create(key).lock().state.model.clone()
+    }
+    /// Pins the session to a model; `None` goes back to the configured one.
+    pub fn set_model(&self, key: &str, model: Option<ModelSelection>) {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        session.state.model = model;
+        session.dirty = true;
+    }
+    /// Generation overrides set with `/set` in this session.
+    pub fn get_settings(&self, key: &str) -> GenerationSettings {
+        self.get_or_create(key).lock().state.settings.clone()
+    }
+    pub fn set_settings(&self, key: &str, settings: GenerationSettings) {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        session.state.settings = settings;
+        session.dirty = true;
+    }
+    /// Marks whether the session's last turn stopped at the tool iteration
+    /// limit.
+    pub fn set_interrupted(&self, key: &str, interrupted: bool) {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        if session.state.interrupted != interrupted {
+            session.state.interrupted = interrupted;
+            session.dirty = true;
+        }
+    }
+    pub fn is_interrupted(&self, key: &str) -> bool {
+        self.get_or_create(key).lock().state.interrupted
+    }
+    /// Adds `content` as the result of every tool call in the latest
+    /// assistant message that has none yet, so an interrupted turn still
+    /// leaves a well-formed history.
+    pub fn close_pending_tool_calls(&self, key: &str, content: &str) {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        let messages = &session.state.messages;
+        let Some(pos) = messages
+            .iter()
+            .rposition(|m| m.role == "assistant" && !m.tool_calls.is_empty())
+        else {
+            return;
+        };
+        let answered: Vec<String> = messages[pos + 1..]
+            .iter()
+            .filter_map(|m| m.tool_call_id.clone())
+            .collect();
+        let pending: Vec<String> = messages[pos]
+            .tool_calls
+            .iter()
+            .map(|tc| tc.id.clone())
+            .filter(|id| !answered.contains(id))
+            .collect();
+        for id in pending {
+            session
+                .state
+                .messages
+                .push(ProviderMessage::tool(content, &id));
+            session.dirty = true;
+        }
+    }
+    /// Replaces `folded`, the oldest messages, with `summary`, which should
+    /// cover both them and the previous summary. Does nothing and returns
+    /// false when history no longer starts with `folded` (the session was
+    /// reset, trimmed or undone into while the summary was being written).
+    pub fn apply_summary(&self, key: &str, summary: &str, folded: &[ProviderMessage]) -> bool {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        if !session.state.messages.starts_with(folded) {
+            return false;
+        }
+        session.drain_front(folded.len());
+        session.state.summary = summary.to_string();
+        true
+    }
+    /// Removes the last turn: the latest user message and the assistant and
+    /// tool messages after it. Returns that user message, or `None` when
+    /// there is no turn to remove.
+    pub fn pop_turn(&self, key: &str) -> Option<ProviderMessage> {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        let start = session
+            .state
+            .messages
+            .iter()
+            .rposition(|m| m.role == "user")?;
+        let user = session.state.messages[start].clone();
+        session.truncate(start);
+        session.state.interrupted = false;
+        Some(user)
+    }
+    /// Archives the conversation in the store and starts it over; the pinned
+    /// model and `/set` settings stay. Returns false when there was no
+    /// conversation to archive.
+    pub fn archive(&self, key: &str) -> anyhow::Result<bool> {
+        let session = self.get_or_create(key);
+        let mut session = session.lock();
+        if session.state.messages.is_empty() && session.state.summary.is_empty() {
+            return Ok(false);
+        }
+        self.store.archive(key, &session.state)?;
+        let len = session.state.messages.len();
+        session.drain_front(len);
+        session.state.summary.clear();
+        session.state.interrupted = false;
+        drop(session);
+        self.save(key)?;
+        Ok(true)
+    }
+    pub fn save(&self, key: &str) -> anyhow::Result<()> {
+        let Some(session) = self
+            .sessions
+            .read()
+            .get(key)
+            .map(|cached| cached.session.clone())
+        else {
+            return Ok(());
+        };
+        // Holding the session lock while writing keeps saves of one session
+        // in order.
+        let mut session = session.lock();
+        if !session.dirty {
+            return Ok(());
+        }
+        if session.state.is_empty() {
+            // Nothing left to keep, e.g. after /new or undoing the only turn.
+            self.store.delete(key)?;
+        } else {
+            let delta = MessageDelta {
+                dropped: session.dropped,
+                kept: session.saved,
+            };
+            self.store.save(key, &session.state, delta)?;
+        }
+        session.saved = session.state.messages.len();
+        session.dropped = 0;
+        session.dirty = false;
+        Ok(())
+    }
+    /// Stored sessions, most recently active first.
+    pub fn list(&self) -> anyhow::Result<Vec<SessionInfo>> {
+        let mut sessions = self.store.list()?;
+        sessions.sort_by(|a, b| b.last_active.cmp(&a.last_active));
+        Ok(sessions)
+    }
+    /// The stored state of `key`, `None` when nothing is stored under it.
+    pub fn transcript(&self, key: &str) -> anyhow::Result<Option<Transcript>> {
+        Ok(self.store.load(key)?.map(|state| Transcript {
+            key: key.to_string(),
+            summary: state.summary,
+            model: state.model,
+            messages: state.messages,
+        }))
+    }
+    /// Forgets `key` in memory and in the store. Returns false when nothing
+    /// was stored under it.
+    pub fn delete(&self, key: &str) -> anyhow::Result<bool> {
+        self.sessions.write().remove(key);
+        self.store.delete(key)
+    }
+    /// Deletes archived copies of `key`, or of every session when `None`,
+    /// made before `before` (at any time when `None`).
+    pub fn delete_archives(
+        &self,
+        key: Option<&str>,
+        before: Option<chrono::DateTime<chrono::Local>>,
+    ) -> anyhow::Result<usize> {
+        self.store.delete_archives(key, before)
+    }
+    /// Deletes sessions last active before `before`, skipping any that a
+    /// turn is using right now. Returns the deleted keys.
+    pub fn delete_idle(
+        &self,
+        before: chrono::DateTime<chrono::Local>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut deleted = Vec::new();
+        for info in self.store.list()? {
+            if info.last_active >= before {
+                continue;
+            }
+            {
+                let mut sessions = self.sessions.write();
+                if let Some(cached) = sessions.get(&info.key) {
+                    let idle = Arc::strong_count(&cached.session) == 1
+                        && cached.session.try_lock().is_some_and(|s| !s.dirty);
+                    if !idle {
+                        continue;
+                    }
+                    sessions.remove(&info.key);
+                }
+            }
+            if self.store.delete(&info.key)? {
+                deleted.push(info.key);
+            }
+        }
+        Ok(deleted)
+    }
+    /// Cuts stored sessions that are over `max_messages`, e.g. after the
+    /// limit was lowered. Returns how many were cut.
+    pub fn trim_stored(&self) -> anyhow::Result<usize> {
+        let mut trimmed = 0;
+        for info in self.store.list()? {
+            if info.messages <= self.max_messages {
+                continue;
+            }
+            let session = self.get_or_create(&info.key);
+            let mut session = session.lock();
+            let excess = self.history_excess(&session.state.messages);
+            if excess == 0 {
+                continue;
+            }
+            session.drain_front(excess);
+            drop(session);
+            self.save(&info.key)?;
+            trimmed += 1;
+        }
+        Ok(trimmed)
+    }
+}
+/// Copies every JSON session in `sessions_dir` into `target` and moves the
+/// files to `<sessions>/migrated/`. Files from before the key was stored are
+/// imported as `channel:rest`, the usual key shape. Returns the imported
+/// keys.
+pub fn migrate_json(sessions_dir: &Path, target: &dyn SessionStore) -> anyhow::Result<Vec<String>> {
+    let source = JsonStore::new(sessions_dir.to_path_buf());
+    let migrated_dir = sessions_dir.join("migrated");
+    let mut imported = Vec::new();
+    for entry in source.entries()? {
+        let key = match entry.key() {
+            Some(key) => key.to_string(),
+            None => entry.stem.replacen('_', ":", 1),
+        };
+        let path = entry.path.clone();
+        target.save(&key, &entry.into_state(), MessageDelta::default())?;
+        std::fs::create_dir_all(&migrated_dir)?;
+        if let Some(name) = path.file_name() {
+            std::fs::rename(&path, migrated_dir.join(name))?;
+        }
+        imported.push(key);
+    }
+    Ok(imported)
+}
+/// Parses an age like `30d`: a number with `m` (minutes), `h`, `d` or `w`.
+pub fn parse_age(text: &str) -> anyhow::Result<chrono::Duration> {
+    let text = text.trim();
+    let split = text.char_indices().last().map_or(0, |(idx, _)| idx);
+    let (number, unit) = text.split_at(split);
+    let number =
+        i64::from(number.parse::<u32>().map_err(|_| {
+            anyhow::anyhow!("invalid age {:?}: expected e.g. 30d, 12h or 2w", text)
+        })?);
+    match unit {
+        "m" => Ok(chrono::Duration::minutes(number)),
+        "h" => Ok(chrono::Duration::hours(number)),
+        "d" => Ok(chrono::Duration::days(number)),
+        "w" => Ok(chrono::Duration::weeks(number)),
+        _ => anyhow::bail!("invalid age {:?}: the unit must be m, h, d or w", text),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    #[test]
+    fn summary_survives_reload_and_legacy_files_still_load() {
+        let tmp = TempDir::new().expect("tempdir");
+        let dir = tmp.path().to_path_buf();
+        std::fs::write(
+            dir.join("telegram_1.json"),
+            r#"[{"role":"user","content":"old format"}]"#,
+        )
+        .expect("legacy file");
+        let sessions = SessionManager::new(dir.clone());
+        assert_eq!(sessions.get_history("telegram:1")[0].content, "old format");
+        sessions.add_message("telegram:1", "assistant", "hi");
+        sessions.add_message("telegram:1", "user", "latest");
+        let folded = sessions.get_history("telegram:1")[..2].to_vec();
+        assert!(sessions.apply_summary("telegram:1", "User said hello.", &folded));
+        sessions.save("telegram:1").expect("save");
+        let reloaded = SessionManager::new(dir);
+        assert_eq!(reloaded.get_summary("telegram:1"), "User said hello.");
+        let history = reloaded.get_history("telegram:1");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].content, "latest");
+    }
+    #[test]
+    fn listing_keeps_the_original_key() {
+        let tmp = TempDir::new().expect("tempdir");
+        let sessions = SessionManager::new(tmp.path().to_path_buf());
+        sessions.add_message("telegram:1/2", "user", "hi");
+        sessions.save("telegram:1/2").expect("save");
+        std::fs::write(tmp.path().join("cli_old.json"), "[]").expect("legacy file");
+        let mut keys: Vec<String> = sessions
+            .list()
+            .expect("list")
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["cli_old", "telegram:1/2"]);
+        let transcript = sessions
+            .transcript("telegram:1/2")
+            .expect("load")
+            .expect("transcript");
+        assert_eq!(transcript.messages[0].content, "hi");
+        assert!(sessions.delete("telegram:1/2").expect("delete"));
+        assert!(sessions.transcript("telegram:1/2").expect("load").is_none());
+        assert!(sessions.get_history("telegram:1/2").is_empty());
+        assert_eq!(parse_age("30d").expect("age"), chrono::Duration::days(30));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("-1d").is_err());
+    }
+    #[test]
+    fn sqlite_store_writes_only_what_changed() {
+        let tmp = TempDir::new().expect("tempdir");
+        let open = || {
+            let store = SqliteStore::open(&tmp.path().join("sessions.db")).expect("open");
+            SessionManager::with_store(Arc::new(store), 8)
+        };
+        let sessions = open();
+        for turn in ["one", "two", "three"] {
+            sessions.add_message("cli:a", "user", turn);
+            sessions.add_message("cli:a", "assistant", &format!("re {turn}"));
+            sessions.save("cli:a").expect("save");
+        }
+        let folded = sessions.get_history("cli:a")[..2].to_vec();
+        assert!(sessions.apply_summary("cli:a", "Said one.", &folded));
+        sessions.pop_turn("cli:a").expect("turn");
+        sessions.add_message("cli:a", "user", "four");
+        sessions.set_model(
+            "cli:a",
+            Some(ModelSelection {
+                provider: None,
+                model: "m".to_string(),
+            }),
+        );
+        sessions.save("cli:a").expect("save");
+        let reloaded = open();
+        let history: Vec<String> = reloaded
+            .get_history("cli:a")
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(history, ["two", "re two", "four"]);
+        assert_eq!(reloaded.get_summary("cli:a"), "Said one.");
+        assert_eq!(
+            reloaded.get_model("cli:a").map(|m| m.model).as_deref(),
+            Some("m")
+        );
+        assert!(reloaded.archive("cli:a").expect("archive"));
+        let info = open().list().expect("list");
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].messages, 0);
+        assert!(reloaded.delete("cli:a").expect("delete"));
+        assert!(open().list().expect("list").is_empty());
+    }
+    #[test]
+    fn idle_sessions_are_evicted_least_recently_used_first() {
+        let tmp = TempDir::new().expect("tempdir");
+        let sessions =
+            SessionManager::with_store(Arc::new(JsonStore::new(tmp.path().to_path_buf())), 2);
+        for key in ["a", "b"] {
+            sessions.add_message(key, "user", key);
+            sessions.save(key).expect("save");
+        }
+        sessions.get_history("a");
+        // Unsaved changes keep "c" loaded even when it is over the limit.
+        sessions.add_message("c", "user", "c");
+        let loaded = |key: &str| sessions.sessions.read().contains_key(key);
+        assert!(loaded("a") && !loaded("b") && loaded("c"));
+        assert_eq!(sessions.get_history("b")[0].content, "b");
+    }
+    #[test]
+    fn json_sessions_migrate_into_sqlite() {
+        let tmp = TempDir::new().expect("tempdir");
+        let dir = tmp.path().to_path_buf();
+        let json = SessionManager::new(dir.clone());
+        json.add_message("telegram:1/2", "user", "hi");
+        json.save("telegram:1/2").expect("save");
+        std::fs::write(
+            dir.join("cli_old.json"),
+            r#"[{"role":"user","content":"legacy"}]"#,
+        )
+        .expect("legacy file");
+        let store = Arc::new(SqliteStore::open(&dir.join("sessions.db")).expect("open"));
+        let mut imported = migrate_json(&dir, store.as_ref()).expect("migrate");
+        imported.sort();
+        assert_eq!(imported, ["cli:old", "telegram:1/2"]);
+        assert!(dir.join("migrated").join("cli_old.json").exists());
+        let sqlite = SessionManager::with_store(store, 8);
+        assert_eq!(sqlite.get_history("cli:old")[0].content, "legacy");
+        assert_eq!(sqlite.get_history("telegram:1/2")[0].content, "hi");
+    }
+}
@@ -0,0 +1,193 @@
+use super::{MessageDelta, SessionInfo, SessionState, SessionStore};
+use parking_lot::Mutex;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    key TEXT PRIMARY KEY,
+    summary TEXT NOT NULL DEFAULT '',
+    model TEXT,
+    settings TEXT,
+    interrupted INTEGER NOT NULL DEFAULT 0,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS messages (
+    key TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (key, seq)
+);
+CREATE TABLE IF NOT EXISTS archived_sessions (
+    key TEXT NOT NULL,
+    archived_at INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+";
+/// All sessions in one SQLite database. Messages are rows ordered by `seq`,
+/// so a save after a turn only inserts that turn's messages.
+pub struct SqliteStore {
+    // Saves of different sessions share the connection; each is a short
+    // transaction, unlike the full rewrites of the JSON store.
+    conn: Mutex<Connection>,
+}
+impl SqliteStore {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        conn.execute_batch("PRAGMA synchronous = NORMAL;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+impl SessionStore for SqliteStore {
+    fn load(&self, key: &str) -> anyhow::Result<Option<SessionState>> {
+        let conn = self.conn.lock();
+        let row = conn
+            .query_row(
+                "SELECT summary, model, settings, interrupted FROM sessions WHERE key = ?1",
+                [key],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, bool>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((summary, model, settings, interrupted)) = row else {
+            return Ok(None);
+        };
+        let mut stmt =
+            conn.prepare_cached("SELECT data FROM messages WHERE key = ?1 ORDER BY seq")?;
+        let messages = stmt
+            .query_map([key], |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Some(SessionState {
+            summary,
+            model: model.as_deref().map(serde_json::from_str).transpose()?,
+            settings: settings
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?
+                .unwrap_or_default(),
+            interrupted,
+            messages,
+        }))
+    }
+    fn save(&self, key: &str, state: &SessionState, delta: MessageDelta) -> anyhow::Result<()> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        let first: i64 = tx.query_row(
+            "SELECT COALESCE(MIN(seq), 0) FROM messages WHERE key = ?1",
+            [key],
+            |row| row.get(0),
+        )?;
+        let kept = delta.kept.min(state.messages.len());
+        let start = first + delta.dropped as i64;
+        let end = start + kept as i64;
+        tx.execute(
+            "DELETE FROM messages WHERE key = ?1 AND (seq < ?2 OR seq >= ?3)",
+            params![key, start, end],
+        )?;
+        {
+            let mut insert =
+                tx.prepare_cached("INSERT INTO messages (key, seq, data) VALUES (?1, ?2, ?3)")?;
+            for (seq, message) in (end..).zip(&state.messages[kept..]) {
+                insert.execute(params![key, seq, serde_json::to_string(message)?])?;
+            }
+        }
+        let model = state
+            .model
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let settings = if state.settings.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&state.settings)?)
+        };
+        tx.execute(
+            "INSERT INTO sessions (key, summary, model, settings, interrupted, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(key) DO UPDATE SET summary = excluded.summary, model = excluded.model,
+                 settings = excluded.settings, interrupted = excluded.interrupted,
+                 updated_at = excluded.updated_at",
+            params![
+                key,
+                state.summary,
+                model,
+                settings,
+                state.interrupted,
+                now_millis()
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn delete(&self, key: &str) -> anyhow::Result<bool> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM messages WHERE key = ?1", [key])?;
+        let deleted = tx.execute("DELETE FROM sessions WHERE key = ?1", [key])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+    /// Keeps the session as one JSON row in `archived_sessions`.
+    fn archive(&self, key: &str, state: &SessionState) -> anyhow::Result<()> {
+        self.conn.lock().execute(
+            "INSERT INTO archived_sessions (key, archived_at, data) VALUES (?1, ?2, ?3)",
+            params![key, now_millis(), serde_json::to_string(state)?],
+        )?;
+        Ok(())
+    }
+    fn list(&self) -> anyhow::Result<Vec<SessionInfo>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT s.key, s.updated_at, COUNT(m.seq), COALESCE(SUM(LENGTH(m.data)), 0)
+             FROM sessions s LEFT JOIN messages m ON m.key = s.key GROUP BY s.key",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+        let mut sessions = Vec::new();
+        for row in rows {
+            let (key, updated_at, messages, size) = row?;
+            sessions.push(SessionInfo {
+                key,
+                messages: messages as usize,
+                last_active: chrono::DateTime::from_timestamp_millis(updated_at)
+                    .unwrap_or_default()
+                    .into(),
+                size: size as u64,
+            });
+        }
+        Ok(sessions)
+    }
+    fn delete_archives(
+        &self,
+        key: Option<&str>,
+        before: Option<chrono::DateTime<chrono::Local>>,
+    ) -> anyhow::Result<usize> {
+        Ok(self.conn.lock().execute(
+            "DELETE FROM archived_sessions
+             WHERE (?1 IS NULL OR key = ?1) AND (?2 IS NULL OR archived_at < ?2)",
+            params![key, before.map(|time| time.timestamp_millis())],
+        )?)
+    }
+}
@@ -1,10 +1,14 @@
 use super::{Tool, ToolResult, arg_string};
+use crate::approval::{ApprovalBroker, Decision, remember_prefix};
 use crate::config::ExecToolsConfig;
 use async_trait::async_trait;
+use parking_lot::RwLock;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
 pub struct ExecTool {
@@ -12,6 +16,10 @@ pub struct ExecTool {
     policy: ExecPolicyConfig,
     stdout_max_bytes: usize,
     stderr_max_bytes: usize,
+    approvals: Arc<RwLock<Option<Arc<ApprovalBroker>>>>,
+    approval_timeout: Duration,
+    remember_approvals: bool,
+    llm_confirm: bool,
 }
 impl ExecTool {
     pub fn new(workspace: PathBuf, config: ExecToolsConfig) -> Self {
@@ -19,13 +27,70 @@ impl ExecTool {
         let stderr_max_bytes = config.stderr_max_bytes.max(1024);
         Self {
             workspace,
+            approval_timeout: Duration::from_secs(config.approval_timeout_secs.max(1)),
+            remember_approvals: config.remember_approvals,
+            llm_confirm: config.llm_confirm,
             policy: ExecPolicyConfig::from_config(config),
             stdout_max_bytes,
             stderr_max_bytes,
+            approvals: Arc::new(RwLock::new(None)),
+        }
+    }
+    pub fn with_approvals(mut self, approvals: Arc<RwLock<Option<Arc<ApprovalBroker>>>>) -> Self {
+        self.approvals = approvals;
+        self
+    }
+    /// Gets a `RequireConfirm` command approved: by the chat's user when
+    /// there is one, otherwise by the model's `confirm` flag if allowed.
+    async fn approve(
+        &self,
+        command: &str,
+        reason: &str,
+        confirm: bool,
+        channel: &str,
+        chat_id: &str,
+    ) -> Result<(), String> {
+        let broker = self.approvals.read().clone();
+        let Some(broker) = broker.filter(|b| b.can_ask(channel)) else {
+            return match (self.llm_confirm, confirm) {
+                (true, true) => Ok(()),
+                (true, false) => Err(format!("{reason}. Re-run with confirm=true.")),
+                (false, _) => Err(format!(
+                    "{reason}, and there is no user to approve it here."
+                )),
+            };
+        };
+        let prefix = remember_prefix(command).filter(|_| self.remember_approvals);
+        if prefix
+            .as_deref()
+            .is_some_and(|p| broker.is_remembered(channel, chat_id, p))
+        {
+            return Ok(());
+        }
+        match broker
+            .request(
+                channel,
+                chat_id,
+                command,
+                reason,
+                prefix,
+                self.approval_timeout,
+            )
+            .await
+        {
+            Decision::Approve | Decision::ApproveAlways => Ok(()),
+            Decision::Deny => Err(
+                "The user denied this command. Do not retry it; ask them how to proceed."
+                    .to_string(),
+            ),
+            Decision::TimedOut => Err(format!(
+                "Nobody approved this command within {}s, so it was not run.",
+                self.approval_timeout.as_secs()
+            )),
         }
     }
 }
-fn normalise_command(cmd: &str) -> String {
+pub(crate) fn normalise_command(cmd: &str) -> String {
     cmd.to_ascii_lowercase()
         .split_whitespace()
         .collect::<Vec<_>>()
@@ -113,7 +178,7 @@ impl ExecPolicyConfig {
         }
     }
 }
-fn starts_with_command(command: &str, prefix: &str) -> bool {
+pub(crate) fn starts_with_command(command: &str, prefix: &str) -> bool {
     command == prefix
         || command
             .strip_prefix(prefix)
@@ -186,6 +251,27 @@ where
     }
     Ok((captured, truncated))
 }
+/// Kills the command's process group when dropped, so a timed-out or
+/// cancelled turn doesn't leave `sh -c` grandchildren running.
+struct ProcessGroupGuard(Option<u32>);
+impl ProcessGroupGuard {
+    /// The command finished on its own; leave anything it backgrounded alone.
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.0.and_then(|pgid| libc::pid_t::try_from(pgid).ok()) {
+            // SAFETY: killpg only sends a signal; a group that has already
+            // exited just makes it fail with ESRCH.
+            unsafe {
+                libc::killpg(pgid, libc::SIGKILL);
+            }
+        }
+    }
+}
 #[async_trait]
 impl Tool for ExecTool {
     fn name(&self) -> &str {
@@ -195,19 +281,27 @@ impl Tool for ExecTool {
         "Execute a shell command in workspace"
     }
     fn parameters(&self) -> Value {
-        serde_json::json!({
+        let mut params = serde_json::json!({
             "type": "object",
             "properties": {
-                "command": { "type": "string", "description": "Command to execute" },
-                "confirm": {
-                    "type": "boolean",
-                    "description": "Set true to run commands that can change system state"
-                }
+                "command": { "type": "string", "description": "Command to execute" }
             },
             "required": ["command"]
-        })
+        });
+        if self.llm_confirm {
+            params["properties"]["confirm"] = serde_json::json!({
+                "type": "boolean",
+                "description": "Set true to run commands that can change system state when no user is available to approve them"
+            });
+        }
+        params
     }
-    async fn execute(&self, args: HashMap<String, Value>, _: &str, _: &str) -> ToolResult {
+    async fn execute(
+        &self,
+        args: HashMap<String, Value>,
+        channel: &str,
+        chat_id: &str,
+    ) -> ToolResult {
         let command = match arg_string(&args, "command") {
             Some(v) if !v.is_empty() => v,
             _ => return ToolResult::error("Missing required parameter: command"),
@@ -219,10 +313,15 @@ impl Tool for ExecTool {
             .unwrap_or(false);
         match classify_command(&normalised, &self.policy) {
             ExecPolicy::Deny(reason) => return ToolResult::error(reason),
-            ExecPolicy::RequireConfirm(reason) if !confirm => {
-                return ToolResult::error(&format!("{reason}. Re-run with confirm=true."));
+            ExecPolicy::RequireConfirm(reason) => {
+                if let Err(err) = self
+                    .approve(&command, reason, confirm, channel, chat_id)
+                    .await
+                {
+                    return ToolResult::error(&err);
+                }
             }
-            ExecPolicy::Allow | ExecPolicy::RequireConfirm(_) => {}
+            ExecPolicy::Allow => {}
         }
         let timeout = std::time::Duration::from_secs(30);
         let start = std::time::Instant::now();
@@ -231,20 +330,25 @@ impl Tool for ExecTool {
             cmd.args(["/C", &command]);
             cmd.current_dir(&self.workspace)
                 .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
+                .stderr(Stdio::piped())
+                .kill_on_drop(true);
             cmd.spawn()
         } else {
             let mut cmd = tokio::process::Command::new("sh");
             cmd.args(["-c", &command]);
             cmd.current_dir(&self.workspace)
                 .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
+                .stderr(Stdio::piped())
+                .kill_on_drop(true);
+            #[cfg(unix)]
+            cmd.process_group(0);
             cmd.spawn()
         };
         let mut child = match child {
             Ok(child) => child,
             Err(e) => return ToolResult::error(&format!("Failed to execute command: {}", e)),
         };
+        let mut group = ProcessGroupGuard(child.id());
         let stdout = child.stdout.take();
         let stderr = child.stderr.take();
         let (Some(stdout), Some(stderr)) = (stdout, stderr) else {
@@ -259,7 +363,10 @@ impl Tool for ExecTool {
             tokio::spawn(async move { read_stream_limited(stderr, stderr_cap).await });
         let status = match tokio::time::timeout(timeout, child.wait()).await {
             Ok(result) => match result {
-                Ok(status) => status,
+                Ok(status) => {
+                    group.disarm();
+                    status
+                }
                 Err(e) => return ToolResult::error(&format!("Failed to execute command: {}", e)),
             },
             Err(_) => {
@@ -4,10 +4,15 @@ mod exec;
 mod fs;
 mod memory_tool;
 mod messaging;
+mod permissions;
 mod web;
+use crate::approval::ApprovalBroker;
 use crate::bus::{InboundMessage, MessageBus};
+use crate::config::{AgentsConfig, ToolPermissionsConfig};
+use crate::context_window;
 use crate::providers::ToolDefinition;
 use crate::providers::{LlmResponse, Message, Provider};
+use crate::usage::{UsageContext, UsageLedger};
 use async_trait::async_trait;
 pub use cron_tool::CronTool;
 pub use device::{I2cTool, SpiTool};
@@ -16,6 +21,7 @@ pub use fs::{AppendFileTool, EditFileTool, ListDirTool, ReadFileTool, WriteFileT
 pub use memory_tool::MemoryTool;
 pub use messaging::{MessageTool, SpawnTool, SubagentTool};
 use parking_lot::{Mutex, RwLock};
+pub use permissions::ToolPermissions;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -46,8 +52,13 @@ pub struct SubagentManager {
     model: String,
     bus: Arc<MessageBus>,
     tools: ToolRegistry,
-    max_iterations: i32,
+    /// Generation defaults and per-channel overrides for subagent runs.
+    generation: AgentsConfig,
     tool_output_max_chars: usize,
+    usage: Option<(Arc<UsageLedger>, String)>,
+    /// Profile this manager works for; routes announcements back to it.
+    agent_name: String,
+    permissions: ToolPermissionsConfig,
 }
 #[derive(Clone)]
 pub struct ToolLoopConfig<'a> {
@@ -59,6 +70,12 @@ pub struct ToolLoopConfig<'a> {
     pub channel: &'a str,
     pub chat_id: &'a str,
     pub tool_output_max_chars: usize,
+    /// Model context size in tokens; the request is trimmed to fit it.
+    pub context_size: usize,
+    /// Ledger plus the provider name to bill calls to.
+    pub usage: Option<(&'a UsageLedger, &'a str)>,
+    pub session_key: &'a str,
+    pub permissions: &'a ToolPermissions,
 }
 impl SubagentManager {
     pub fn new(
@@ -69,6 +86,8 @@ impl SubagentManager {
         max_iterations: i32,
         tool_output_max_chars: usize,
     ) -> Self {
+        let mut generation = AgentsConfig::default();
+        generation.defaults.max_tool_iterations = max_iterations;
         Self {
             tasks: Arc::new(Mutex::new(HashMap::new())),
             next_id: AtomicU64::new(1),
@@ -76,10 +95,29 @@ impl SubagentManager {
             model,
             bus,
             tools,
-            max_iterations,
+            generation,
             tool_output_max_chars,
+            usage: None,
+            agent_name: String::new(),
+            permissions: ToolPermissionsConfig::default(),
         }
     }
+    pub fn with_usage_ledger(mut self, ledger: Arc<UsageLedger>, provider_name: String) -> Self {
+        self.usage = Some((ledger, provider_name));
+        self
+    }
+    pub fn with_generation(mut self, generation: AgentsConfig) -> Self {
+        self.generation = generation;
+        self
+    }
+    pub fn with_agent_name(mut self, name: &str) -> Self {
+        self.agent_name = name.to_string();
+        self
+    }
+    pub fn with_permissions(mut self, permissions: ToolPermissionsConfig) -> Self {
+        self.permissions = permissions;
+        self
+    }
     pub fn spawn(
         self: &Arc<Self>,
         task: String,
@@ -129,19 +167,33 @@ impl SubagentManager {
             ),
             Message::user(&task),
         ];
+        let session_key = format!("subagent:{}:{}", origin_channel, origin_chat_id);
+        let settings = self.generation.generation_for_channel(&origin_channel);
+        // Whoever asked for the task, the subagent itself has no sender.
+        let permissions =
+            ToolPermissions::resolve(&self.permissions, &origin_channel, &origin_chat_id, None);
         let loop_result = run_tool_loop(
             ToolLoopConfig {
                 provider: self.provider.as_ref(),
                 model: &self.model,
                 tools: &self.tools,
-                max_iterations: self.max_iterations,
-                options: HashMap::from([
-                    ("max_tokens".to_string(), serde_json::json!(4096)),
-                    ("temperature".to_string(), serde_json::json!(0.7)),
-                ]),
+                max_iterations: settings
+                    .max_tool_iterations
+                    .unwrap_or(self.generation.defaults.max_tool_iterations),
+                options: settings.to_options(),
                 channel: &origin_channel,
                 chat_id: &origin_chat_id,
                 tool_output_max_chars: self.tool_output_max_chars,
+                context_size: context_window::context_size(
+                    &self.model,
+                    &self.generation.defaults.context_windows,
+                ),
+                usage: self
+                    .usage
+                    .as_ref()
+                    .map(|(ledger, provider)| (ledger.as_ref(), provider.as_str())),
+                session_key: &session_key,
+                permissions: &permissions,
             },
             &mut messages,
         )
@@ -203,7 +255,8 @@ impl SubagentManager {
                 content: announce,
                 media: None,
                 session_key: format!("subagent:{}", task_id),
-                metadata: None,
+                metadata: (!self.agent_name.is_empty())
+                    .then(|| HashMap::from([("agent".to_string(), self.agent_name.clone())])),
             })
             .await;
     }
@@ -214,13 +267,44 @@ pub async fn run_tool_loop(
 ) -> anyhow::Result<ToolLoopResult> {
     let mut iterations = 0;
     let mut content = String::new();
+    // Subagents have no sender of their own, so only the global budget applies.
+    let usage_ctx = cfg.usage.map(|(_, provider)| UsageContext {
+        session_key: cfg.session_key,
+        channel: cfg.channel,
+        provider,
+        model: cfg.model,
+        sender_id: "",
+        background: false,
+    });
     while iterations < cfg.max_iterations {
         iterations += 1;
-        let defs = cfg.tools.to_provider_defs();
+        if let (Some((ledger, _)), Some(ctx)) = (cfg.usage, usage_ctx.as_ref())
+            && let Some(notice) = ledger.budget_exhausted(ctx)
+        {
+            content = notice;
+            break;
+        }
+        let defs = cfg.tools.to_provider_defs(cfg.permissions);
+        let max_output = cfg
+            .options
+            .get("max_tokens")
+            .and_then(Value::as_u64)
+            .unwrap_or_default() as usize;
+        let budget = context_window::prompt_budget(
+            cfg.context_size,
+            max_output,
+            context_window::estimate_tool_defs(&defs),
+        );
+        context_window::fit(messages, budget);
         let response: LlmResponse = cfg
             .provider
             .chat_with_options(messages, Some(&defs), cfg.model, cfg.options.clone())
             .await?;
+        if let (Some((ledger, _)), Some(ctx), Some(usage)) =
+            (cfg.usage, usage_ctx.as_ref(), response.usage.as_ref())
+        {
+            ledger.record(ctx, usage);
+        }
         content = response.content.clone();
         if response.tool_calls.is_empty() {
             break;
@@ -230,14 +314,18 @@ pub async fn run_tool_loop(
             content: response.content,
             tool_calls: response.tool_calls.clone(),
             tool_call_id: None,
+            images: vec![],
         });
         for tc in response.tool_calls {
             let tool_name = tc.name.unwrap_or_default();
             let args = tc.arguments.unwrap_or_default();
-            let result = if let Some(tool) = cfg.tools.get(&tool_name) {
-                tool.execute(args, cfg.channel, cfg.chat_id).await
-            } else {
-                ToolResult::error(&format!("tool '{}' not found", tool_name))
+            let result = match (
+                cfg.tools.get(&tool_name),
+                cfg.permissions.check(&tool_name, &args),
+            ) {
+                (None, _) => ToolResult::error(&format!("tool '{}' not found", tool_name)),
+                (Some(_), Err(denied)) => ToolResult::error(&denied),
+                (Some(tool), Ok(())) => tool.execute(args, cfg.channel, cfg.chat_id).await,
             };
             let llm_content = result
                 .error
@@ -307,6 +395,7 @@ pub struct ToolRegistry {
     web_config: WebToolsConfig,
     exec_config: ExecToolsConfig,
     cron_service: Arc<parking_lot::Mutex<crate::cron::CronService>>,
+    approvals: Arc<RwLock<Option<Arc<ApprovalBroker>>>>,
 }
 impl ToolRegistry {
     #[allow(dead_code)]
@@ -364,6 +453,7 @@ impl ToolRegistry {
             web_config,
             exec_config,
             cron_service,
+            approvals: Arc::new(RwLock::new(None)),
         };
         registry.register_builtin_tools();
         registry
@@ -392,17 +482,22 @@ impl ToolRegistry {
             self.workspace.clone(),
             self.restrict_to_workspace,
         ));
-        self.register(ExecTool::new(
-            self.workspace.clone(),
-            self.exec_config.clone(),
-        ));
-        let shared_http = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .connect_timeout(std::time::Duration::from_secs(10))
-            .pool_max_idle_per_host(4)
-            .redirect(reqwest::redirect::Policy::limited(5))
-            .build()
-            .unwrap_or_default();
+        self.register(
+            ExecTool::new(self.workspace.clone(), self.exec_config.clone())
+                .with_approvals(self.approvals.clone()),
+        );
+        let shared_http =
+            crate::http::client_builder(self.web_config.proxy.as_deref(), &self.web_config.http)
+                .and_then(|b| {
+                    // Pages are read whole, so they get a deadline as well.
+                    Ok(b.redirect(reqwest::redirect::Policy::limited(5))
+                        .timeout(crate::http::request_timeout(&self.web_config.http))
+                        .build()?)
+                })
+                .unwrap_or_else(|err| {
+                    tracing::error!("web tools http client: {}", err);
+                    reqwest::Client::default()
+                });
         self.register(WebSearchTool::from_config(
             &self.web_config,
             shared_http.clone(),
@@ -424,6 +519,10 @@ impl ToolRegistry {
     pub fn register<T: Tool + 'static>(&mut self, tool: T) {
         self.tools.insert(tool.name().to_string(), Arc::new(tool));
     }
+    /// Keeps only the tools named in `allowed`.
+    pub fn retain(&mut self, allowed: &[String]) {
+        self.tools.retain(|name, _| allowed.contains(name));
+    }
     pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
         self.tools.get(name).cloned()
     }
@@ -438,14 +537,26 @@ impl ToolRegistry {
     pub fn set_subagent_manager(&self, manager: Arc<SubagentManager>) {
         *self.subagent_manager.write() = Some(manager);
     }
-    pub fn get_summaries(&self) -> Vec<String> {
-        let mut result: Vec<String> = self.tools.values().map(|t| t.summary()).collect();
+    pub fn set_approval_broker(&self, broker: Arc<ApprovalBroker>) {
+        *self.approvals.write() = Some(broker);
+    }
+    /// Summaries of the tools `permissions` allows, for the system prompt.
+    pub fn get_summaries(&self, permissions: &ToolPermissions) -> Vec<String> {
+        let mut result: Vec<String> = self
+            .tools
+            .values()
+            .filter(|t| permissions.allows_tool(t.name()))
+            .map(|t| t.summary())
+            .collect();
         result.sort();
         result
     }
-    pub fn to_provider_defs(&self) -> Vec<ToolDefinition> {
+    /// Definitions of the tools `permissions` allows; the rest are never
+    /// shown to the model.
+    pub fn to_provider_defs(&self, permissions: &ToolPermissions) -> Vec<ToolDefinition> {
         self.tools
             .values()
+            .filter(|tool| permissions.allows_tool(tool.name()))
             .map(|tool| ToolDefinition {
                 tool_type: "function".to_string(),
                 function: crate::providers::ToolFunctionDefinition {
@@ -1094,6 +1205,42 @@ mod tests {
             text.len()
         );
     }
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn exec_cancellation_kills_background_children() {
+        let tmp = TempDir::new().expect("tmp");
+        let registry = ToolRegistry::new(tmp.path().to_path_buf(), true);
+        let tool = registry.get("exec").expect("tool");
+        let mut args = HashMap::new();
+        args.insert(
+            "command".to_string(),
+            Value::String("sleep 30 & echo $! > child.pid; wait".to_string()),
+        );
+        args.insert("confirm".to_string(), Value::Bool(true));
+        let run = tool.execute(args, "", "");
+        // Dropping the future is what cancelling a turn does.
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(500), run)
+                .await
+                .is_err()
+        );
+        let pid = std::fs::read_to_string(tmp.path().join("child.pid")).expect("pid file");
+        let stat_path = format!("/proc/{}/stat", pid.trim());
+        // SIGKILL is delivered asynchronously: wait until the child is gone,
+        // or a zombie waiting for init to reap it.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        let dead = loop {
+            let stat = std::fs::read_to_string(&stat_path);
+            if stat.map_or(true, |s| s.contains(") Z ")) {
+                break true;
+            }
+            if std::time::Instant::now() >= deadline {
+                break false;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        };
+        assert!(dead, "background child survived");
+    }
     #[tokio::test]
     async fn exec_deny_list_case_insensitive() {
         let tmp = TempDir::new().expect("tmp");
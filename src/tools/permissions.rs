@@ -0,0 +1,199 @@
+use super::exec::{normalise_command, starts_with_command};
+use crate::config::{ToolPermissionsConfig, ToolRole};
+use serde_json::Value;
+use std::collections::HashMap;
+/// Shell syntax that could chain a second command after an allowed prefix.
+const SHELL_OPERATORS: &[char] = &[';', '&', '|', '`', '$', '<', '>', '\n', '\r'];
+/// What one turn may use, from `tools.permissions`. A tool or command is
+/// allowed only when every role that applies allows it.
+#[derive(Debug, Clone, Default)]
+pub struct ToolPermissions {
+    roles: Vec<(String, ToolRole)>,
+}
+impl ToolPermissions {
+    /// Everything allowed, as without any policy.
+    #[cfg(test)]
+    pub fn unrestricted() -> Self {
+        Self::default()
+    }
+    /// Permissions of `sender` in `chat_id` on `channel`. Turns without a
+    /// sender (subagents, cron, heartbeat) get only what every role that can
+    /// apply in the chat allows, so they never exceed the person who started
+    /// them.
+    pub fn resolve(
+        config: &ToolPermissionsConfig,
+        channel: &str,
+        chat_id: &str,
+        sender: Option<&str>,
+    ) -> Self {
+        let mut names = Vec::new();
+        let mut caught_all = false;
+        for rule in &config.rules {
+            match sender {
+                Some(sender) if rule.matches(channel, chat_id, sender) => {
+                    names.push(rule.role.clone());
+                    caught_all = true;
+                    break;
+                }
+                None if rule.matches_chat(channel, chat_id) => {
+                    names.push(rule.role.clone());
+                    if rule.sender.is_none() {
+                        caught_all = true;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !caught_all {
+            names.push(config.default_role.clone());
+        }
+        names.dedup();
+        let roles = names
+            .into_iter()
+            .map(|name| {
+                let role = config.role(&name).unwrap_or_else(|| {
+                    tracing::warn!("unknown tool role '{}': no tools allowed", name);
+                    ToolRole {
+                        allow: Some(Vec::new()),
+                        ..ToolRole::default()
+                    }
+                });
+                (name, role)
+            })
+            .collect();
+        Self { roles }
+    }
+    /// Whether `tool` is offered to the model at all.
+    pub fn allows_tool(&self, tool: &str) -> bool {
+        self.denying_role(tool).is_none()
+    }
+    fn denying_role(&self, tool: &str) -> Option<&str> {
+        self.roles
+            .iter()
+            .find(|(_, role)| {
+                role.deny.iter().any(|t| t == tool)
+                    || role
+                        .allow
+                        .as_ref()
+                        .is_some_and(|allow| !allow.iter().any(|t| t == tool))
+            })
+            .map(|(name, _)| name.as_str())
+    }
+    /// Checks one call before it runs; the error is the tool result the
+    /// model gets instead.
+    pub fn check(&self, tool: &str, args: &HashMap<String, Value>) -> Result<(), String> {
+        if let Some(role) = self.denying_role(tool) {
+            return Err(format!(
+                "Tool '{}' is not allowed for role '{}' in this chat",
+                tool, role
+            ));
+        }
+        if tool != "exec" {
+            return Ok(());
+        }
+        // Operators are looked for before normalising, which turns line
+        // breaks into spaces.
+        let raw = args.get("command").and_then(Value::as_str).unwrap_or("");
+        let command = normalise_command(raw);
+        for (name, role) in &self.roles {
+            let prefixes = |list: &[String]| {
+                list.iter()
+                    .any(|prefix| starts_with_command(&command, &normalise_command(prefix)))
+            };
+            if prefixes(&role.exec_deny_prefixes) {
+                return Err(format!(
+                    "Command not allowed for role '{}' in this chat",
+                    name
+                ));
+            }
+            if let Some(allow) = &role.exec_allow_prefixes
+                && (raw.contains(SHELL_OPERATORS) || !prefixes(allow))
+            {
+                return Err(format!(
+                    "Role '{}' may only run single commands starting with: {}",
+                    name,
+                    allow.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ToolPermissionRule;
+    use serde_json::json;
+    fn rule(role: &str, chat_id: Option<&str>, sender: Option<&str>) -> ToolPermissionRule {
+        ToolPermissionRule {
+            role: role.to_string(),
+            channel: Some("telegram".to_string()),
+            chat_id: chat_id.map(str::to_string),
+            sender: sender.map(str::to_string),
+        }
+    }
+    #[test]
+    fn roles_follow_rules_and_senderless_turns_get_the_narrowest() {
+        let mut config = ToolPermissionsConfig {
+            default_role: "guest".to_string(),
+            rules: vec![
+                rule("owner", None, Some("42")),
+                rule("ops", Some("-100"), None),
+            ],
+            ..ToolPermissionsConfig::default()
+        };
+        config.roles.insert(
+            "ops".to_string(),
+            ToolRole {
+                deny: vec!["write_file".to_string()],
+                exec_allow_prefixes: Some(vec!["systemctl status".to_string()]),
+                ..ToolRole::default()
+            },
+        );
+        let exec = |command: &str| HashMap::from([("command".to_string(), json!(command))]);
+        let owner = ToolPermissions::resolve(&config, "telegram", "42", Some("42"));
+        assert!(owner.check("exec", &exec("rm -r build")).is_ok());
+        let guest = ToolPermissions::resolve(&config, "telegram", "7", Some("7"));
+        assert!(guest.allows_tool("web_search"));
+        assert_eq!(
+            guest.check("exec", &exec("ls")).unwrap_err(),
+            "Tool 'exec' is not allowed for role 'guest' in this chat"
+        );
+        let ops = ToolPermissions::resolve(&config, "telegram", "-100", Some("7"));
+        assert!(!ops.allows_tool("write_file"));
+        assert!(ops.check("exec", &exec("systemctl  status nginx")).is_ok());
+        assert!(ops.check("exec", &exec("systemctl restart nginx")).is_err());
+        assert!(
+            ops.check("exec", &exec("systemctl status nginx; reboot"))
+                .is_err()
+        );
+        assert!(
+            ops.check("exec", &exec("systemctl status nginx\nrm -r ~/data"))
+                .is_err()
+        );
+        assert!(
+            ops.check("exec", &exec("systemctl status nginx\r\nreboot"))
+                .is_err()
+        );
+        // The owner's subagent in the group gets the group's role, not theirs.
+        let subagent = ToolPermissions::resolve(&config, "telegram", "-100", None);
+        assert!(subagent.check("exec", &exec("rm -r build")).is_err());
+        assert!(subagent.allows_tool("read_file"));
+        // In the owner's own chat it can't exceed what a guest there could do.
+        let subagent = ToolPermissions::resolve(&config, "telegram", "42", None);
+        assert!(!subagent.allows_tool("exec"));
+        assert!(subagent.allows_tool("web_fetch"));
+        assert!(ToolPermissions::unrestricted().allows_tool("i2c"));
+        let typo = ToolPermissions::resolve(
+            &ToolPermissionsConfig {
+                default_role: "onwer".to_string(),
+                ..ToolPermissionsConfig::default()
+            },
+            "cli",
+            "direct",
+            Some("cli"),
+        );
+        assert!(!typo.allows_tool("read_file"));
+    }
+}
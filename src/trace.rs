@@ -0,0 +1,750 @@
+use crate::config::Config;
+use crate::providers::{Message, ToolCall, UsageInfo};
+use chrono::{DateTime, Local, Utc};
+use parking_lot::Mutex;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Instant;
+const REDACTED: &str = "[REDACTED]";
+/// Secret-looking strings masked even when they aren't in the config.
+const SECRET_PATTERNS: &[&str] = &[
+    r"\bsk-[A-Za-z0-9_-]{16,}",
+    r"\bgsk_[A-Za-z0-9]{16,}",
+    r"\bgh[pousr]_[A-Za-z0-9]{20,}",
+    r"\bxox[abpr]-[A-Za-z0-9-]{10,}",
+    r"\bAIza[A-Za-z0-9_-]{30,}",
+    r"\b\d{6,12}:[A-Za-z0-9_-]{30,}",
+    r"(?i)\bbearer\s+[A-Za-z0-9._~+/=-]{16,}",
+];
+/// Config keys whose string values are secrets.
+const SECRET_KEYS: &[&str] = &["api_key", "token", "secret", "password", "access_token"];
+/// Config values shorter than this are too likely to be false positives.
+const MIN_SECRET_LEN: usize = 8;
+/// One step of a turn, as written to the trace file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    TurnStart {
+        session_key: String,
+        channel: String,
+        chat_id: String,
+        sender_id: String,
+        provider: String,
+        model: String,
+        user_message: String,
+        system_prompt_hash: String,
+    },
+    /// Only the messages the previous request of the turn didn't have;
+    /// `earlier` counts the leading ones left out.
+    Request {
+        iteration: i32,
+        model: String,
+        earlier: usize,
+        messages: Vec<Message>,
+        tools: Vec<String>,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        options: HashMap<String, Value>,
+    },
+    Response {
+        iteration: i32,
+        duration_ms: u64,
+        content: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tool_calls: Vec<ToolCall>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        finish_reason: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        usage: Option<UsageInfo>,
+    },
+    ProviderError {
+        iteration: i32,
+        duration_ms: u64,
+        error: String,
+    },
+    ToolCall {
+        iteration: i32,
+        id: String,
+        name: String,
+        args: HashMap<String, Value>,
+    },
+    ToolResult {
+        iteration: i32,
+        id: String,
+        name: String,
+        duration_ms: u64,
+        result: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    TurnEnd {
+        /// `ok`, `limit`, `error` or `cancelled`.
+        outcome: String,
+        iterations: i32,
+        duration_ms: u64,
+        response: String,
+    },
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceLine {
+    pub ts: DateTime<Utc>,
+    pub turn_id: String,
+    #[serde(flatten)]
+    pub event: TraceEvent,
+}
+/// Masks secrets before anything reaches a trace file: the secret values
+/// found in the config, well-known key formats and `trace.redact` patterns.
+pub struct Redactor {
+    secrets: Vec<String>,
+    patterns: Vec<Regex>,
+}
+impl Redactor {
+    pub fn new(config: &Config) -> Self {
+        let mut secrets = Vec::new();
+        if let Ok(value) = serde_json::to_value(config) {
+            collect_secrets(&value, false, &mut secrets);
+        }
+        // Longest first, so a secret containing another is masked whole.
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        secrets.dedup();
+        let patterns = SECRET_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .chain(config.trace.redact.iter().cloned())
+            .filter_map(|p| match Regex::new(&p) {
+                Ok(re) => Some(re),
+                Err(err) => {
+                    tracing::warn!("invalid trace.redact pattern {:?}: {}", p, err);
+                    None
+                }
+            })
+            .collect();
+        Self { secrets, patterns }
+    }
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), REDACTED);
+            }
+        }
+        for pattern in &self.patterns {
+            if pattern.is_match(&text) {
+                text = pattern.replace_all(&text, REDACTED).into_owned();
+            }
+        }
+        text
+    }
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => *s = self.redact(s),
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact_value(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.redact_value(v)),
+            _ => {}
+        }
+    }
+}
+fn collect_secrets(value: &Value, secret: bool, out: &mut Vec<String>) {
+    match value {
+        Value::String(s) if secret && s.trim().len() >= MIN_SECRET_LEN => {
+            out.push(s.trim().to_string())
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_secrets(v, secret, out)),
+        Value::Object(map) => {
+            for (key, v) in map {
+                let key = key.to_lowercase();
+                let is_secret = secret || SECRET_KEYS.iter().any(|k| key.ends_with(k));
+                collect_secrets(v, is_secret, out);
+            }
+        }
+        _ => {}
+    }
+}
+/// Stable 64-bit FNV-1a hash, hex-encoded; identifies system prompts.
+pub fn prompt_hash(text: &str) -> String {
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+/// Per-turn JSONL traces in `<workspace>/traces/<YYYY-MM-DD>/<turn-id>.jsonl`.
+/// System prompts are stored once under `traces/prompts/<hash>.txt`.
+pub struct TraceLog {
+    dir: PathBuf,
+    enabled: bool,
+    redactor: std::sync::Arc<Redactor>,
+}
+impl TraceLog {
+    pub fn new(workspace: &Path, config: &Config) -> Self {
+        Self {
+            dir: traces_dir(workspace),
+            enabled: config.trace.enabled,
+            redactor: std::sync::Arc::new(Redactor::new(config)),
+        }
+    }
+    pub fn start_turn(&self) -> TurnTrace {
+        let now = Local::now();
+        let id = format!(
+            "{}-{}",
+            now.format("%Y%m%d-%H%M%S"),
+            &uuid::Uuid::new_v4().simple().to_string()[..6]
+        );
+        TurnTrace {
+            path: self.enabled.then(|| turn_path(&self.dir, &id)),
+            prompts: self.dir.join("prompts"),
+            id,
+            redactor: self.redactor.clone(),
+            started: Instant::now(),
+            sent: Mutex::new(None),
+            iterations: AtomicI32::new(0),
+        }
+    }
+}
+/// Trace of one running turn; every method is a no-op when tracing is off.
+pub struct TurnTrace {
+    pub id: String,
+    path: Option<PathBuf>,
+    prompts: PathBuf,
+    redactor: std::sync::Arc<Redactor>,
+    started: Instant,
+    /// Length and system prompt hash of the previous request, to log only
+    /// what each request adds.
+    sent: Mutex<Option<(usize, String)>>,
+    iterations: AtomicI32,
+}
+impl TurnTrace {
+    pub fn enabled(&self) -> bool {
+        self.path.is_some()
+    }
+    pub fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+    pub fn record(&self, event: TraceEvent) {
+        let Some(path) = self.path.as_ref() else {
+            return;
+        };
+        let line = TraceLine {
+            ts: Utc::now(),
+            turn_id: self.id.clone(),
+            event,
+        };
+        let Ok(mut value) = serde_json::to_value(&line) else {
+            return;
+        };
+        self.redactor.redact_value(&mut value);
+        if let Err(err) = append_line(path, &value) {
+            tracing::warn!("failed to write trace {}: {}", self.id, err);
+        }
+    }
+    /// Closes the turn; `outcome` is `ok`, `limit`, `error` or `cancelled`.
+    pub fn finish(&self, outcome: &str, response: &str) {
+        self.record(TraceEvent::TurnEnd {
+            outcome: outcome.to_string(),
+            iterations: self.iterations.load(Ordering::Relaxed),
+            duration_ms: self.elapsed_ms(),
+            response: response.to_string(),
+        });
+    }
+    /// Stores the system prompt (once per distinct prompt) and returns its hash.
+    pub fn system_prompt(&self, messages: &[Message]) -> String {
+        let prompt = messages
+            .first()
+            .filter(|m| m.role == "system")
+            .map_or("", |m| m.content.as_str());
+        let hash = prompt_hash(prompt);
+        if self.enabled() {
+            let path = self.prompts.join(format!("{}.txt", hash));
+            if !path.exists() {
+                let _ = std::fs::create_dir_all(&self.prompts);
+                if let Err(err) = std::fs::write(&path, self.redactor.redact(prompt)) {
+                    tracing::warn!("failed to store system prompt {}: {}", hash, err);
+                }
+            }
+        }
+        hash
+    }
+    /// Logs a provider request. The system prompt is replaced by its hash and
+    /// messages already logged for this turn are left out, unless the context
+    /// was trimmed in between.
+    pub fn request(
+        &self,
+        iteration: i32,
+        model: &str,
+        messages: &[Message],
+        tools: Vec<String>,
+        options: &HashMap<String, Value>,
+        trimmed: bool,
+    ) {
+        if !self.enabled() {
+            return;
+        }
+        self.iterations.store(iteration, Ordering::Relaxed);
+        let hash = self.system_prompt(messages);
+        let mut sent = self.sent.lock();
+        let from = match sent.as_ref() {
+            Some((len, prev)) if !trimmed && *prev == hash && *len <= messages.len() => *len,
+            _ => 0,
+        };
+        *sent = Some((messages.len(), hash.clone()));
+        drop(sent);
+        let messages = messages[from..]
+            .iter()
+            .map(|m| {
+                let mut m = m.clone();
+                if m.role == "system" {
+                    m.content = format!("[system prompt {}]", hash);
+                }
+                for image in &mut m.images {
+                    image.data = format!("<{} base64 chars>", image.data.len());
+                }
+                m
+            })
+            .collect();
+        self.record(TraceEvent::Request {
+            iteration,
+            model: model.to_string(),
+            earlier: from,
+            messages,
+            tools,
+            options: options.clone(),
+        });
+    }
+}
+fn append_line(path: &Path, value: &Value) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", value)
+}
+pub fn traces_dir(workspace: &Path) -> PathBuf {
+    workspace.join("traces")
+}
+/// Turn ids start with their local date (`YYYYMMDD-...`), which names the
+/// directory the trace is in.
+fn turn_path(dir: &Path, id: &str) -> PathBuf {
+    let day = match (id.get(0..4), id.get(4..6), id.get(6..8)) {
+        (Some(y), Some(m), Some(d)) => format!("{}-{}-{}", y, m, d),
+        _ => "unknown".to_string(),
+    };
+    dir.join(day).join(format!("{}.jsonl", id))
+}
+pub fn load_turn(workspace: &Path, id: &str) -> anyhow::Result<Vec<TraceLine>> {
+    let path = turn_path(&traces_dir(workspace), id);
+    let text = std::fs::read_to_string(&path)
+        .map_err(|err| anyhow::anyhow!("no trace for turn {} ({}): {}", id, path.display(), err))?;
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+/// Deletes the traces of turns started before `before`, then stored system
+/// prompts no remaining trace refers to. Returns how many turns were
+/// deleted.
+pub fn prune_traces(workspace: &Path, before: chrono::NaiveDate) -> anyhow::Result<usize> {
+    let dir = traces_dir(workspace);
+    let days = match std::fs::read_dir(&dir) {
+        Ok(days) => days,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let mut deleted = 0;
+    for day in days.filter_map(Result::ok) {
+        let is_old = day
+            .file_name()
+            .to_str()
+            .and_then(|name| chrono::NaiveDate::parse_from_str(name, "%Y-%m-%d").ok())
+            .is_some_and(|date| date < before);
+        if !is_old {
+            continue;
+        }
+        for turn in std::fs::read_dir(day.path())?.filter_map(Result::ok) {
+            std::fs::remove_file(turn.path())?;
+            deleted += 1;
+        }
+        std::fs::remove_dir(day.path())?;
+    }
+    prune_prompts(&dir)?;
+    Ok(deleted)
+}
+/// Deletes the traces of turns in any of the sessions `keys`, as `/forget`
+/// does. Returns how many turns were deleted.
+pub fn forget_sessions(workspace: &Path, keys: &[String]) -> anyhow::Result<usize> {
+    let dir = traces_dir(workspace);
+    let mut deleted = 0;
+    for path in turn_files(&dir) {
+        let in_keys = read_lines(&path).iter().any(|line| match &line.event {
+            TraceEvent::TurnStart { session_key, .. } => keys.contains(session_key),
+            _ => false,
+        });
+        if in_keys {
+            std::fs::remove_file(&path)?;
+            deleted += 1;
+            if let Some(day) = path.parent() {
+                // Fails, as intended, while the day still has traces.
+                let _ = std::fs::remove_dir(day);
+            }
+        }
+    }
+    if deleted > 0 {
+        prune_prompts(&dir)?;
+    }
+    Ok(deleted)
+}
+/// Deletes the stored system prompts that no trace under `dir` refers to.
+fn prune_prompts(dir: &Path) -> anyhow::Result<()> {
+    let prompts = match std::fs::read_dir(dir.join("prompts")) {
+        Ok(prompts) => prompts,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut used = std::collections::HashSet::new();
+    for path in turn_files(dir) {
+        for line in read_lines(&path) {
+            match line.event {
+                TraceEvent::TurnStart {
+                    system_prompt_hash, ..
+                } => {
+                    used.insert(system_prompt_hash);
+                }
+                TraceEvent::Request { messages, .. } => {
+                    used.extend(messages.iter().filter_map(|m| {
+                        m.content
+                            .strip_prefix("[system prompt ")
+                            .and_then(|rest| rest.strip_suffix(']'))
+                            .map(str::to_string)
+                    }))
+                }
+                _ => {}
+            }
+        }
+    }
+    for prompt in prompts.filter_map(Result::ok) {
+        let path = prompt.path();
+        let unused = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|hash| !used.contains(hash));
+        if unused {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+/// Trace files of every stored turn.
+fn turn_files(dir: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(dir)
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| {
+            e.file_name()
+                .to_str()
+                .is_some_and(|n| n.ends_with(".jsonl"))
+        })
+        .map(|e| e.into_path())
+        .collect()
+}
+fn read_lines(path: &Path) -> Vec<TraceLine> {
+    std::fs::read_to_string(path)
+        .map(|text| {
+            text.lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+/// Most recent turn ids first.
+pub fn recent_turns(workspace: &Path, limit: usize) -> Vec<String> {
+    let mut ids: Vec<String> = walkdir::WalkDir::new(traces_dir(workspace))
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| {
+            e.file_name()
+                .to_str()
+                .and_then(|n| n.strip_suffix(".jsonl"))
+                .map(str::to_string)
+        })
+        .collect();
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+    ids.truncate(limit);
+    ids
+}
+/// One-line description of a turn for `asterclaw trace list`.
+pub fn summarize_turn(lines: &[TraceLine]) -> String {
+    let mut session = String::new();
+    let mut message = String::new();
+    let mut end = "unfinished".to_string();
+    for line in lines {
+        match &line.event {
+            TraceEvent::TurnStart {
+                session_key,
+                user_message,
+                ..
+            } => {
+                session = session_key.clone();
+                message = clip(user_message, 60);
+            }
+            TraceEvent::TurnEnd {
+                outcome,
+                iterations,
+                duration_ms,
+                ..
+            } => {
+                end = format!(
+                    "{}, {} iter, {}",
+                    outcome,
+                    iterations,
+                    seconds(*duration_ms)
+                );
+            }
+            _ => {}
+        }
+    }
+    format!("{}  [{}]  {}", session, end, message)
+}
+/// Human-readable rendering of a turn for `asterclaw trace show`. Long
+/// texts are clipped to `max_chars` (0 keeps everything).
+pub fn render_turn(lines: &[TraceLine], max_chars: usize) -> String {
+    let clip = |text: &str| {
+        if max_chars == 0 {
+            text.to_string()
+        } else {
+            clip(text, max_chars)
+        }
+    };
+    let mut out = String::new();
+    for line in lines {
+        let ts = line.ts.with_timezone(&Local).format("%H:%M:%S%.3f");
+        let entry = match &line.event {
+            TraceEvent::TurnStart {
+                session_key,
+                channel,
+                chat_id,
+                sender_id,
+                provider,
+                model,
+                user_message,
+                system_prompt_hash,
+            } => format!(
+                "Turn {} · session {} · {}:{} · sender {}\n  model {}/{} · system prompt {}\n  user: {}",
+                line.turn_id,
+                session_key,
+                channel,
+                chat_id,
+                if sender_id.is_empty() { "-" } else { sender_id },
+                provider,
+                model,
+                system_prompt_hash,
+                clip(user_message)
+            ),
+            TraceEvent::Request {
+                iteration,
+                model,
+                earlier,
+                messages,
+                tools,
+                options,
+            } => {
+                let mut entry = format!(
+                    "→ request #{} to {}: {} messages ({} new), {} tools",
+                    iteration,
+                    model,
+                    earlier + messages.len(),
+                    messages.len(),
+                    tools.len()
+                );
+                if !options.is_empty() {
+                    let mut opts: Vec<String> = options
+                        .iter()
+                        .map(|(k, v)| format!("{}={}", k, v))
+                        .collect();
+                    opts.sort();
+                    entry.push_str(&format!(" [{}]", opts.join(", ")));
+                }
+                for m in messages {
+                    entry.push_str(&format!("\n    {}: {}", m.role, clip(&m.content)));
+                }
+                entry
+            }
+            TraceEvent::Response {
+                iteration,
+                duration_ms,
+                content,
+                tool_calls,
+                finish_reason,
+                usage,
+            } => {
+                let mut entry = format!("← response #{} in {}", iteration, seconds(*duration_ms));
+                if let Some(usage) = usage {
+                    entry.push_str(&format!(
+                        ", {}+{} tokens",
+                        usage.prompt_tokens, usage.completion_tokens
+                    ));
+                }
+                if let Some(reason) = finish_reason {
+                    entry.push_str(&format!(", finish {}", reason));
+                }
+                if !content.is_empty() {
+                    entry.push_str(&format!("\n    {}", clip(content)));
+                }
+                for tc in tool_calls {
+                    entry.push_str(&format!(
+                        "\n    calls {} ({})",
+                        tc.name.as_deref().unwrap_or("?"),
+                        tc.id
+                    ));
+                }
+                entry
+            }
+            TraceEvent::ProviderError {
+                iteration,
+                duration_ms,
+                error,
+            } => format!(
+                "✗ request #{} failed after {}: {}",
+                iteration,
+                seconds(*duration_ms),
+                error
+            ),
+            TraceEvent::ToolCall { id, name, args, .. } => format!(
+                "⚙ {} ({}) {}",
+                name,
+                id,
+                clip(&serde_json::to_string(args).unwrap_or_default())
+            ),
+            TraceEvent::ToolResult {
+                id,
+                name,
+                duration_ms,
+                result,
+                error,
+                ..
+            } => match error {
+                Some(err) => format!(
+                    "✗ {} ({}) failed in {}: {}",
+                    name,
+                    id,
+                    seconds(*duration_ms),
+                    clip(err)
+                ),
+                None => format!(
+                    "✓ {} ({}) in {}: {}",
+                    name,
+                    id,
+                    seconds(*duration_ms),
+                    clip(result)
+                ),
+            },
+            TraceEvent::TurnEnd {
+                outcome,
+                iterations,
+                duration_ms,
+                response,
+            } => format!(
+                "Turn ended: {} after {} iteration(s) in {}\n  reply: {}",
+                outcome,
+                iterations,
+                seconds(*duration_ms),
+                clip(response)
+            ),
+        };
+        out.push_str(&format!("{}  {}\n", ts, entry));
+    }
+    out
+}
+fn clip(text: &str, max_chars: usize) -> String {
+    let text = text.replace('\n', "\n      ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+    let kept: String = text.chars().take(max_chars).collect();
+    format!("{}…", kept)
+}
+fn seconds(ms: u64) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn redacts_config_secrets_and_known_key_formats() {
+        let mut config = Config::default();
+        config.providers.openrouter.api_key = Some("or-live-0123456789abcdef".to_string());
+        config.channels.telegram.token = "short".to_string();
+        config.trace.redact = vec![r"ticket-\d+".to_string()];
+        let redactor = Redactor::new(&config);
+        assert_eq!(
+            redactor.redact("key or-live-0123456789abcdef, Bearer abcdefghijklmnopqrstuvwxyz"),
+            "key [REDACTED], [REDACTED]"
+        );
+        assert_eq!(
+            redactor.redact("token sk-proj-AAAABBBBCCCCDDDDEEEE and ticket-42"),
+            "token [REDACTED] and [REDACTED]"
+        );
+        assert_eq!(redactor.redact("short stays"), "short stays");
+    }
+    #[test]
+    fn turn_trace_logs_only_new_messages_and_renders() {
+        let tmp = tempfile::TempDir::new().expect("tmp");
+        let mut config = Config::default();
+        config.providers.openrouter.api_key = Some("or-live-0123456789abcdef".to_string());
+        let log = TraceLog::new(tmp.path(), &config);
+        let turn = log.start_turn();
+        let mut messages = vec![
+            Message::system("You are helpful"),
+            Message::user("my key is or-live-0123456789abcdef"),
+        ];
+        let hash = turn.system_prompt(&messages);
+        turn.record(TraceEvent::TurnStart {
+            session_key: "cli:default".to_string(),
+            channel: "cli".to_string(),
+            chat_id: "direct".to_string(),
+            sender_id: "cli".to_string(),
+            provider: "openrouter".to_string(),
+            model: "m".to_string(),
+            user_message: messages[1].content.clone(),
+            system_prompt_hash: hash.clone(),
+        });
+        turn.request(1, "m", &messages, vec![], &HashMap::new(), false);
+        messages.push(Message::tool("done", "call_1"));
+        turn.request(2, "m", &messages, vec![], &HashMap::new(), false);
+        turn.record(TraceEvent::TurnEnd {
+            outcome: "ok".to_string(),
+            iterations: 2,
+            duration_ms: 1500,
+            response: "ok".to_string(),
+        });
+        let lines = load_turn(tmp.path(), &turn.id).expect("trace");
+        let TraceEvent::Request {
+            earlier, messages, ..
+        } = &lines[2].event
+        else {
+            panic!("expected the second request");
+        };
+        assert_eq!((*earlier, messages.len()), (2, 1));
+        let stored = std::fs::read_to_string(
+            traces_dir(tmp.path())
+                .join("prompts")
+                .join(format!("{}.txt", hash)),
+        )
+        .expect("prompt");
+        assert_eq!(stored, "You are helpful");
+        let rendered = render_turn(&lines, 200);
+        assert!(rendered.contains(&format!("system prompt {}", hash)));
+        assert!(rendered.contains("my key is [REDACTED]"));
+        assert!(rendered.contains("Turn ended: ok after 2 iteration(s) in 1.5s"));
+        assert_eq!(recent_turns(tmp.path(), 10), vec![turn.id.clone()]);
+        assert!(summarize_turn(&lines).starts_with("cli:default  [ok, 2 iter, 1.5s]"));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::providers::{Message, ToolCall};
+use crate::session::Transcript;
+/// `name(arguments)` of a tool call, whichever of the two shapes it uses.
+fn call_text(call: &ToolCall) -> String {
+    let name = call
+        .name
+        .as_deref()
+        .or(call.function.as_ref().map(|f| f.name.as_str()))
+        .unwrap_or("?");
+    let args = match (&call.arguments, &call.function) {
+        (Some(args), _) => serde_json::to_string(args).unwrap_or_default(),
+        (None, Some(function)) => function.arguments.clone(),
+        (None, None) => String::new(),
+    };
+    format!("{}({})", name, args)
+}
+fn role_title(message: &Message) -> String {
+    match message.role.as_str() {
+        "user" => "User".to_string(),
+        "assistant" => "Assistant".to_string(),
+        "tool" => "Tool result".to_string(),
+        "system" => "System".to_string(),
+        other => other.to_string(),
+    }
+}
+/// A backtick fence of at least `shortest` that is longer than any
+/// backtick run in `text`.
+fn fence(text: &str, shortest: usize) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(shortest.max(longest + 1))
+}
+/// Markdown transcript for `asterclaw sessions show` and `export --format md`.
+pub fn render_markdown(transcript: &Transcript) -> String {
+    let mut out = format!("# Session {}\n", transcript.key);
+    if let Some(model) = &transcript.model {
+        out.push_str(&format!("\nModel: {}\n", model.model));
+    }
+    if !transcript.summary.is_empty() {
+        out.push_str(&format!(
+            "\n> **Summary of earlier messages:** {}\n",
+            transcript.summary.replace('\n', "\n> ")
+        ));
+    }
+    for message in &transcript.messages {
+        out.push_str(&format!("\n### {}\n\n", role_title(message)));
+        if message.role == "tool" {
+            let fence = fence(&message.content, 3);
+            out.push_str(&format!("{fence}\n{}\n{fence}\n", message.content));
+        } else if !message.content.is_empty() {
+            out.push_str(&message.content);
+            out.push('\n');
+        }
+        for call in &message.tool_calls {
+            let text = call_text(call);
+            let fence = fence(&text, 1);
+            out.push_str(&format!("\n→ {fence}{text}{fence}\n"));
+        }
+    }
+    out
+}
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+/// Self-contained HTML page for `export --format html`.
+pub fn render_html(transcript: &Transcript) -> String {
+    let title = escape_html(&transcript.key);
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Session {title}</title>\n<style>\n\
+body {{ font-family: sans-serif; max-width: 50em; margin: 2em auto; }}\n\
+.message {{ border-left: 4px solid #ccc; margin: 1em 0; padding: 0 1em; }}\n\
+.user {{ border-color: #3b82f6; }} .assistant {{ border-color: #10b981; }} .tool {{ border-color: #9ca3af; }}\n\
+pre {{ white-space: pre-wrap; word-wrap: break-word; }}\n\
+</style>\n</head>\n<body>\n<h1>Session {title}</h1>\n"
+    );
+    if !transcript.summary.is_empty() {
+        out.push_str(&format!(
+            "<blockquote><strong>Summary of earlier messages:</strong> {}</blockquote>\n",
+            escape_html(&transcript.summary)
+        ));
+    }
+    for message in &transcript.messages {
+        out.push_str(&format!(
+            "<div class=\"message {}\">\n<h3>{}</h3>\n",
+            escape_html(&message.role),
+            role_title(message)
+        ));
+        if !message.content.is_empty() {
+            out.push_str(&format!("<pre>{}</pre>\n", escape_html(&message.content)));
+        }
+        for call in &message.tool_calls {
+            out.push_str(&format!(
+                "<p>→ <code>{}</code></p>\n",
+                escape_html(&call_text(call))
+            ));
+        }
+        out.push_str("</div>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn renders_tool_calls_and_escapes_output() {
+        let call = ToolCall {
+            id: "c1".to_string(),
+            tool_type: "function".to_string(),
+            function: None,
+            name: Some("exec".to_string()),
+            arguments: Some(
+                [("command".to_string(), serde_json::json!("ls"))]
+                    .into_iter()
+                    .collect(),
+            ),
+        };
+        let assistant = |content: &str, tool_calls: Vec<ToolCall>| Message {
+            role: "assistant".to_string(),
+            content: content.to_string(),
+            tool_calls,
+            tool_call_id: None,
+            images: vec![],
+        };
+        let transcript = Transcript {
+            key: "telegram:1".to_string(),
+            summary: String::new(),
+            model: None,
+            messages: vec![
+                Message::user("list files"),
+                assistant("", vec![call]),
+                Message::tool("```\n<b>a.txt</b>", "c1"),
+                assistant("One file.", vec![]),
+            ],
+        };
+        let md = render_markdown(&transcript);
+        assert!(md.starts_with("# Session telegram:1\n"));
+        assert!(md.contains("→ `exec({\"command\":\"ls\"})`"));
+        assert!(md.contains("````\n```\n<b>a.txt</b>\n````"));
+        let html = render_html(&transcript);
+        assert!(html.contains("&lt;b&gt;a.txt&lt;/b&gt;"));
+        assert!(html.contains("<code>exec({&quot;command&quot;:&quot;ls&quot;})</code>"));
+    }
+}
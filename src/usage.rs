@@ -0,0 +1,426 @@
+use crate::config::{BudgetLimits, BudgetsConfig, ModelPrice, UsageConfig};
+use crate::providers::UsageInfo;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub ts: DateTime<Utc>,
+    pub session_key: String,
+    pub channel: String,
+    pub provider: String,
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sender_id: String,
+    /// Heartbeat and cron turns, billed against the background budget.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub background: bool,
+}
+/// Who a provider call should be billed to.
+pub struct UsageContext<'a> {
+    pub session_key: &'a str,
+    pub channel: &'a str,
+    pub provider: &'a str,
+    pub model: &'a str,
+    /// Empty when the call can't be attributed to a sender (e.g. subagents).
+    pub sender_id: &'a str,
+    pub background: bool,
+}
+/// Append-only JSONL ledger of provider calls, kept in `<workspace>/usage/ledger.jsonl`.
+pub struct UsageLedger {
+    path: PathBuf,
+    prices: HashMap<String, ModelPrice>,
+    budgets: BudgetsConfig,
+    /// Running totals per budget scope and period, seeded from the ledger on
+    /// the first budget check.
+    spend: Mutex<Option<HashMap<String, UsageTotals>>>,
+    write_lock: Mutex<()>,
+}
+#[derive(Clone, Copy)]
+enum Period {
+    Day,
+    Month,
+}
+impl Period {
+    fn key(self, ts: &DateTime<Utc>) -> String {
+        match self {
+            Period::Day => ts.format("%Y-%m-%d").to_string(),
+            Period::Month => ts.format("%Y-%m").to_string(),
+        }
+    }
+}
+/// Budget scopes a call counts against: background turns only touch the
+/// background budget, everything else the global and per-sender ones.
+fn scopes(sender_id: &str, background: bool) -> Vec<String> {
+    if background {
+        return vec!["background".to_string()];
+    }
+    let mut scopes = vec!["global".to_string()];
+    if !sender_id.is_empty() {
+        scopes.push(format!("sender:{}", sender_id));
+    }
+    scopes
+}
+fn spend_key(scope: &str, period: Period, ts: &DateTime<Utc>) -> String {
+    format!("{}@{}", scope, period.key(ts))
+}
+impl UsageLedger {
+    pub fn new(workspace: &Path, config: &UsageConfig) -> Self {
+        Self {
+            path: workspace.join("usage").join("ledger.jsonl"),
+            prices: config.prices.clone(),
+            budgets: config.budgets.clone(),
+            spend: Mutex::new(None),
+            write_lock: Mutex::new(()),
+        }
+    }
+    /// Returns a user-facing explanation when a budget that applies to `ctx`
+    /// is used up, in which case the provider must not be called.
+    pub fn budget_exhausted(&self, ctx: &UsageContext<'_>) -> Option<String> {
+        let limits = |scope: &str| -> (&BudgetLimits, &'static str) {
+            match scope {
+                "background" => (&self.budgets.background, "background"),
+                "global" => (&self.budgets.global, "global"),
+                _ => (&self.budgets.per_sender, "your"),
+            }
+        };
+        let now = Utc::now();
+        let mut spend = self.spend.lock();
+        let spend = spend.get_or_insert_with(|| self.load_spend(&now));
+        for scope in scopes(ctx.sender_id, ctx.background) {
+            let (limits, who) = limits(&scope);
+            for (period, tokens, cost) in [
+                (Period::Day, limits.daily_tokens, limits.daily_cost_usd),
+                (
+                    Period::Month,
+                    limits.monthly_tokens,
+                    limits.monthly_cost_usd,
+                ),
+            ] {
+                if tokens.is_none() && cost.is_none() {
+                    continue;
+                }
+                let used = spend
+                    .get(&spend_key(&scope, period, &now))
+                    .cloned()
+                    .unwrap_or_default();
+                let (name, resets) = match period {
+                    Period::Day => ("daily", "tomorrow"),
+                    Period::Month => ("monthly", "next month"),
+                };
+                if let Some(max) = tokens
+                    && used.total_tokens >= max
+                {
+                    return Some(format!(
+                        "⛔ The {} {} token budget is used up ({} of {} tokens). It resets {} (UTC).",
+                        who, name, used.total_tokens, max, resets
+                    ));
+                }
+                if let Some(max) = cost
+                    && used.cost_usd >= max
+                {
+                    return Some(format!(
+                        "⛔ The {} {} spending budget is used up (${:.4} of ${:.2}). It resets {} (UTC).",
+                        who, name, used.cost_usd, max, resets
+                    ));
+                }
+            }
+        }
+        None
+    }
+    fn load_spend(&self, now: &DateTime<Utc>) -> HashMap<String, UsageTotals> {
+        let month = Period::Month.key(now);
+        let mut spend = HashMap::new();
+        for record in self.load() {
+            if Period::Month.key(&record.ts) == month {
+                add_spend(&mut spend, &record);
+            }
+        }
+        spend
+    }
+    pub fn record(&self, ctx: &UsageContext<'_>, usage: &UsageInfo) {
+        let provider = usage.provider.as_deref().unwrap_or(ctx.provider);
+        let model = usage.model.as_deref().unwrap_or(ctx.model);
+        let prompt_tokens = usage.prompt_tokens.max(0) as u64;
+        let completion_tokens = usage.completion_tokens.max(0) as u64;
+        let total_tokens = if usage.total_tokens > 0 {
+            usage.total_tokens as u64
+        } else {
+            prompt_tokens + completion_tokens
+        };
+        let record = UsageRecord {
+            ts: Utc::now(),
+            session_key: ctx.session_key.to_string(),
+            channel: ctx.channel.to_string(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            prompt_tokens,
+            completion_tokens,
+            total_tokens,
+            cost_usd: self
+                .price_for(model)
+                .map(|p| p.cost(prompt_tokens, completion_tokens)),
+            sender_id: ctx.sender_id.to_string(),
+            background: ctx.background,
+        };
+        if let Some(spend) = self.spend.lock().as_mut() {
+            add_spend(spend, &record);
+        }
+        if let Err(err) = self.append(&record) {
+            tracing::warn!("failed to record usage: {}", err);
+        }
+    }
+    fn append(&self, record: &UsageRecord) -> anyhow::Result<()> {
+        let line = serde_json::to_string(record)?;
+        let _guard = self.write_lock.lock();
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+    /// Exact model name first, then the part after a `provider/` prefix.
+    fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices.get(model).or_else(|| {
+            model
+                .rsplit_once('/')
+                .and_then(|(_, bare)| self.prices.get(bare))
+        })
+    }
+    pub fn load(&self) -> Vec<UsageRecord> {
+        let Ok(raw) = std::fs::read_to_string(&self.path) else {
+            return Vec::new();
+        };
+        raw.lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsageTotals {
+    pub calls: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost_usd: f64,
+}
+impl UsageTotals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.calls += 1;
+        self.prompt_tokens += record.prompt_tokens;
+        self.completion_tokens += record.completion_tokens;
+        self.total_tokens += record.total_tokens;
+        self.cost_usd += record.cost_usd.unwrap_or(0.0);
+    }
+}
+fn add_spend(spend: &mut HashMap<String, UsageTotals>, record: &UsageRecord) {
+    for scope in scopes(&record.sender_id, record.background) {
+        for period in [Period::Day, Period::Month] {
+            spend
+                .entry(spend_key(&scope, period, &record.ts))
+                .or_default()
+                .add(record);
+        }
+    }
+}
+pub fn totals_by<'a, F>(
+    records: impl IntoIterator<Item = &'a UsageRecord>,
+    key: F,
+) -> BTreeMap<String, UsageTotals>
+where
+    F: Fn(&UsageRecord) -> String,
+{
+    let mut out: BTreeMap<String, UsageTotals> = BTreeMap::new();
+    for record in records {
+        out.entry(key(record)).or_default().add(record);
+    }
+    out
+}
+pub fn day_key(record: &UsageRecord) -> String {
+    record.ts.format("%Y-%m-%d").to_string()
+}
+fn format_line(label: &str, t: &UsageTotals) -> String {
+    let mut line = format!(
+        "  {}: {} tokens ({} in / {} out), {} calls",
+        label, t.total_tokens, t.prompt_tokens, t.completion_tokens, t.calls
+    );
+    if t.cost_usd > 0.0 {
+        line.push_str(&format!(", ${:.4}", t.cost_usd));
+    }
+    line
+}
+pub fn format_section(title: &str, totals: &BTreeMap<String, UsageTotals>) -> String {
+    let mut out = format!("{}:", title);
+    if totals.is_empty() {
+        out.push_str("\n  (none)");
+    }
+    for (label, t) in totals {
+        out.push('\n');
+        out.push_str(&format_line(label, t));
+    }
+    out
+}
+/// Compact report for the `/usage` chat command: today, the current session
+/// and today's models.
+pub fn chat_report(records: &[UsageRecord], session_key: &str) -> String {
+    let today = Utc::now().format("%Y-%m-%d").to_string();
+    let today_records: Vec<&UsageRecord> = records.iter().filter(|r| day_key(r) == today).collect();
+    let today_total = totals_by(today_records.iter().copied(), |_| "today".to_string());
+    let session_total = totals_by(
+        records.iter().filter(|r| r.session_key == session_key),
+        |_| "this session".to_string(),
+    );
+    let mut out = String::from("Usage");
+    for (label, t) in today_total.iter().chain(session_total.iter()) {
+        out.push('\n');
+        out.push_str(&format_line(label, t));
+    }
+    if today_total.is_empty() && session_total.is_empty() {
+        out.push_str("\n  no provider calls recorded yet");
+        return out;
+    }
+    let models = totals_by(today_records.iter().copied(), |r| {
+        format!("{}/{}", r.provider, r.model)
+    });
+    out.push_str("\n\n");
+    out.push_str(&format_section("Models today", &models));
+    out
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    fn usage(prompt: i32, completion: i32) -> UsageInfo {
+        UsageInfo {
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            total_tokens: prompt + completion,
+            provider: None,
+            model: None,
+        }
+    }
+    #[test]
+    fn records_are_priced_and_aggregated() {
+        let tmp = TempDir::new().expect("tempdir");
+        let prices = HashMap::from([(
+            "gpt-4o".to_string(),
+            ModelPrice {
+                input_per_million: 2.5,
+                output_per_million: 10.0,
+            },
+        )]);
+        let ledger = UsageLedger::new(
+            tmp.path(),
+            &UsageConfig {
+                prices,
+                ..Default::default()
+            },
+        );
+        let ctx = |session: &'static str, model: &'static str| UsageContext {
+            session_key: session,
+            channel: "telegram",
+            provider: "openrouter",
+            model,
+            sender_id: "42",
+            background: false,
+        };
+        ledger.record(
+            &ctx("telegram:1", "openai/gpt-4o"),
+            &usage(1_000_000, 100_000),
+        );
+        ledger.record(&ctx("telegram:2", "glm-4.7"), &usage(10, 5));
+        let records = ledger.load();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].cost_usd, Some(3.5));
+        assert_eq!(records[1].cost_usd, None);
+        let by_session = totals_by(&records, |r| r.session_key.clone());
+        assert_eq!(by_session["telegram:2"].total_tokens, 15);
+        let report = chat_report(&records, "telegram:1");
+        assert!(report.contains("today: 1100015 tokens"));
+        assert!(report.contains("this session: 1100000 tokens"));
+        assert!(report.contains("openrouter/glm-4.7"));
+    }
+    #[test]
+    fn fallback_backend_overrides_context_provider() {
+        let tmp = TempDir::new().expect("tempdir");
+        let ledger = UsageLedger::new(tmp.path(), &UsageConfig::default());
+        let mut info = usage(1, 1);
+        info.provider = Some("groq".to_string());
+        info.model = Some("llama-3.1-8b".to_string());
+        ledger.record(
+            &UsageContext {
+                session_key: "cli:default",
+                channel: "cli",
+                provider: "openrouter",
+                model: "openai/gpt-4o",
+                sender_id: "cli",
+                background: false,
+            },
+            &info,
+        );
+        let record = &ledger.load()[0];
+        assert_eq!(record.provider, "groq");
+        assert_eq!(record.model, "llama-3.1-8b");
+    }
+    #[test]
+    fn budgets_block_per_scope_and_keep_background_separate() {
+        let tmp = TempDir::new().expect("tempdir");
+        let config = UsageConfig {
+            budgets: BudgetsConfig {
+                global: BudgetLimits {
+                    monthly_tokens: Some(1_000),
+                    ..Default::default()
+                },
+                per_sender: BudgetLimits {
+                    daily_tokens: Some(100),
+                    ..Default::default()
+                },
+                background: BudgetLimits {
+                    daily_tokens: Some(50),
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        };
+        let ledger = UsageLedger::new(tmp.path(), &config);
+        let ctx = |sender_id: &'static str, background: bool| UsageContext {
+            session_key: "telegram:1",
+            channel: "telegram",
+            provider: "openrouter",
+            model: "glm-4.7",
+            sender_id,
+            background,
+        };
+        assert!(ledger.budget_exhausted(&ctx("alice", false)).is_none());
+        ledger.record(&ctx("alice", false), &usage(80, 20));
+        let msg = ledger
+            .budget_exhausted(&ctx("alice", false))
+            .expect("alice is over her daily cap");
+        assert!(msg.contains("your daily token budget"), "{msg}");
+        assert!(ledger.budget_exhausted(&ctx("bob", false)).is_none());
+        ledger.record(&ctx("heartbeat", true), &usage(60, 0));
+        assert!(ledger.budget_exhausted(&ctx("system:cron", true)).is_some());
+        assert!(ledger.budget_exhausted(&ctx("bob", false)).is_none());
+        // A fresh ledger rebuilds the totals from disk.
+        let reloaded = UsageLedger::new(tmp.path(), &config);
+        assert!(reloaded.budget_exhausted(&ctx("alice", false)).is_some());
+        ledger.record(&ctx("bob", false), &usage(900, 0));
+        let msg = ledger
+            .budget_exhausted(&ctx("carol", false))
+            .expect("global monthly cap reached");
+        assert!(msg.contains("global monthly token budget"), "{msg}");
+    }
+}
@@ -15,11 +15,11 @@ pub struct TranscriptionResponse {
     pub duration: Option<f64>,
 }
 impl GroqTranscriber {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, client: reqwest::Client) -> Self {
         Self {
             api_key,
             api_base: "https://api.groq.com/openai/v1".to_string(),
-            client: reqwest::Client::new(),
+            client,
         }
     }
     pub fn is_available(&self) -> bool {
@@ -277,6 +277,12 @@ async fn e2e_agent_with_mock_provider() -> anyhow::Result<()> {
         String::from_utf8_lossy(&out.stderr)
     );
     assert!(String::from_utf8_lossy(&out.stdout).contains("mock-e2e-response"));
+
+    let usage = run_asterclaw(&home, &["usage"])?;
+    assert!(usage.status.success());
+    let report = String::from_utf8_lossy(&usage.stdout);
+    assert!(report.contains("cli:e2e: 2 tokens"), "{report}");
+    assert!(report.contains("openai/gpt-4o-mini"), "{report}");
     Ok(())
 }
 